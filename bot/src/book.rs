@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use model::*;
+use log::*;
+use crate::eval::*;
+use crate::minimax::*;
+use crate::tt::TranspositionTable;
+use crate::tune::self_play_game;
+use crate::engine::EnginePlayer;
+
+/// Opening book: weighted moves for positions, identified by their Zobrist key.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Book {
+    entries :HashMap<u64, Vec<(Move,u32)>>,
+}
+
+impl Book {
+    /// Number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Only used by tests, but clippy expects `is_empty` next to `len`.
+    #[allow(dead_code)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Add weight to a move in the position.
+    pub fn add(&mut self, board :&Board, mv :Move, weight :u32) {
+        let moves = self.entries.entry(board.zobrist_key()).or_insert_with(Vec::new);
+        match moves.iter_mut().find(|(m,_)| *m == mv) {
+            Some((_,w)) => *w += weight,
+            None => moves.push((mv, weight)),
+        }
+    }
+
+    /// Book moves and their weights for the position.
+    pub fn moves(&self, board :&Board) -> &[(Move,u32)] {
+        self.entries.get(&board.zobrist_key()).map(|m| m.as_slice()).unwrap_or(&[])
+    }
+
+    /// Choose a legal book move at random, with probability proportional to its weight.
+    pub fn choose(&self, board :&Board, rng :&mut impl Rng) -> Option<Move> {
+        let moves = self.moves(board).iter()
+            .filter(|(mv,w)| *w > 0 && board.is_valid_move(mv))
+            .collect::<Vec<_>>();
+        let total :u32 = moves.iter().map(|(_,w)| *w).sum();
+        if total == 0 { return None; }
+        let mut r = rng.gen_range(0, total);
+        for (mv,w) in moves {
+            if r < *w { return Some(*mv); }
+            r -= *w;
+        }
+        unreachable!()
+    }
+
+    /// Read a book from a file with a line for each position: the position
+    /// key in hexadecimal, followed by pairs of move and weight.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn load(path :&str) -> Result<Book,String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read book file {:?}: {}", path, e))?;
+        text.parse()
+    }
+
+    pub fn save(&self, path :&str) -> Result<(),String> {
+        std::fs::write(path, self.to_string())
+            .map_err(|e| format!("Could not write book file {:?}: {}", path, e))
+    }
+}
+
+impl std::fmt::Display for Book {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut keys = self.entries.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let mut moves = self.entries[key].clone();
+            moves.sort_by_key(|(_,w)| std::cmp::Reverse(*w));
+            write!(f, "{:016x}", key)?;
+            for (mv,w) in moves {
+                write!(f, " {} {}", printer(&mv), w)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Book {
+    type Err = String;
+    fn from_str(text :&str) -> Result<Book,String> {
+        let mut book = Book::default();
+        for (i,line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let mut parts = line.split_whitespace();
+            let key = parts.next().unwrap();
+            let key = u64::from_str_radix(key, 16)
+                .map_err(|e| format!("Line {}: could not parse position key {:?}: {}", i+1, key, e))?;
+            let parts = parts.collect::<Vec<_>>();
+            if parts.is_empty() || parts.len() % 2 != 0 {
+                return Err(format!("Line {}: expected pairs of move and weight", i+1));
+            }
+            let moves = book.entries.entry(key).or_insert_with(Vec::new);
+            for pair in parts.chunks(2) {
+                let mv = parse(pair[0])
+                    .map_err(|e| format!("Line {}: could not parse move {:?}: {}", i+1, pair[0], e))?;
+                let weight = pair[1].parse()
+                    .map_err(|e| format!("Line {}: could not parse weight {:?}: {}", i+1, pair[1], e))?;
+                moves.push((mv, weight));
+            }
+        }
+        Ok(book)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BookConfig {
+    /// Number of self-play games.
+    pub games :usize,
+    /// Number of moves from the start of each game that are added to the book.
+    pub plies :usize,
+    /// Games that are not finished after this many moves are not used.
+    pub max_plies :usize,
+    /// Probability of making a random move in the self-play games.
+    pub random_move_prob :f64,
+    /// If set, every book position is searched to this depth,
+    /// and the best move is added with `search_weight`.
+    pub search_depth :Option<u16>,
+    pub search_weight :u32,
+    pub seed :u64,
+}
+
+impl Default for BookConfig {
+    fn default() -> BookConfig {
+        BookConfig {
+            games: 100, plies: 8, max_plies: 200, random_move_prob: 0.2,
+            search_depth: None, search_weight: 10, seed: 0,
+        }
+    }
+}
+
+/// Build a book from self-play games, where each move made by the winner
+/// of a game within the first `plies` moves gets weight 1. The positions can
+/// then be searched to add the best moves found by the search.
+pub fn build_book(evaluator :&dyn Evaluator, config :&BookConfig) -> Book {
+    let mut book = Book::default();
+    let mut positions = HashMap::new();
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    for game in 0..config.games {
+        let (boards, moves, winner) = self_play_game(evaluator, &mut rng,
+                                                     config.random_move_prob, config.max_plies);
+        info!("Book game {}: {} moves, winner {:?}", game + 1, moves.len(), winner);
+        let winner = match winner {
+            Some(w) => w,
+            None => continue,
+        };
+        for (board,mv) in boards.iter().zip(moves.iter()).take(config.plies) {
+            if board.player() == winner {
+                book.add(board, *mv, 1);
+                positions.insert(board.zobrist_key(), *board);
+            }
+        }
+    }
+
+    if let Some(depth) = config.search_depth {
+        let mut table = TranspositionTable::new(DEFAULT_TABLE_SIZE);
+        let mut positions = positions.into_iter().collect::<Vec<_>>();
+        positions.sort_by_key(|(key,_)| *key);
+        for (_,board) in positions {
+            let mut search = Search::new(&mut table, None);
+            search.move_ordering = true;
+            search.prune_walls = true;
+            if let Some(result) = iterative_deepening(&mut search, &board, depth) {
+                debug!("Book search: {}", result);
+                book.add(&board, result.best_move, config.search_weight);
+            }
+        }
+    }
+    book
+}
+
+/// Plays moves from an opening book while the game is in the book,
+/// and then lets the wrapped player continue from the current position.
+pub struct BookPlayer<P :EnginePlayer> {
+    book :Book,
+    player :P,
+    board :Board,
+    in_book :bool,
+    rng :StdRng,
+}
+
+impl<P :EnginePlayer> BookPlayer<P> {
+    pub fn new(book :Book, player :P, seed :u64) -> BookPlayer<P> {
+        BookPlayer {
+            book, player,
+            board: Default::default(),
+            in_book: true,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl<P :EnginePlayer> EnginePlayer for BookPlayer<P> {
+    fn set_clock(&mut self, clock :Clock) {
+        self.player.set_clock(clock);
+    }
+
+    fn set_position(&mut self, board :Board) {
+        self.board = board;
+        self.in_book = true;
+    }
+
+    fn set_stop(&mut self, stop :std::sync::Arc<std::sync::atomic::AtomicBool>) {
+        self.player.set_stop(stop);
+    }
+
+    fn search_info(&self) -> Option<SearchInfo> {
+        if self.in_book { None } else { self.player.search_info() }
+    }
+}
+
+impl<P :EnginePlayer> Player for BookPlayer<P> {
+    fn reset(&mut self) {
+        self.board = Default::default();
+        self.in_book = true;
+        self.player.reset();
+    }
+
+    fn mv(&mut self, mv :Option<Move>) -> Move {
+        if !self.in_book { return self.player.mv(mv); }
+
+        if let Some(mv) = mv { self.board.integrate(mv).unwrap(); }
+        if let Some(book_move) = self.book.choose(&self.board, &mut self.rng) {
+            info!("BookPlayer book move {}", printer(&book_move));
+            self.board.integrate(book_move).unwrap();
+            return book_move;
+        }
+
+        info!("BookPlayer leaving the book");
+        self.in_book = false;
+        self.player.set_position(self.board);
+        self.player.mv(None)
+    }
+}
+
+#[test]
+fn test_book_file_format() {
+    let board :Board = Default::default();
+    let mut book = Book::default();
+    book.add(&board, Move::PawnTo(Position { x: 5, y: 2 }), 3);
+    book.add(&board, Move::WallAt(Orientation::Horizontal, Position { x: 4, y: 8 }), 1);
+    book.add(&board, Move::PawnTo(Position { x: 5, y: 2 }), 2);
+    let mut after = board;
+    after.integrate(Move::PawnTo(Position { x: 5, y: 2 })).unwrap();
+    book.add(&after, Move::PawnTo(Position { x: 5, y: 8 }), 1);
+
+    assert_eq!(2, book.len());
+    assert_eq!(&[(Move::PawnTo(Position { x: 5, y: 2 }), 5),
+                 (Move::WallAt(Orientation::Horizontal, Position { x: 4, y: 8 }), 1)],
+               book.moves(&board));
+    let text = book.to_string();
+    assert_eq!(book, text.parse::<Book>().unwrap());
+    assert_eq!(book, format!("# comment\n\n{}", text).parse::<Book>().unwrap());
+
+    assert!("0123 e2".parse::<Book>().unwrap_err().starts_with("Line 1:"));
+    assert!("\nxyz e2 1".parse::<Book>().unwrap_err().starts_with("Line 2:"));
+    assert!("0123 e2 1 j1 1".parse::<Book>().is_err());
+}
+
+#[test]
+fn test_book_player() {
+    let board :Board = Default::default();
+    let (e2, d1) = (Move::PawnTo(Position { x: 5, y: 2 }), Move::PawnTo(Position { x: 4, y: 1 }));
+    let mut book = Book::default();
+    book.add(&board, e2, 3);
+    book.add(&board, d1, 1);
+
+    // Weighted random choice between the book moves.
+    let mut rng = StdRng::seed_from_u64(0);
+    let choices = (0..400).map(|_| book.choose(&board, &mut rng).unwrap()).collect::<Vec<_>>();
+    let count_e2 = choices.iter().filter(|mv| **mv == e2).count();
+    assert!(count_e2 > 250 && count_e2 < 350, "e2 chosen {} times", count_e2);
+    assert!(choices.iter().all(|mv| *mv == e2 || *mv == d1));
+
+    // The first move comes from the book, and the wrapped player
+    // continues from the position after leaving the book.
+    let heuristic = HeuristicBot::with_evaluator(Default::default(), default_evaluator());
+    let mut player = BookPlayer::new(book, heuristic, 0);
+    let first = player.mv(None);
+    assert!(first == e2 || first == d1);
+    let mut b = board;
+    b.integrate(first).unwrap();
+    let reply = Move::PawnTo(Position { x: 5, y: 8 });
+    b.integrate(reply).unwrap();
+    let second = player.mv(Some(reply));
+    assert!(!player.in_book);
+    assert!(b.is_valid_move(&second));
+    assert_eq!(b, player.board);
+}
+
+#[test]
+fn test_book_player_engines() {
+    use crate::options::{Options, Engine};
+    let board :Board = Default::default();
+    let e2 = Move::PawnTo(Position { x: 5, y: 2 });
+    let mut book = Book::default();
+    book.add(&board, e2, 1);
+
+    // Every engine created from the options can continue after the book.
+    for engine in &[Engine::Heuristic, Engine::Minimax, Engine::Mcts] {
+        let options = Options { engine: *engine, depth: Some(1), iterations: Some(50), .. Default::default() };
+        let mut player = BookPlayer::new(book.clone(), options.player().unwrap(), 0);
+        assert_eq!(e2, player.mv(None));
+        assert!(player.search_info().is_none());
+        let mut b = board;
+        b.integrate(e2).unwrap();
+        let reply = Move::PawnTo(Position { x: 5, y: 8 });
+        b.integrate(reply).unwrap();
+        assert!(b.is_valid_move(&player.mv(Some(reply))), "{:?}", engine);
+        assert!(!player.in_book);
+
+        // A new position goes back to the book.
+        player.set_position(board);
+        assert_eq!(e2, player.mv(None));
+    }
+}
+
+#[test]
+fn test_build_book() {
+    let evaluator = PathDifference { wall_weight: 0.1 };
+    let config = BookConfig { games: 3, plies: 4, max_plies: 200, search_depth: Some(1), .. Default::default() };
+    let book = build_book(&evaluator, &config);
+    assert!(!book.is_empty());
+    let board :Board = Default::default();
+    for (mv,w) in book.moves(&board) {
+        assert!(board.is_valid_move(mv));
+        assert!(*w > 0);
+    }
+}
@@ -1,17 +1,194 @@
 use model::*;
+use crate::minimax::*;
+use crate::tt::TranspositionTable;
+use crate::eval::*;
+use crate::tune::*;
+use crate::book::*;
+use std::io::{self, BufRead, Write};
 
+/// Play moves read from standard input and print the responses, see `run_bot`.
 pub fn stdin_bot(mut player :impl Player) {
-    use std::io::{self, BufRead};
     let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let move_out = match line.unwrap().as_str() {
-            "start" => player.mv(None),
-            x => {
-                let move_in = parse(x).unwrap();
-                player.mv(Some(move_in))
+    let stdout = io::stdout();
+    let result = run_bot(&mut player, stdin.lock(), &mut stdout.lock(), &mut io::stderr());
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Line protocol for a bot player. Each line is one of:
+///  - `start`: make the first move of the game.
+///  - a move by the opponent, which is answered with the player's move.
+///  - `newgame` or `reset`: start a new game.
+///  - `quit`: stop the bot.
+///
+/// Blank lines are ignored. Lines that cannot be parsed or contain illegal
+/// moves are reported on `errors` and otherwise ignored. The bot stops at the
+/// end of the input or when the output is closed.
+pub fn run_bot(player :&mut impl Player, input :impl BufRead,
+               output :&mut impl Write, errors :&mut impl Write) -> io::Result<()> {
+    let mut board :Board = Default::default();
+    for line in input.lines() {
+        let line = line?;
+        let mv = match line.trim() {
+            "" => continue,
+            "quit" => break,
+            "newgame" | "reset" => {
+                player.reset();
+                board = Default::default();
+                continue;
+            },
+            "start" if board != Default::default() => {
+                writeln!(errors, "Error: start is only allowed at the start of a game")?;
+                continue;
+            },
+            "start" => None,
+            x => match parse(x) {
+                Ok(mv) => Some(mv),
+                Err(e) => {
+                    writeln!(errors, "Error: could not parse move {:?}: {}", x, e)?;
+                    continue;
+                },
             },
         };
 
-        println!("{}", printer(&move_out));
+        if let Some(mv) = mv {
+            if let Some(winner) = board.get_winner() {
+                writeln!(errors, "Error: the game is over, player {} won", winner + 1)?;
+                continue;
+            }
+            if let Err(e) = board.integrate(mv) {
+                writeln!(errors, "Error: illegal move {}: {}", printer(&mv), e)?;
+                continue;
+            }
+            if board.get_winner().is_some() { continue; }
+        }
+
+        let reply = player.mv(mv);
+        board.integrate(reply).unwrap_or_else(|e|
+            panic!("Player made an illegal move {}: {}", printer(&reply), e));
+        writeln!(output, "{}", printer(&reply))?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Print perft node counts divided by the first move, from the position after
+/// playing the given moves from the start position.
+/// Arguments: `<depth> [move ...]`.
+pub fn perft_command(args :&[String]) -> Result<(),String> {
+    let depth = args.get(0).ok_or("perft requires a depth argument")?
+        .parse::<u32>().map_err(|e| format!("Could not parse depth: {}", e))?;
+
+    let mut board :Board = Default::default();
+    for mv in &args[1..] {
+        let m = parse(mv).map_err(|e| format!("Could not parse move {:?}: {}", mv, e))?;
+        board.integrate(m).map_err(|e| format!("Illegal move {}: {}", mv, e))?;
+    }
+
+    let start = std::time::Instant::now();
+    let mut total = 0;
+    for (mv,nodes) in perft_divide(&board, depth) {
+        println!("{}: {}", printer(&mv), nodes);
+        total += nodes;
+    }
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {:.3}s", start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+/// Compare searches to a fixed depth without move ordering, with move ordering,
+/// and with wall pruning, from the position after playing the given moves.
+/// Arguments: `<depth> [move ...]`.
+pub fn bench_command(args :&[String]) -> Result<(),String> {
+    let depth = args.get(0).ok_or("bench requires a depth argument")?
+        .parse::<u16>().map_err(|e| format!("Could not parse depth: {}", e))?;
+
+    let mut board :Board = Default::default();
+    for mv in &args[1..] {
+        let m = parse(mv).map_err(|e| format!("Could not parse move {:?}: {}", mv, e))?;
+        board.integrate(m).map_err(|e| format!("Illegal move {}: {}", mv, e))?;
+    }
+
+    let modes = [("no ordering", false, false), ("ordering", true, false),
+                 ("ordering + wall pruning", true, true)];
+    for (name, move_ordering, prune_walls) in modes.iter() {
+        let mut table = TranspositionTable::new(DEFAULT_TABLE_SIZE);
+        let mut search = Search::new(&mut table, None);
+        search.move_ordering = *move_ordering;
+        search.prune_walls = *prune_walls;
+        let result = iterative_deepening(&mut search, &board, depth).ok_or("No legal moves in the position")?;
+        println!("{}: {}", name, result);
     }
+    Ok(())
+}
+
+/// Fit evaluation weights to the results of self-play games, and write them
+/// to a weights file. The games are played with the initial weights, which
+/// are read from a weights file if given.
+/// Arguments: `<games> <output file> [initial weights file]`.
+pub fn tune_command(args :&[String]) -> Result<(),String> {
+    let games = args.get(0).ok_or("tune requires the number of games")?
+        .parse::<usize>().map_err(|e| format!("Could not parse number of games: {}", e))?;
+    let output = args.get(1).ok_or("tune requires an output file")?;
+    let initial = match args.get(2) {
+        Some(path) => Weights::load(path)?,
+        None => Default::default(),
+    };
+
+    let config = TuneConfig { games, .. Default::default() };
+    let samples = generate_samples(&Linear { weights: initial }, &config);
+    if samples.is_empty() { return Err("No finished games to fit weights to".to_string()); }
+    println!("Positions: {}", samples.len());
+    println!("Loss with initial weights: {:.4}", loss(&samples, &initial));
+
+    let weights = fit_weights(&samples, initial);
+    println!("Loss with fitted weights: {:.4}", loss(&samples, &weights));
+    print!("{}", weights);
+    weights.save(output)
+}
+
+/// Build an opening book from self-play games, optionally adding the best
+/// moves from a search to the given depth in every book position.
+/// Arguments: `<games> <plies> <output file> [search depth]`.
+pub fn book_command(args :&[String]) -> Result<(),String> {
+    let games = args.get(0).ok_or("book requires the number of games")?
+        .parse::<usize>().map_err(|e| format!("Could not parse number of games: {}", e))?;
+    let plies = args.get(1).ok_or("book requires the number of moves to include")?
+        .parse::<usize>().map_err(|e| format!("Could not parse number of moves: {}", e))?;
+    let output = args.get(2).ok_or("book requires an output file")?;
+    let search_depth = match args.get(3) {
+        Some(d) => Some(d.parse::<u16>().map_err(|e| format!("Could not parse depth: {}", e))?),
+        None => None,
+    };
+
+    let config = BookConfig { games, plies, search_depth, .. Default::default() };
+    let book = build_book(&*default_evaluator(), &config);
+    println!("Positions: {}", book.len());
+    book.save(output)
+}
+
+#[test]
+fn test_run_bot() {
+    let evaluator = std::sync::Arc::new(PathDifference { wall_weight: 0.05 });
+    let mut player = HeuristicBot::with_evaluator(Default::default(), evaluator);
+    let input = "start\r\n\n  \ne8\r\nzz9\ne1\nreset\nstart\nnewgame\ne2\nquit\ne7\n";
+    let (mut output, mut errors) = (Vec::new(), Vec::new());
+    run_bot(&mut player, input.as_bytes(), &mut output, &mut errors).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    let errors = String::from_utf8(errors).unwrap();
+    // Replies to the two starts, to e8 in the first game and to e2 in the third game.
+    assert_eq!(4, output.lines().count(), "output: {:?}", output);
+    let mut board :Board = Default::default();
+    let first = parse(output.lines().next().unwrap()).unwrap();
+    board.integrate(first).unwrap();
+    board.integrate(parse("e8").unwrap()).unwrap();
+    assert!(board.is_valid_move(&parse(output.lines().nth(1).unwrap()).unwrap()));
+    // The unparsable move and the illegal jump to e1 are reported.
+    assert_eq!(2, errors.lines().count(), "errors: {:?}", errors);
+    assert!(errors.contains("zz9"));
+    assert!(errors.contains("illegal move e1"));
 }
@@ -0,0 +1,414 @@
+use model::*;
+use log::*;
+use std::collections::HashMap;
+
+/// Result of a position with perfect play, for the player to move,
+/// with the number of moves (plies) until the game ends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RaceResult {
+    Win(u16),
+    Loss(u16),
+    /// Neither player can force a win.
+    Draw,
+}
+
+/// Exact solution of the pawn race for a fixed set of walls, where no more
+/// walls can be placed. Every pair of pawn positions with either player to move
+/// is solved by retrograde analysis, taking jumps into account.
+pub struct RaceSolver {
+    walls :(u64,u64),
+    results :Vec<Option<RaceResult>>,
+}
+
+fn state_index(board :&Board) -> usize {
+    let (p0, p1) = (board.position(0), board.position(1));
+    (encode9(p0.x,p0.y)*81 + encode9(p1.x,p1.y))*2 + board.player()
+}
+
+impl RaceSolver {
+    /// Solve the race for the walls on the board.
+    pub fn new(board :&Board) -> RaceSolver {
+        let mut template = *board;
+        template.walls_left = [0,0];
+        let boards = (0..81*81*2).map(|i| {
+            let (p0, p1, player) = (i / 2 / 81, i / 2 % 81, i % 2);
+            if p0 == p1 { return None; }
+            let mut b = template;
+            b.set_position(0, decode9(p0));
+            b.set_position(1, decode9(p1));
+            b.set_player(player);
+            Some(b)
+        }).collect::<Vec<_>>();
+
+        let mut results = boards.iter().map(|b| b.and_then(|b| {
+            b.get_winner().map(|w| if w == b.player() { RaceResult::Win(0) } else { RaceResult::Loss(0) })
+        })).collect::<Vec<_>>();
+
+        // Each round resolves the positions where the game ends one move later than
+        // in the previous round: a win if some move leads to a loss for the opponent,
+        // and a loss if every move leads to a win for the opponent.
+        // Positions that are never resolved are draws.
+        let children = boards.iter().map(|b| match b {
+            Some(b) if b.get_winner().is_none() => b.legal_pawn_moves().iter().map(|mv| {
+                let mut child = *b;
+                child.integrate(*mv).unwrap();
+                state_index(&child)
+            }).collect::<Vec<_>>(),
+            _ => Vec::new(),
+        }).collect::<Vec<_>>();
+
+        let mut rounds = 0;
+        loop {
+            let mut new_results = results.clone();
+            for (i, moves) in children.iter().enumerate() {
+                if results[i].is_some() || moves.is_empty() { continue; }
+                let child_results = moves.iter().map(|c| results[*c]).collect::<Vec<_>>();
+                let fastest_win = child_results.iter().filter_map(|r| match r {
+                    Some(RaceResult::Loss(n)) => Some(*n),
+                    _ => None,
+                }).min();
+                if let Some(n) = fastest_win {
+                    new_results[i] = Some(RaceResult::Win(n + 1));
+                } else if child_results.iter().all(|r| matches!(r, Some(RaceResult::Win(_)))) {
+                    let slowest_loss = child_results.iter().filter_map(|r| match r {
+                        Some(RaceResult::Win(n)) => Some(*n),
+                        _ => None,
+                    }).max().unwrap();
+                    new_results[i] = Some(RaceResult::Loss(slowest_loss + 1));
+                }
+            }
+            rounds += 1;
+            if new_results == results { break; }
+            results = new_results;
+        }
+        debug!("Race solved in {} rounds", rounds);
+
+        RaceSolver { walls: board.get_wall_bitsets(), results }
+    }
+
+    /// Is the solution valid for the walls on this board?
+    pub fn matches(&self, board :&Board) -> bool {
+        self.walls == board.get_wall_bitsets()
+    }
+
+    /// Result for the player to move, assuming no more walls are placed.
+    pub fn result(&self, board :&Board) -> RaceResult {
+        self.results[state_index(board)].unwrap_or(RaceResult::Draw)
+    }
+
+    /// A pawn move that wins as fast as possible, or loses as slowly as possible.
+    pub fn best_move(&self, board :&Board) -> Option<Move> {
+        // Rank moves by the result for the opponent after the move.
+        let rank = |r :RaceResult| match r {
+            RaceResult::Loss(n) => (2, -(n as i32)),
+            RaceResult::Draw => (1, 0),
+            RaceResult::Win(n) => (0, n as i32),
+        };
+        board.legal_pawn_moves().iter().cloned().max_by_key(|mv| {
+            let mut child = *board;
+            child.integrate(*mv).unwrap();
+            rank(self.result(&child))
+        })
+    }
+}
+
+/// Largest number of walls left for the opponent for which `Endgame` tries
+/// to prove that the walls cannot stop a player. Each wall multiplies the
+/// size of the proof search.
+const MAX_PROOF_WALLS :u8 = 2;
+
+/// Limit on the positions searched in such a proof.
+const MAX_PROOF_NODES :usize = 2000;
+
+/// Search for a proof that a player reaches the goal row before the opponent,
+/// whatever walls the opponent places.
+///
+/// The race is modelled with distances to the goal rows, ignoring the pawns.
+/// The player steps along a shortest path, and may not step onto the cell
+/// where the opponent's pawn is now. The opponent either makes progress
+/// with a pawn move, or places a wall that leaves the player a path to the
+/// goal. It needs its current distance to the goal in pawn moves, less one
+/// for a possible jump over the player.
+struct WallProof {
+    player :usize,
+    memo :HashMap<(u64,u64,usize,u8,u8,bool), bool>,
+    nodes :usize,
+}
+
+impl WallProof {
+    fn new(player :usize) -> WallProof {
+        WallProof { player, memo: HashMap::new(), nodes: 0 }
+    }
+
+    /// Steps along a shortest path to the goal for the player.
+    fn steps(&self, board :&Board) -> Vec<Position> {
+        let dist = board.distance_map(self.player);
+        let d = |p :Position| dist[encode9(p.x,p.y)];
+        let pos = board.position(self.player);
+        let other = board.position(1 - self.player);
+        board.open_neighbors(pos).filter(|n| d(*n) + 1 == d(pos) && *n != other).collect()
+    }
+
+    /// A step that wins for the player to move, if one can be proven.
+    fn winning_step(&mut self, board :&Board) -> Option<Move> {
+        let opponent_moves = board.distance_to_goal(1 - self.player)?.saturating_sub(1);
+        let walls = board.walls_left[1 - self.player];
+        for step in self.steps(board) {
+            let mut b = *board;
+            b.set_position(self.player, step);
+            if self.wins(&b, opponent_moves, walls, false)? { return Some(Move::PawnTo(step)); }
+        }
+        None
+    }
+
+    /// Does the player win when the opponent needs `opponent_moves` more pawn
+    /// moves and has `walls` walls left? `None` if the search is too large.
+    fn wins(&mut self, board :&Board, opponent_moves :u8, walls :u8, player_to_move :bool) -> Option<bool> {
+        let d = match board.distance_to_goal(self.player) {
+            Some(d) => d,
+            None => return Some(false),
+        };
+        if d == 0 { return Some(true); }
+        if opponent_moves == 0 { return Some(false); }
+        // Without walls, or too far behind even without them, the distances decide.
+        let race_won = if player_to_move { d <= opponent_moves } else { d < opponent_moves };
+        if walls == 0 || !race_won { return Some(race_won); }
+
+        let pos = board.position(self.player);
+        let (horizontal, vertical) = board.get_wall_bitsets();
+        let key = (horizontal, vertical, encode9(pos.x,pos.y), opponent_moves, walls, player_to_move);
+        if let Some(won) = self.memo.get(&key) { return Some(*won); }
+        self.nodes += 1;
+        if self.nodes > MAX_PROOF_NODES { return None; }
+
+        let won = if player_to_move {
+            let mut won = false;
+            for step in self.steps(board) {
+                let mut b = *board;
+                b.set_position(self.player, step);
+                if self.wins(&b, opponent_moves, walls, false)? { won = true; break; }
+            }
+            won
+        } else {
+            let mut won = self.wins(board, opponent_moves - 1, walls, true)?;
+            'walls: for ori in &[Orientation::Horizontal, Orientation::Vertical] {
+                for x in 1..=8 {
+                    for y in 1..=8 {
+                        if !won { break 'walls; }
+                        let pos = Position { x, y };
+                        if board.wall_conflicts(*ori, pos) { continue; }
+                        let mut b = *board;
+                        b.add_wall(*ori, pos);
+                        if b.distance_to_goal(self.player).is_none() { continue; }
+                        won = self.wins(&b, opponent_moves, walls - 1, true)?;
+                    }
+                }
+            }
+            won
+        };
+        self.memo.insert(key, won);
+        Some(won)
+    }
+}
+
+/// Keeps the race solution for the current walls, for players to consult
+/// before searching.
+#[derive(Default)]
+pub struct Endgame {
+    solver :Option<RaceSolver>,
+}
+
+impl Endgame {
+    fn solver(&mut self, board :&Board) -> &RaceSolver {
+        if !self.solver.as_ref().map(|s| s.matches(board)).unwrap_or(false) {
+            self.solver = Some(RaceSolver::new(board));
+        }
+        self.solver.as_ref().unwrap()
+    }
+
+    /// The player that is sure to win, if the opponent's walls cannot stop
+    /// them. When the opponent has no walls left, this is the exact result of
+    /// the pawn race. When the opponent has a few walls left, the win is proven
+    /// by a search over the opponent's walls (see `WallProof`), with the
+    /// pawns only interacting by a jump.
+    pub fn guaranteed_winner(&mut self, board :&Board) -> Option<usize> {
+        let me = board.player();
+        let has_walls = |p :usize| board.walls_left[p] > 0;
+        if !has_walls(0) || !has_walls(1) {
+            match self.solver(board).result(board) {
+                RaceResult::Win(_) if !has_walls(1 - me) => return Some(me),
+                RaceResult::Loss(_) if !has_walls(me) => return Some(1 - me),
+                _ => {},
+            }
+        }
+        [me, 1 - me].iter().cloned().find(|p| self.walls_cannot_stop(board, *p))
+    }
+
+    /// Is there a proof that the opponent's walls cannot stop the player?
+    fn walls_cannot_stop(&self, board :&Board, player :usize) -> bool {
+        let walls = board.walls_left[1 - player];
+        if walls == 0 || walls > MAX_PROOF_WALLS { return false; }
+        let opponent_moves = match board.distance_to_goal(1 - player) {
+            Some(d) => d.saturating_sub(1),
+            None => return false,
+        };
+        let mut proof = WallProof::new(player);
+        let won = proof.wins(board, opponent_moves, walls, board.player() == player);
+        debug!("Wall proof for player {}: {:?} ({} nodes)", player + 1, won, proof.nodes);
+        won == Some(true)
+    }
+
+    /// Perfect move when the game is decided by the pawn race: any position
+    /// without walls left, and positions where the player to move wins the
+    /// race and the opponent has no walls left to stop it. When the opponent
+    /// has a few walls left, a step that is proven to win despite them.
+    pub fn best_move(&mut self, board :&Board) -> Option<Move> {
+        let me = board.player();
+        if board.walls_left[1 - me] == 0 {
+            if board.walls_left[me] == 0 || self.guaranteed_winner(board) == Some(me) {
+                return self.solver(board).best_move(board);
+            }
+            return None;
+        }
+        if board.walls_left[1 - me] > MAX_PROOF_WALLS { return None; }
+        WallProof::new(me).winning_step(board)
+    }
+}
+
+#[cfg(test)]
+fn board_with(p0 :Position, p1 :Position, player :usize, walls_left :[u8;2]) -> Board {
+    let mut board :Board = Default::default();
+    board.set_position(0, p0);
+    board.set_position(1, p1);
+    board.set_player(player);
+    board.walls_left = walls_left;
+    board
+}
+
+/// Can the player to move force a win within `depth` plies, using only pawn moves?
+#[cfg(test)]
+fn forced_win(board :&Board, depth :u16) -> bool {
+    if depth == 0 { return false; }
+    board.legal_pawn_moves().iter().any(|mv| {
+        let mut child = *board;
+        child.integrate(*mv).unwrap();
+        child.get_winner().is_some() ||
+            (depth > 1 && child.legal_pawn_moves().iter().all(|reply| {
+                let mut grandchild = child;
+                grandchild.integrate(*reply).unwrap();
+                grandchild.get_winner().is_none() && forced_win(&grandchild, depth - 2)
+            }))
+    })
+}
+
+#[test]
+fn test_race_solver() {
+    let pos = |x,y| Position { x, y };
+    let mut board = board_with(pos(1,8), pos(5,9), 0, [0,0]);
+    let solver = RaceSolver::new(&board);
+    assert_eq!(RaceResult::Win(1), solver.result(&board));
+    assert_eq!(Some(Move::PawnTo(pos(1,9))), solver.best_move(&board));
+
+    // Symmetric start without walls: a draw, because the player who steps
+    // next to the other pawn is jumped over and loses the race.
+    board = board_with(pos(5,1), pos(5,9), 0, [0,0]);
+    assert_eq!(RaceResult::Draw, solver.result(&board));
+    assert!(solver.best_move(&board).is_some());
+    // Far ahead on different files wins, with either player to move.
+    board.set_position(0, pos(1,5));
+    assert_eq!(RaceResult::Win(7), solver.result(&board));
+    // With player 2 to move it is a draw: player 2 reaches the a file in time
+    // to block player 1 from the goal row, moving along it, because the board
+    // edge does not allow diagonal jumps.
+    board.set_player(1);
+    assert_eq!(RaceResult::Draw, solver.result(&board));
+    assert_eq!(Some(Move::PawnTo(pos(4,9))), solver.best_move(&board));
+    board = board_with(pos(5,1), pos(5,9), 1, [0,0]);
+
+    // Compare short wins with a direct search, with a wall to make it less regular.
+    board.add_wall(Orientation::Horizontal, pos(4,5));
+    let solver = RaceSolver::new(&board);
+    let mut checked = 0;
+    for p0 in 0..81 {
+        for p1 in 0..81 {
+            let b = board_with(decode9(p0), decode9(p1), 0, [0,0]);
+            if p0 == p1 || b.get_winner().is_some() { continue; }
+            let mut b = b;
+            b.add_wall(Orientation::Horizontal, pos(4,5));
+            if let RaceResult::Win(n) = solver.result(&b) {
+                if n <= 5 {
+                    assert!(forced_win(&b, n), "no win in {} for {:?}", n, b);
+                    assert!(n < 3 || !forced_win(&b, n - 2), "faster win than {} for {:?}", n, b);
+                    checked += 1;
+                }
+            }
+        }
+    }
+    assert!(checked > 100);
+}
+
+#[test]
+fn test_guaranteed_winner() {
+    let pos = |x,y| Position { x, y };
+    let mut endgame :Endgame = Default::default();
+
+    // Player 1 (index 0) is far ahead.
+    let board = board_with(pos(5,7), pos(5,6), 0, [0,0]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    assert!(endgame.best_move(&board).is_some());
+
+    // Player 2 has walls left to stop player 1.
+    let board = board_with(pos(5,7), pos(5,6), 0, [0,3]);
+    assert_eq!(None, endgame.guaranteed_winner(&board));
+    assert_eq!(None, endgame.best_move(&board));
+
+    // Player 1 has walls left but does not need them.
+    let board = board_with(pos(5,7), pos(5,6), 0, [3,0]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    assert!(matches!(endgame.best_move(&board), Some(Move::PawnTo(_))));
+
+    // Player 2 to move without walls, losing the race.
+    let board = board_with(pos(5,7), pos(5,6), 1, [3,0]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    assert_eq!(None, endgame.best_move(&board));
+}
+
+#[test]
+fn test_walls_cannot_stop() {
+    let pos = |x,y| Position { x, y };
+    let mut endgame :Endgame = Default::default();
+
+    // Player 2 needs six moves, and player 1 two. The wall a8h after a8
+    // lengthens player 1's path by two moves, which is not enough.
+    let board = board_with(pos(1,7), pos(9,7), 0, [0,1]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    assert_eq!(Some(Move::PawnTo(pos(1,8))), endgame.best_move(&board));
+    // The same with player 2 to move, and with two walls against a player 2 further behind.
+    let board = board_with(pos(1,7), pos(9,7), 1, [0,1]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    let board = board_with(pos(1,7), pos(9,9), 0, [0,2]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    assert!(endgame.best_move(&board).is_some());
+
+    // Player 2 needs three moves. Player 1 wins the race without walls,
+    // but the wall at a8h after a8 stops player 1.
+    let board = board_with(pos(1,7), pos(9,4), 0, [0,0]);
+    assert_eq!(Some(0), endgame.guaranteed_winner(&board));
+    let board = board_with(pos(1,7), pos(9,4), 0, [0,1]);
+    assert_eq!(None, endgame.guaranteed_winner(&board));
+    assert_eq!(None, endgame.best_move(&board));
+
+    // Check the winning step against an opponent that places its wall at once,
+    // trying every wall, and then races along its shortest path.
+    let mut board = board_with(pos(1,7), pos(9,7), 0, [0,1]);
+    let step = endgame.best_move(&board).unwrap();
+    board.integrate(step).unwrap();
+    for mv in board.legal_wall_moves().iter() {
+        let mut b = board;
+        b.integrate(*mv).unwrap();
+        while b.get_winner().is_none() {
+            b.integrate(Move::PawnTo(b.shortest_path(b.player()).unwrap()[0])).unwrap();
+        }
+        assert_eq!(Some(0), b.get_winner(), "after {}", printer(mv));
+    }
+}
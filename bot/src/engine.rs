@@ -0,0 +1,342 @@
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use model::*;
+use log::*;
+use crate::options::Options;
+
+/// Options announced in the handshake, with their default values.
+const ENGINE_OPTIONS :&[(&str, Option<&str>)] = &[
+    ("engine", Some("heuristic")),
+    ("depth", None),
+    ("time", None),
+    ("iterations", None),
+    ("playout", Some("shortest:0.7")),
+    ("evaluator", Some("resistance")),
+    ("weights", None),
+    ("tt-size", Some("64")),
+    ("threads", Some("1")),
+    ("prune-walls", Some("false")),
+    ("seed", None),
+    ("book", None),
+];
+
+/// A player that can be driven by the engine protocol, which can start from
+/// any position and is given the clocks before each move. The player searches
+/// in another thread than the one reading the protocol.
+pub trait EnginePlayer :Player + Send {
+    /// Time left for the player, given before each call to `mv` when
+    /// the game is played with a clock.
+    fn set_clock(&mut self, clock :Clock);
+
+    /// Continue the game from the given position, with the player to move
+    /// given by the board.
+    fn set_position(&mut self, board :Board);
+
+    /// Flag that is set when the referee sends `stop`. The player should then
+    /// end the search for the current move and answer with the best move so far.
+    fn set_stop(&mut self, stop :Arc<AtomicBool>);
+
+    /// Information about the search for the last move, or `None` if the
+    /// move was not searched.
+    fn search_info(&self) -> Option<SearchInfo>;
+}
+
+impl<P :EnginePlayer + ?Sized> EnginePlayer for Box<P> {
+    fn set_clock(&mut self, clock :Clock) { (**self).set_clock(clock) }
+    fn set_position(&mut self, board :Board) { (**self).set_position(board) }
+    fn set_stop(&mut self, stop :Arc<AtomicBool>) { (**self).set_stop(stop) }
+    fn search_info(&self) -> Option<SearchInfo> { (**self).search_info() }
+}
+
+/// Run the engine protocol (see `model::protocol`) on standard input and output.
+pub fn stdin_engine(options :Options, player :Box<dyn EnginePlayer>) {
+    let stdin = io::stdin();
+    let result = run_engine(options, player, stdin.lock(), &mut io::stdout(), &mut io::stderr());
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn respond(output :&mut impl Write, response :Response) -> io::Result<()> {
+    writeln!(output, "{}", response)?;
+    output.flush()
+}
+
+/// A search running in its own thread, so that the engine can read `stop`
+/// while the player searches. The thread answers with `info` and `bestmove`
+/// and returns the move.
+struct Running<'scope> {
+    thread :thread::ScopedJoinHandle<'scope, io::Result<Move>>,
+    stop :Arc<AtomicBool>,
+}
+
+impl Running<'_> {
+    /// Wait for the search to finish, and add the move to the moves seen by the player.
+    fn finish(self, seen :&mut Vec<Move>) -> io::Result<()> {
+        let reply = self.thread.join().expect("The search thread panicked")?;
+        seen.push(reply);
+        Ok(())
+    }
+}
+
+/// Engine side of the protocol. The player is created again from the options
+/// when they have been changed by `setoption`. Commands that cannot be parsed
+/// or carried out are reported on `errors` and otherwise ignored.
+///
+/// The player searches in another thread, while this thread keeps reading
+/// commands. `stop` and `quit` end the search early. Other commands than
+/// `isready` wait for the search to finish before they are carried out.
+pub fn run_engine(mut options :Options, player :Box<dyn EnginePlayer>, input :impl BufRead,
+                  output :&mut (impl Write + Send), errors :&mut impl Write) -> io::Result<()> {
+    let player = Mutex::new(player);
+    let output = Mutex::new(output);
+    let mut options_changed = false;
+    // The position given by the referee.
+    let mut board :Board = Default::default();
+    let mut moves :Vec<Move> = Vec::new();
+    // The moves that the player has seen, from its own point of view.
+    let mut seen :Vec<Move> = Vec::new();
+
+    thread::scope(|scope| {
+        let mut running :Option<Running> = None;
+        for line in input.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() { continue; }
+            debug!("Engine received {:?}", line);
+            let command = match line.parse::<Command>() {
+                Ok(command) => command,
+                Err(e) => {
+                    writeln!(errors, "Error: {}", e)?;
+                    continue;
+                },
+            };
+
+            if matches!(command, Command::Stop | Command::Quit) {
+                if let Some(r) = &running { r.stop.store(true, Ordering::Relaxed); }
+            }
+            if !matches!(command, Command::IsReady) {
+                if let Some(r) = running.take() { r.finish(&mut seen)?; }
+            }
+
+            // Apply changed options before using the player.
+            if options_changed && running.is_none()
+                && matches!(command, Command::IsReady | Command::NewGame | Command::Go(_)) {
+                options_changed = false;
+                match options.player() {
+                    Ok(p) => {
+                        *player.lock().unwrap() = p;
+                        seen.clear();
+                    },
+                    Err(e) => writeln!(errors, "Error: {}", e)?,
+                }
+            }
+
+            match command {
+                Command::Quoridor => {
+                    let output = &mut *output.lock().unwrap();
+                    respond(output, Response::Id { key: "name".to_string(),
+                        value: format!("bot {}", env!("CARGO_PKG_VERSION")) })?;
+                    respond(output, Response::Id { key: "author".to_string(),
+                        value: env!("CARGO_PKG_AUTHORS").to_string() })?;
+                    respond(output, Response::Protocol(PROTOCOL_VERSION))?;
+                    for (name, default) in ENGINE_OPTIONS {
+                        respond(output, Response::EngineOption {
+                            name: name.to_string(), default: default.map(|d| d.to_string()),
+                        })?;
+                    }
+                    respond(output, Response::Ok)?;
+                },
+                Command::SetOption { name, value } => match options.set_option(&name, &value) {
+                    Ok(()) => options_changed = true,
+                    Err(e) => writeln!(errors, "Error: {}", e)?,
+                },
+                Command::IsReady => respond(&mut *output.lock().unwrap(), Response::ReadyOk)?,
+                Command::NewGame => {
+                    player.lock().unwrap().reset();
+                    board = Default::default();
+                    moves.clear();
+                    seen.clear();
+                },
+                Command::Position(new_moves) => {
+                    let mut new_board :Board = Default::default();
+                    let illegal = new_moves.iter().find_map(|mv| new_board.integrate(*mv).err().map(|e| (mv,e)));
+                    match illegal {
+                        Some((mv,e)) => writeln!(errors, "Error: illegal move {} in position: {}", printer(mv), e)?,
+                        None => {
+                            board = new_board;
+                            moves = new_moves;
+                        },
+                    }
+                },
+                Command::Go(params) => {
+                    if let Some(winner) = board.get_winner() {
+                        writeln!(errors, "Error: the game is over, player {} won", winner + 1)?;
+                        continue;
+                    }
+
+                    // Follow the game move by move when possible, so that players can
+                    // reuse their search, and otherwise set up the position.
+                    let last_move = if moves.len() == seen.len() + 1 && moves.starts_with(&seen) {
+                        moves.last().cloned()
+                    } else if moves.is_empty() && seen.is_empty() {
+                        None
+                    } else {
+                        player.lock().unwrap().set_position(board);
+                        None
+                    };
+                    seen = moves.clone();
+
+                    let stop = Arc::new(AtomicBool::new(false));
+                    let (player, output) = (&player, &output);
+                    let clock = params.clock(board.player());
+                    let search_stop = stop.clone();
+                    let thread = scope.spawn(move || {
+                        let mut player = player.lock().unwrap();
+                        if let Some(clock) = clock { player.set_clock(clock); }
+                        player.set_stop(search_stop);
+                        let reply = player.mv(last_move);
+                        let output = &mut *output.lock().unwrap();
+                        if let Some(info) = player.search_info() { respond(output, Response::Info(info))?; }
+                        respond(output, Response::BestMove(reply))?;
+                        Ok(reply)
+                    });
+                    running = Some(Running { thread, stop });
+                },
+                Command::Stop => {},
+                Command::Quit => break,
+            }
+        }
+        match running {
+            Some(r) => r.finish(&mut seen),
+            None => Ok(()),
+        }
+    })
+}
+
+#[cfg(test)]
+struct RecordingPlayer {
+    board :Board,
+    calls :Arc<Mutex<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl Player for RecordingPlayer {
+    fn mv(&mut self, mv :Option<Move>) -> Move {
+        self.calls.lock().unwrap().push(format!("mv {:?}", mv.map(|m| printer(&m))));
+        if let Some(mv) = mv { self.board.integrate(mv).unwrap(); }
+        let reply = self.board.legal_pawn_moves()[0];
+        self.board.integrate(reply).unwrap();
+        reply
+    }
+
+    fn reset(&mut self) {
+        self.board = Default::default();
+    }
+}
+
+#[cfg(test)]
+impl EnginePlayer for RecordingPlayer {
+    fn set_clock(&mut self, _clock :Clock) {}
+
+    fn set_position(&mut self, board :Board) {
+        self.calls.lock().unwrap().push("set_position".to_string());
+        self.board = board;
+    }
+
+    fn set_stop(&mut self, _stop :Arc<AtomicBool>) {}
+
+    fn search_info(&self) -> Option<SearchInfo> { None }
+}
+
+#[test]
+fn test_engine_handshake() {
+    let input = "quoridor\nsetoption name evaluator value path\nsetoption name nope value 1\n\
+                 isready\nnewgame\nposition startpos\ngo p1time 10000 p2time 10000\nbogus\nquit\nisready\n";
+    let (mut output, mut errors) = (Vec::new(), Vec::new());
+    let player = Box::new(RecordingPlayer { board: Default::default(), calls: Default::default() });
+    run_engine(Default::default(), player, input.as_bytes(), &mut output, &mut errors).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    let errors = String::from_utf8(errors).unwrap();
+
+    let responses = output.lines().map(|l| l.parse::<Response>().unwrap()).collect::<Vec<_>>();
+    assert!(matches!(&responses[0], Response::Id { key, .. } if key == "name"));
+    assert!(responses.contains(&Response::Protocol(PROTOCOL_VERSION)));
+    assert!(responses.contains(&Response::EngineOption { name: "engine".to_string(),
+                                                         default: Some("heuristic".to_string()) }));
+    // The handshake ends with ok, and the quit stops the engine before the last isready.
+    let ok = responses.iter().position(|r| *r == Response::Ok).unwrap();
+    assert_eq!(Response::ReadyOk, responses[ok + 1]);
+    assert!(matches!(responses[ok + 2], Response::Info(SearchInfo { depth: Some(1), .. })));
+    let board :Board = Default::default();
+    match responses[ok + 3] {
+        Response::BestMove(mv) => assert!(board.is_valid_move(&mv)),
+        ref r => panic!("expected bestmove, got {:?}", r),
+    }
+    assert_eq!(ok + 4, responses.len());
+
+    assert_eq!(2, errors.lines().count(), "errors: {:?}", errors);
+    assert!(errors.contains("--nope") && errors.contains("bogus"));
+}
+
+#[test]
+fn test_engine_positions() {
+    let calls :Arc<Mutex<Vec<String>>> = Default::default();
+    let player = Box::new(RecordingPlayer { board: Default::default(), calls: calls.clone() });
+    let board :Board = Default::default();
+    let first = printer(&board.legal_pawn_moves()[0]);
+    let input = format!("newgame\nposition startpos\ngo\n\
+                         position startpos moves {} e8\ngo\n\
+                         position startpos moves e2 e8\ngo\n\
+                         position startpos moves e2 e5\ngo\n", first);
+    let (mut output, mut errors) = (Vec::new(), Vec::new());
+    run_engine(Default::default(), player, input.as_bytes(), &mut output, &mut errors).unwrap();
+    let errors = String::from_utf8(errors).unwrap();
+
+    // The first two moves follow the game, the third sets up a new position,
+    // and the position with an illegal move is rejected, so the last go repeats the third.
+    assert_eq!(&["mv None", "mv Some(\"e8\")", "set_position", "mv None", "set_position", "mv None"],
+               calls.lock().unwrap().as_slice());
+    assert_eq!(4, String::from_utf8(output).unwrap().lines().count());
+    assert_eq!(1, errors.lines().count(), "errors: {:?}", errors);
+    assert!(errors.contains("illegal move e5"));
+}
+
+#[test]
+fn test_engine_stop() {
+    use std::time::{Duration, Instant};
+    // Without a time limit, the search only ends when the engine is stopped.
+    let options = Options { engine: crate::options::Engine::Minimax, depth: Some(32), .. Default::default() };
+    let player = options.player().unwrap();
+    let (reader, mut writer) = io::pipe().unwrap();
+    let (mut output, mut errors) = (Vec::new(), Vec::new());
+    let stopped = thread::scope(|scope| {
+        let engine = scope.spawn(|| run_engine(options.clone(), player, io::BufReader::new(reader),
+                                               &mut output, &mut errors));
+        writeln!(writer, "position startpos moves e2 e8\ngo").unwrap();
+        thread::sleep(Duration::from_millis(300));
+        let start = Instant::now();
+        writeln!(writer, "stop").unwrap();
+        // The end of the input waits for the search, which only ends because of the stop.
+        drop(writer);
+        engine.join().unwrap().unwrap();
+        start.elapsed()
+    });
+    assert!(stopped < Duration::from_secs(5), "stopped after {:?}", stopped);
+    assert!(errors.is_empty());
+
+    let responses = String::from_utf8(output).unwrap().lines()
+        .map(|l| l.parse::<Response>().unwrap()).collect::<Vec<_>>();
+    assert_eq!(2, responses.len(), "{:?}", responses);
+    assert!(matches!(responses[0], Response::Info(_)));
+    let mut board :Board = Default::default();
+    board.integrate(Move::PawnTo(Position { x: 5, y: 2 })).unwrap();
+    board.integrate(Move::PawnTo(Position { x: 5, y: 8 })).unwrap();
+    match responses[1] {
+        Response::BestMove(mv) => assert!(board.is_valid_move(&mv)),
+        ref r => panic!("expected bestmove, got {:?}", r),
+    }
+}
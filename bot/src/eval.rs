@@ -0,0 +1,330 @@
+use std::sync::Arc;
+use model::*;
+use crate::minimax::{effective_resistance, player_flow};
+
+/// Static evaluation of a board for the search.
+pub trait Evaluator : std::fmt::Debug + Send + Sync {
+    /// Score of the board from the perspective of player 1 (index 0):
+    /// positive numbers are good for player 1, negative for player 2.
+    /// Finished games score plus or minus infinity.
+    fn evaluate(&self, board :&Board) -> f32;
+}
+
+/// The default evaluator: effective resistance using the conjugate gradient solver.
+pub fn default_evaluator() -> Arc<dyn Evaluator> {
+    Arc::new(Resistance { solver: Solver::ConjugateGradient, wall_weight: 0.05 })
+}
+
+/// Score for a finished game, if there is a winner.
+fn winner_score(board :&Board) -> Option<f32> {
+    board.get_winner().map(|winner| if winner == 0 { std::f32::INFINITY } else { -std::f32::INFINITY })
+}
+
+fn walls_left_difference(board :&Board) -> f32 {
+    board.walls_left[0] as f32 - board.walls_left[1] as f32
+}
+
+/// Difference in shortest path length to the goal row, plus a bonus for each
+/// wall left.
+#[derive(Copy, Clone, Debug)]
+pub struct PathDifference {
+    pub wall_weight :f32,
+}
+
+impl Evaluator for PathDifference {
+    fn evaluate(&self, board :&Board) -> f32 {
+        if let Some(score) = winner_score(board) { return score; }
+        let distance = |p| board.distance_to_goal(p).unwrap_or(UNREACHABLE) as f32;
+        (distance(1) - distance(0)) + self.wall_weight * walls_left_difference(board)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Solver {
+    /// Iterative least squares solver (`effective_resistance`).
+    Lsqr,
+    /// Conjugate gradient solver for the graph Laplacian (`effective_resistance_cg`).
+    ConjugateGradient,
+}
+
+/// Difference in effective resistance between the pawn and the goal row,
+/// seeing each open step between cells as a unit resistor. This rewards both
+/// short paths and having many alternative paths. Plus a bonus for each wall left.
+#[derive(Copy, Clone, Debug)]
+pub struct Resistance {
+    pub solver :Solver,
+    pub wall_weight :f32,
+}
+
+impl Evaluator for Resistance {
+    fn evaluate(&self, board :&Board) -> f32 {
+        if let Some(score) = winner_score(board) { return score; }
+        let resistance = |p| match self.solver {
+            Solver::Lsqr => effective_resistance(board, p),
+            Solver::ConjugateGradient => effective_resistance_cg(board, p),
+        };
+        (resistance(1) - resistance(0)) as f32 + self.wall_weight * walls_left_difference(board)
+    }
+}
+
+/// Difference in maximum flow from the pawn to the goal row (see `player_flow`),
+/// plus a bonus for each wall left.
+#[derive(Copy, Clone, Debug)]
+pub struct Flow {
+    pub wall_weight :f32,
+}
+
+impl Evaluator for Flow {
+    fn evaluate(&self, board :&Board) -> f32 {
+        if let Some(score) = winner_score(board) { return score; }
+        // Flow capacities start at 100 at the pawn, scale to around one per step.
+        let flow = |p| player_flow(board, p) as f32 / 100.0;
+        (flow(0) - flow(1)) + self.wall_weight * walls_left_difference(board)
+    }
+}
+
+/// Weighted sum of other evaluators.
+#[derive(Clone, Debug)]
+pub struct Weighted(pub Vec<(f32, Arc<dyn Evaluator>)>);
+
+impl Evaluator for Weighted {
+    fn evaluate(&self, board :&Board) -> f32 {
+        if let Some(score) = winner_score(board) { return score; }
+        self.0.iter().map(|(weight, evaluator)| weight * evaluator.evaluate(board)).sum()
+    }
+}
+
+/// Number of features used by `Linear`.
+pub const NUM_FEATURES :usize = 5;
+
+/// Weights for the features of `Linear`. Each feature is a difference between
+/// the players, positive when good for player 1 (index 0).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Weights {
+    /// Shortest path length to the goal row.
+    pub path :f32,
+    /// Number of walls left.
+    pub walls :f32,
+    /// Effective resistance to the goal row.
+    pub resistance :f32,
+    /// Maximum flow to the goal row, see `player_flow`.
+    pub flow :f32,
+    /// Number of possible pawn moves.
+    pub mobility :f32,
+}
+
+const FEATURE_NAMES :[&str; NUM_FEATURES] = ["path", "walls", "resistance", "flow", "mobility"];
+
+impl Default for Weights {
+    /// The same evaluation as the default evaluator.
+    fn default() -> Weights {
+        Weights { path: 0.0, walls: 0.05, resistance: 1.0, flow: 0.0, mobility: 0.0 }
+    }
+}
+
+impl Weights {
+    pub fn to_array(&self) -> [f32; NUM_FEATURES] {
+        [self.path, self.walls, self.resistance, self.flow, self.mobility]
+    }
+
+    pub fn from_array(w :[f32; NUM_FEATURES]) -> Weights {
+        Weights { path: w[0], walls: w[1], resistance: w[2], flow: w[3], mobility: w[4] }
+    }
+
+    /// Read weights from a file with lines of the form `name = value`.
+    /// Empty lines and lines starting with `#` are ignored, and
+    /// missing weights are zero.
+    pub fn load(path :&str) -> Result<Weights,String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read weights file {:?}: {}", path, e))?;
+        text.parse()
+    }
+
+    pub fn save(&self, path :&str) -> Result<(),String> {
+        std::fs::write(path, self.to_string())
+            .map_err(|e| format!("Could not write weights file {:?}: {}", path, e))
+    }
+}
+
+impl std::fmt::Display for Weights {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, weight) in FEATURE_NAMES.iter().zip(self.to_array().iter()) {
+            writeln!(f, "{} = {}", name, weight)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Weights {
+    type Err = String;
+    fn from_str(text :&str) -> Result<Weights,String> {
+        let mut weights = [0.0; NUM_FEATURES];
+        for (i,line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+            let mut parts = line.splitn(2, '=').map(|p| p.trim());
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => return Err(format!("Line {}: expected `name = value`", i+1)),
+            };
+            let idx = FEATURE_NAMES.iter().position(|n| *n == name)
+                .ok_or_else(|| format!("Line {}: unknown weight {:?}", i+1, name))?;
+            weights[idx] = value.parse()
+                .map_err(|e| format!("Line {}: could not parse {:?}: {}", i+1, value, e))?;
+        }
+        Ok(Weights::from_array(weights))
+    }
+}
+
+/// Feature values of a board, in the order of `Weights::to_array`.
+pub fn features(board :&Board) -> [f32; NUM_FEATURES] {
+    let distance = |p| board.distance_to_goal(p).unwrap_or(UNREACHABLE) as f32;
+    let resistance = |p| effective_resistance_cg(board, p) as f32;
+    let flow = |p| player_flow(board, p) as f32 / 100.0;
+    let mobility = |p| {
+        let mut b = *board;
+        b.set_player(p);
+        b.legal_pawn_moves().len() as f32
+    };
+    [distance(1) - distance(0),
+     walls_left_difference(board),
+     resistance(1) - resistance(0),
+     flow(0) - flow(1),
+     mobility(0) - mobility(1)]
+}
+
+/// Weighted sum of the board's features, with weights that can be fitted
+/// to game results with `bot tune`.
+#[derive(Copy, Clone, Debug)]
+pub struct Linear {
+    pub weights :Weights,
+}
+
+impl Evaluator for Linear {
+    fn evaluate(&self, board :&Board) -> f32 {
+        if let Some(score) = winner_score(board) { return score; }
+        let w = self.weights.to_array();
+        w.iter().zip(features(board).iter()).map(|(w,f)| w*f).sum()
+    }
+}
+
+/// Effective resistance between the player's pawn and the goal row, found by
+/// solving the Laplacian system of the grid graph with the conjugate gradient method.
+/// The goal row is a single grounded node, leaving 72 unknown potentials.
+pub fn effective_resistance_cg(board :&Board, player :usize) -> f64 {
+    let goal_y = goal_row(player);
+    let pos = board.position(player);
+    if pos.y == goal_y { return 0.0; }
+
+    // Open neighbors of each cell that are not in the goal row.
+    // Steps into the goal row only count in the degree.
+    let mut degree = [0.0f64; 81];
+    let mut neighbors = [[0usize; 4]; 81];
+    let mut n_neighbors = [0usize; 81];
+    for cell in 0..81 {
+        let p = decode9(cell);
+        if p.y == goal_y { continue; }
+        for n in board.open_neighbors(p) {
+            degree[cell] += 1.0;
+            if n.y != goal_y {
+                neighbors[cell][n_neighbors[cell]] = encode9(n.x,n.y);
+                n_neighbors[cell] += 1;
+            }
+        }
+    }
+
+    let laplacian = |x :&[f64;81], y :&mut [f64;81]| {
+        for cell in 0..81 {
+            y[cell] = degree[cell] * x[cell] -
+                neighbors[cell][..n_neighbors[cell]].iter().map(|n| x[*n]).sum::<f64>();
+        }
+    };
+    let dot = |a :&[f64;81], b :&[f64;81]| a.iter().zip(b.iter()).map(|(a,b)| a*b).sum::<f64>();
+
+    // Unit current into the pawn's cell. Cells that are cut off from the pawn
+    // keep zero residual and potential, so the singular parts of the system
+    // do not matter.
+    let mut x = [0.0f64; 81];
+    let mut r = [0.0f64; 81];
+    r[encode9(pos.x,pos.y)] = 1.0;
+    let mut p = r;
+    let mut ap = [0.0f64; 81];
+    let mut rs = dot(&r,&r);
+    for _ in 0..200 {
+        laplacian(&p, &mut ap);
+        let alpha = rs / dot(&p,&ap);
+        for i in 0..81 {
+            x[i] += alpha * p[i];
+            r[i] -= alpha * ap[i];
+        }
+        let rs_new = dot(&r,&r);
+        if rs_new < 1e-20 { break; }
+        for i in 0..81 {
+            p[i] = r[i] + (rs_new / rs) * p[i];
+        }
+        rs = rs_new;
+    }
+    x[encode9(pos.x,pos.y)]
+}
+
+#[test]
+fn test_weights_file_format() {
+    let weights = Weights { path: 1.5, walls: -0.25, resistance: 0.0, flow: 2.0, mobility: 0.125 };
+    assert_eq!(Ok(weights), weights.to_string().parse());
+    assert_eq!(Ok(Weights { path: 2.0, walls: 0.0, resistance: 0.0, flow: 0.0, mobility: 0.0 }),
+               "# comment\n\npath = 2\n".parse());
+    assert!("path 2".parse::<Weights>().is_err());
+    assert!("speed = 2".parse::<Weights>().is_err());
+}
+
+#[test]
+fn test_resistance_solvers_agree() {
+    let mut board :Board = Default::default();
+    let check = |board :&Board| {
+        for player in 0..2 {
+            let lsqr = effective_resistance(board, player);
+            let cg = effective_resistance_cg(board, player);
+            assert!((lsqr - cg).abs() < 1e-6, "lsqr {} cg {} on {:?}", lsqr, cg, board);
+        }
+    };
+    check(&board);
+
+    // Walls that leave a narrow passage, and an enclosed region without pawns.
+    for x in 1..=6 {
+        if x % 2 == 1 { board.add_wall(Orientation::Horizontal, Position { x, y: 1 }); }
+    }
+    board.add_wall(Orientation::Vertical, Position { x: 8, y: 4 });
+    board.add_wall(Orientation::Vertical, Position { x: 8, y: 6 });
+    board.add_wall(Orientation::Horizontal, Position { x: 8, y: 3 });
+    board.add_wall(Orientation::Horizontal, Position { x: 8, y: 7 });
+    board.set_position(0, Position { x: 5, y: 1 });
+    check(&board);
+}
+
+#[test]
+fn test_evaluators() {
+    let mut board :Board = Default::default();
+    let path = PathDifference { wall_weight: 0.5 };
+    let flow = Flow { wall_weight: 0.0 };
+    let resistance = Resistance { solver: Solver::ConjugateGradient, wall_weight: 0.0 };
+
+    // The start position is symmetric.
+    assert_eq!(0.0, path.evaluate(&board));
+    assert_eq!(0.0, flow.evaluate(&board));
+    assert!(resistance.evaluate(&board).abs() < 1e-6);
+
+    // Player 1 (index 0) is ahead, with one wall less.
+    board.set_position(0, Position { x: 5, y: 4 });
+    board.walls_left[0] -= 1;
+    assert_eq!(3.0 - 0.5, path.evaluate(&board));
+    assert!(flow.evaluate(&board) > 0.0);
+    assert!(resistance.evaluate(&board) > 0.0);
+
+    let weighted = Weighted(vec![(2.0, Arc::new(path)), (1.0, Arc::new(resistance))]);
+    let expected = 2.0 * path.evaluate(&board) + resistance.evaluate(&board);
+    assert!((weighted.evaluate(&board) - expected).abs() < 1e-6);
+
+    board.set_position(0, Position { x: 5, y: 9 });
+    assert_eq!(std::f32::INFINITY, weighted.evaluate(&board));
+    assert_eq!(std::f32::INFINITY, default_evaluator().evaluate(&board));
+}
@@ -1,11 +1,57 @@
 mod minimax;
 mod bot;
+mod tt;
+mod ordering;
+mod mcts;
+mod eval;
+mod tune;
+mod endgame;
+mod book;
+mod options;
+mod engine;
 
-use model::*;
+fn init_logger(level :Option<&str>) {
+    use env_logger::Env;
+    match level {
+        // An explicit level overrides RUST_LOG.
+        Some(level) => env_logger::Builder::new().parse_filters(level).init(),
+        None => env_logger::from_env(Env::default().default_filter_or("info")).init(),
+    }
+}
+
+fn exit_with_error(e :String) -> ! {
+    eprintln!("Error: {}", e);
+    std::process::exit(1);
+}
 
 fn main() {
-    use env_logger::Env;
-    env_logger::from_env(Env::default().default_filter_or("trace")).init();
+    let args :Vec<String> = std::env::args().collect();
+    let command :Option<fn(&[String]) -> Result<(),String>> = match args.get(1).map(|a| a.as_str()) {
+        Some("perft") => Some(bot::perft_command),
+        Some("tune") => Some(bot::tune_command),
+        Some("bench") => Some(bot::bench_command),
+        Some("book") => Some(bot::book_command),
+        _ => None,
+    };
+    if let Some(command) = command {
+        init_logger(None);
+        if let Err(e) = command(&args[2..]) { exit_with_error(e); }
+        return;
+    }
 
-    bot::stdin_bot(minimax::HeuristicBot::new(Default::default()));
+    let options = options::Options::parse(&args[1..]).unwrap_or_else(|e| {
+        eprintln!("{}", options::USAGE);
+        exit_with_error(e)
+    });
+    if options.help {
+        println!("{}", options::USAGE);
+        return;
+    }
+    init_logger(options.log_level.as_deref());
+    let player = options.player().unwrap_or_else(|e| exit_with_error(e));
+    if options.legacy {
+        bot::stdin_bot(player);
+    } else {
+        engine::stdin_engine(options, player);
+    }
 }
@@ -0,0 +1,398 @@
+use std::time::{Duration, Instant};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use model::*;
+use log::*;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
+use crate::minimax::time_budget;
+use crate::eval::*;
+use crate::engine::EnginePlayer;
+
+/// How the result of a position is estimated after expanding a node.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Playout {
+    /// Uniformly random legal moves.
+    Random,
+    /// Move the pawn along its shortest path with the given probability,
+    /// otherwise make a random legal move.
+    ShortestPath(f64),
+    /// Play a number of moves as in `ShortestPath(0.5)` and then
+    /// estimate the result with the configured evaluator.
+    HeuristicCutoff(usize),
+}
+
+/// Parse a playout as `random`, `shortest:<probability>` or `cutoff:<moves>`.
+impl std::str::FromStr for Playout {
+    type Err = String;
+    fn from_str(s :&str) -> Result<Playout,String> {
+        let (kind, arg) = match s.find(':') {
+            Some(i) => (&s[..i], Some(&s[i+1..])),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("random", None) => Ok(Playout::Random),
+            ("shortest", Some(p)) => match p.parse::<f64>() {
+                Ok(p) if (0.0..=1.0).contains(&p) => Ok(Playout::ShortestPath(p)),
+                _ => Err(format!("Invalid shortest path probability {:?}", p)),
+            },
+            ("cutoff", Some(n)) => n.parse().map(Playout::HeuristicCutoff)
+                .map_err(|e| format!("Invalid cutoff length {:?}: {}", n, e)),
+            _ => Err(format!("Unknown playout {:?}, expected random, shortest:<p> or cutoff:<n>", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MctsConfig {
+    pub playout :Playout,
+    /// UCT exploration constant.
+    pub exploration :f64,
+    /// Time limit for each move.
+    pub move_time :Option<Duration>,
+    /// Number of iterations for each move.
+    pub iterations :Option<u32>,
+    /// Playouts longer than this are stopped and estimated from the path lengths.
+    pub max_playout_length :usize,
+    pub seed :u64,
+    /// Evaluator for `Playout::HeuristicCutoff`.
+    pub evaluator :Arc<dyn Evaluator>,
+}
+
+impl Default for MctsConfig {
+    fn default() -> MctsConfig {
+        MctsConfig {
+            playout: Playout::ShortestPath(0.7),
+            exploration: std::f64::consts::SQRT_2,
+            move_time: Some(Duration::from_secs(5)),
+            iterations: None,
+            max_playout_length: 200,
+            seed: 0,
+            evaluator: default_evaluator(),
+        }
+    }
+}
+
+struct Node {
+    board :Board,
+    /// Move leading to this node.
+    mv :Option<Move>,
+    children :Vec<usize>,
+    /// Number of moves expanded into children, in the order of `Board::legal_moves`.
+    /// The moves are generated again for each expansion instead of being stored.
+    expanded :u8,
+    /// All legal moves have been expanded, or the game is over.
+    fully_expanded :bool,
+    visits :u32,
+    /// Sum of results for the player who made the move leading to this node.
+    wins :f64,
+}
+
+impl Node {
+    fn new(board :Board, mv :Option<Move>) -> Node {
+        Node {
+            board,
+            mv,
+            children: Vec::new(),
+            expanded: 0,
+            fully_expanded: false,
+            visits: 0,
+            wins: 0.0,
+        }
+    }
+}
+
+/// Monte Carlo Tree Search player using UCT selection.
+/// The tree is kept between moves, so that the search continues from
+/// the subtree of the opponent's reply.
+pub struct MctsPlayer {
+    board :Board,
+    config :MctsConfig,
+    clock :Option<Clock>,
+    rng :StdRng,
+    /// Tree nodes, with the root at index 0.
+    nodes :Vec<Node>,
+    search_info :Option<SearchInfo>,
+    /// Set by the engine to end the search early (see `EnginePlayer::set_stop`).
+    stop :Option<Arc<AtomicBool>>,
+}
+
+impl MctsPlayer {
+    pub fn with_config(config :MctsConfig) -> MctsPlayer {
+        let board :Board = Default::default();
+        MctsPlayer {
+            board,
+            rng: StdRng::seed_from_u64(config.seed),
+            config,
+            clock: None,
+            nodes: vec![Node::new(board, None)],
+            search_info: None,
+            stop: None,
+        }
+    }
+
+    /// Make the child reached by the move the new root,
+    /// keeping its subtree. Starts a new tree if the move was not expanded.
+    fn advance_root(&mut self, mv :Move) {
+        let child = self.nodes[0].children.iter().cloned()
+            .find(|c| self.nodes[*c].mv == Some(mv));
+        let child = match child {
+            Some(c) => c,
+            None => {
+                self.nodes = vec![Node::new(self.board, None)];
+                return;
+            }
+        };
+
+        let mut old_nodes = std::mem::take(&mut self.nodes).into_iter()
+            .map(Some).collect::<Vec<_>>();
+        let mut queue :Vec<(usize,Option<usize>)> = vec![(child, None)];
+        while let Some((old, parent)) = queue.pop() {
+            let mut node = old_nodes[old].take().unwrap();
+            let children = std::mem::take(&mut node.children);
+            let idx = self.nodes.len();
+            self.nodes.push(node);
+            if let Some(parent) = parent { self.nodes[parent].children.push(idx); }
+            queue.extend(children.into_iter().map(|c| (c, Some(idx))));
+        }
+        self.nodes[0].mv = None;
+    }
+
+    /// One iteration: select a leaf, expand it, do a playout and update the
+    /// statistics along the path.
+    fn iterate(&mut self) {
+        let mut path = vec![0];
+        let mut node = 0;
+        while self.nodes[node].fully_expanded && !self.nodes[node].children.is_empty() {
+            node = self.select_child(node);
+            path.push(node);
+        }
+
+        if let Some(mv) = self.next_untried(node) {
+            let mut board = self.nodes[node].board;
+            board.integrate(mv).unwrap();
+            let child = self.nodes.len();
+            self.nodes.push(Node::new(board, Some(mv)));
+            self.nodes[node].children.push(child);
+            node = child;
+            path.push(node);
+        }
+
+        let result = self.playout(self.nodes[node].board);
+        for n in path {
+            let node = &mut self.nodes[n];
+            let mover = 1 - node.board.player();
+            node.visits += 1;
+            node.wins += if mover == 0 { result } else { 1.0 - result };
+        }
+    }
+
+    /// The next move to expand from the node, if there are any left.
+    fn next_untried(&mut self, node :usize) -> Option<Move> {
+        let node = &mut self.nodes[node];
+        if node.fully_expanded { return None; }
+        let moves = if node.board.get_winner().is_none() { node.board.legal_moves() } else { MoveList::new() };
+        let mv = moves.get(node.expanded as usize).cloned();
+        node.expanded += 1;
+        node.fully_expanded = node.expanded as usize >= moves.len();
+        mv
+    }
+
+    fn select_child(&self, node :usize) -> usize {
+        let log_visits = (self.nodes[node].visits as f64).ln();
+        let uct = |c :usize| {
+            let child = &self.nodes[c];
+            child.wins / child.visits as f64 +
+                self.config.exploration * (log_visits / child.visits as f64).sqrt()
+        };
+        *self.nodes[node].children.iter()
+            .max_by(|a,b| uct(**a).partial_cmp(&uct(**b)).unwrap()).unwrap()
+    }
+
+    /// Play out the game from the board and return the result for player 0,
+    /// between 0 (loss) and 1 (win).
+    fn playout(&mut self, mut board :Board) -> f64 {
+        let (shortest_path_prob, max_length, heuristic) = match self.config.playout {
+            Playout::Random => (0.0, self.config.max_playout_length, false),
+            Playout::ShortestPath(p) => (p, self.config.max_playout_length, false),
+            Playout::HeuristicCutoff(n) => (0.5, n, true),
+        };
+
+        for _ in 0..max_length {
+            if board.get_winner().is_some() { break; }
+            let mv = if self.rng.gen_bool(shortest_path_prob) {
+                shortest_path_move(&board)
+            } else {
+                None
+            };
+            let mv = match mv {
+                Some(mv) => mv,
+                None => {
+                    let moves = board.legal_moves();
+                    if moves.is_empty() { break; }
+                    moves[self.rng.gen_range(0, moves.len())]
+                }
+            };
+            board.integrate(mv).unwrap();
+        }
+
+        match board.get_winner() {
+            Some(winner) => if winner == 0 { 1.0 } else { 0.0 },
+            None if heuristic => sigmoid(self.config.evaluator.evaluate(&board) as f64),
+            None => estimate(&board),
+        }
+    }
+}
+
+/// The legal pawn move that gets closest to the goal row.
+fn shortest_path_move(board :&Board) -> Option<Move> {
+    let distances = board.distance_map(board.player());
+    board.legal_pawn_moves().iter().cloned().min_by_key(|mv| match mv {
+        Move::PawnTo(p) => distances[encode9(p.x,p.y)],
+        _ => UNREACHABLE,
+    })
+}
+
+/// Estimated result for player 0 from the difference in path lengths.
+fn estimate(board :&Board) -> f64 {
+    let distance = |p| board.distance_to_goal(p).unwrap_or(UNREACHABLE) as f64;
+    // Having the move is worth about half a step.
+    let tempo = if board.player() == 0 { 0.5 } else { -0.5 };
+    sigmoid(distance(1) - distance(0) + tempo)
+}
+
+fn sigmoid(x :f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+impl EnginePlayer for MctsPlayer {
+    fn set_clock(&mut self, clock :Clock) {
+        self.clock = Some(clock);
+    }
+
+    fn set_position(&mut self, board :Board) {
+        self.board = board;
+        self.nodes = vec![Node::new(self.board, None)];
+    }
+
+    fn set_stop(&mut self, stop :Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
+
+    fn search_info(&self) -> Option<SearchInfo> {
+        self.search_info.clone()
+    }
+}
+
+impl Player for MctsPlayer {
+    fn reset(&mut self) {
+        self.board = Default::default();
+        self.clock = None;
+        self.nodes = vec![Node::new(self.board, None)];
+    }
+
+    fn mv(&mut self, mv :Option<Move>) -> Move {
+        let start = Instant::now();
+        if let Some(mv) = mv {
+            self.board.integrate(mv).unwrap();
+            self.advance_root(mv);
+        }
+        let reused = self.nodes[0].visits;
+
+        let deadline = time_budget(self.clock, self.config.move_time).map(|b| start + b);
+        let mut iterations = 0;
+        loop {
+            self.iterate();
+            iterations += 1;
+            if self.config.iterations.map(|n| iterations >= n).unwrap_or(false) { break; }
+            if deadline.map(|d| Instant::now() >= d).unwrap_or(false) { break; }
+            if self.stop.as_ref().map(|s| s.load(atomic::Ordering::Relaxed)).unwrap_or(false) { break; }
+            if self.config.iterations.is_none() && deadline.is_none() { break; }
+        }
+
+        // A move that wins at once is played even if other moves have more visits.
+        let me = self.board.player();
+        let children = &self.nodes[0].children;
+        let winning = children.iter().cloned().find(|c| self.nodes[*c].board.get_winner() == Some(me));
+        let best = match winning.or_else(|| children.iter().cloned().max_by_key(|c| self.nodes[*c].visits)) {
+            Some(best) => best,
+            None => {
+                // Nothing to search: the game is over, or there are no legal moves.
+                // There is no move to make, so the pawn stays where it is.
+                warn!("MctsPlayer asked to move without any legal moves");
+                self.search_info = None;
+                return Move::PawnTo(self.board.position(self.board.player()));
+            },
+        };
+        let best_move = self.nodes[best].mv.unwrap();
+        info!("MctsPlayer: {} iterations ({} reused), {} nodes, move {} visits {} win rate {:.3}, time {:.3}s",
+              iterations, reused, self.nodes.len(), printer(&best_move), self.nodes[best].visits,
+              self.nodes[best].wins / self.nodes[best].visits as f64, start.elapsed().as_secs_f64());
+
+        self.search_info = Some(SearchInfo {
+            nodes: Some(iterations as u64),
+            time: Some(start.elapsed()),
+            pv: vec![best_move],
+            .. Default::default()
+        });
+
+        self.board.integrate(best_move).unwrap();
+        self.advance_root(best_move);
+        best_move
+    }
+}
+
+#[test]
+fn test_mcts_winning_move() {
+    // Player 1 can win by moving to a9, which all playout kinds should find.
+    for playout in &[Playout::Random, Playout::ShortestPath(0.7), Playout::HeuristicCutoff(2)] {
+        let config = MctsConfig { playout: *playout, iterations: Some(300), move_time: None, .. Default::default() };
+        let mut player = MctsPlayer::with_config(config);
+        player.board.set_position(0, Position { x: 1, y: 8 });
+        player.board.walls_left = [0,0];
+        player.nodes = vec![Node::new(player.board, None)];
+        assert_eq!(Move::PawnTo(Position { x: 1, y: 9 }), player.mv(None), "playout {:?}", playout);
+    }
+}
+
+#[test]
+fn test_mcts_finished_game() {
+    // Player 1 has reached the goal row, so there is nothing to search.
+    let config = MctsConfig { iterations: Some(10), move_time: None, .. Default::default() };
+    let mut player = MctsPlayer::with_config(config);
+    let mut board :Board = Default::default();
+    board.set_position(0, Position { x: 5, y: 9 });
+    board.set_player(1);
+    player.set_position(board);
+    assert!(board.get_winner().is_some());
+    assert_eq!(Move::PawnTo(board.position(1)), player.mv(None));
+    assert!(player.nodes[0].children.is_empty());
+}
+
+#[test]
+fn test_mcts_tree_reuse() {
+    let config = MctsConfig { iterations: Some(500), move_time: None, .. Default::default() };
+    let mut player = MctsPlayer::with_config(config);
+    let board :Board = Default::default();
+    let mv = player.mv(None);
+    assert!(board.is_valid_move(&mv));
+
+    // The root is now the position after our move, and keeps its statistics.
+    assert!(player.nodes[0].visits > 0);
+    assert_eq!(player.board, player.nodes[0].board);
+    for c in &player.nodes[0].children {
+        let child = &player.nodes[*c];
+        let mut b = player.board;
+        b.integrate(child.mv.unwrap()).unwrap();
+        assert_eq!(b, child.board);
+    }
+
+    // Following the most visited reply keeps that subtree.
+    let reply = player.nodes[0].children.iter().cloned()
+        .max_by_key(|c| player.nodes[*c].visits).unwrap();
+    let (reply_move, reply_visits) = (player.nodes[reply].mv.unwrap(), player.nodes[reply].visits);
+    player.board.integrate(reply_move).unwrap();
+    player.advance_root(reply_move);
+    assert_eq!(reply_visits, player.nodes[0].visits);
+    assert_eq!(player.board, player.nodes[0].board);
+}
@@ -1,33 +1,68 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
+use std::time::{Duration, Instant};
 use arrayvec::ArrayVec;
 use model::*;
 use log::*;
+use crate::tt::*;
+use crate::ordering::*;
+use crate::eval::*;
+use crate::endgame::*;
+use crate::engine::EnginePlayer;
 
 
 pub struct HeuristicBot {
     board :Board,
+    evaluator :Arc<dyn Evaluator>,
+    endgame :Endgame,
+    search_info :Option<SearchInfo>,
 }
 
 impl HeuristicBot {
-    pub fn new(board :Board) -> HeuristicBot {
-        HeuristicBot { board }
+    pub fn with_evaluator(board :Board, evaluator :Arc<dyn Evaluator>) -> HeuristicBot {
+        HeuristicBot { board, evaluator, endgame: Default::default(), search_info: None }
+    }
+}
+
+impl EnginePlayer for HeuristicBot {
+    /// The heuristic bot only looks one move ahead, so it takes no time to budget.
+    fn set_clock(&mut self, _clock :Clock) {}
+
+    fn set_position(&mut self, board :Board) {
+        self.board = board;
+    }
+
+    /// Moves are chosen without a search, so there is nothing to stop.
+    fn set_stop(&mut self, _stop :Arc<AtomicBool>) {}
+
+    fn search_info(&self) -> Option<SearchInfo> {
+        self.search_info.clone()
     }
 }
 
 impl Player for HeuristicBot {
-    fn reset (&mut self) {}
+    fn reset(&mut self) {
+        self.board = Default::default();
+    }
 
     fn mv(&mut self, mv :Option<Move>) -> Move {
         if let Some(mv) = mv { self.board.integrate(mv).unwrap(); }
-        let i_am_player = self.board.player;
+        let i_am_player = self.board.player();
         info!("HeuristicPlayer Received move {:?} as player {}", mv, i_am_player);
+        if let Some(mv) = self.endgame.best_move(&self.board) {
+            info!("HeuristicPlayer race move {}", printer(&mv));
+            self.search_info = None;
+            self.board.integrate(mv).unwrap();
+            return mv;
+        }
         let mut  moves :Vec<(Move,f32)>= Vec::new();
         //let (mut score, mut mv) = (-std::f32::INFINITY, None);
         for_each_move(&self.board, &mut |m| {
-            let mut new_board = self.board.clone();
+            let mut new_board = self.board;
             new_board.integrate(m).unwrap();
             //debug!("Evaluating heuristic for {:?}", new_board);
-            let new_score = (1.0-2.0*(i_am_player as f32))*board_heuristic(&new_board); // board heuristic always
+            let new_score = (1.0-2.0*(i_am_player as f32))*self.evaluator.evaluate(&new_board); // evaluation always
             //debug!("  Score: {}", new_score);
             //if new_score >= score {
             //    score = new_score;
@@ -44,6 +79,10 @@ impl Player for HeuristicBot {
             debug!("move {:?} score {}", mv, sc);
         }
         let mv = moves.get(0).map(|x| x.0);
+        self.search_info = moves.get(0).map(|(mv,score)| SearchInfo {
+            depth: Some(1), score: Some(*score), nodes: Some(moves.len() as u64), pv: vec![*mv],
+            .. Default::default()
+        });
 
         self.board.integrate(mv.unwrap()).unwrap();
         mv.unwrap()
@@ -51,33 +90,275 @@ impl Player for HeuristicBot {
     }
 }
 
+/// Default transposition table size in megabytes.
+pub const DEFAULT_TABLE_SIZE :usize = 64;
+
 pub struct MinimaxPlayer {
     board :Board,
-    memory :HashMap<Board, BoardInfo>,
+    memory :TranspositionTable,
+    /// Tables for the extra search threads.
+    helper_tables :Vec<TranspositionTable>,
+    config :MinimaxConfig,
+    clock :Option<Clock>,
+    /// Solver for positions decided by the pawn race.
+    endgame :Endgame,
+    search_info :Option<SearchInfo>,
+    /// Set by the engine to end the search early (see `EnginePlayer::set_stop`).
+    stop :Option<Arc<AtomicBool>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MinimaxConfig {
+    /// Transposition table size in megabytes.
+    pub table_size :usize,
+    /// Maximum search depth in plies.
+    pub max_depth :u16,
+    /// Time limit for each move.
+    pub move_time :Option<Duration>,
+    /// Total time for the game. Used when the player is not given
+    /// a clock by the referee (see `EnginePlayer::set_clock`).
+    pub game_time :Option<Duration>,
+    /// Print a table of search statistics after each move.
+    pub print_stats :bool,
+    /// Sort moves to search the most promising moves first (see `MoveOrdering`).
+    pub move_ordering :bool,
+    /// Only search walls that are close to the pawns' shortest paths or to other walls.
+    /// Faster, but may miss good wall moves.
+    pub prune_walls :bool,
+    /// Number of search threads. The table size is divided between the threads.
+    pub threads :usize,
+    pub evaluator :Arc<dyn Evaluator>,
+}
+
+impl Default for MinimaxConfig {
+    fn default() -> MinimaxConfig {
+        MinimaxConfig {
+            table_size: DEFAULT_TABLE_SIZE,
+            max_depth: 32,
+            move_time: Some(Duration::from_secs(5)),
+            game_time: None,
+            print_stats: false,
+            move_ordering: true,
+            prune_walls: false,
+            threads: 1,
+            evaluator: default_evaluator(),
+        }
+    }
 }
 
 impl MinimaxPlayer {
-    pub fn new() -> MinimaxPlayer {
+    pub fn with_config(config :MinimaxConfig) -> MinimaxPlayer {
         MinimaxPlayer {
             board: Default::default(),
-            memory: Default::default(), // TODO could have precomputed moves here?
+            memory: TranspositionTable::new(config.table_size / config.threads.max(1)),
+            helper_tables: (1..config.threads)
+                .map(|_| TranspositionTable::new(config.table_size / config.threads)).collect(),
+            clock: config.game_time.map(|t| Clock { remaining: t, increment: Duration::from_secs(0), move_time: None }),
+            endgame: Default::default(),
+            search_info: None,
+            stop: None,
+            config,
         }
     }
+
+    /// Time to spend on the next move, or None for no time limit.
+    fn time_budget(&self) -> Option<Duration> {
+        time_budget(self.clock, self.config.move_time)
+    }
+}
+
+/// Time to spend on the next move given the player's clock and a time limit
+/// for each move, or None for no time limit.
+pub fn time_budget(clock :Option<Clock>, move_time :Option<Duration>) -> Option<Duration> {
+    // Plan for around 20 more moves, and keep some of the increment as margin.
+    // With a fixed time per move, keep a margin for the communication with the referee.
+    let from_clock = clock.map(|c| match c.move_time {
+        Some(t) => t.min(c.remaining) * 9 / 10,
+        None => (c.remaining / 20 + c.increment * 3 / 4).min(c.remaining / 2),
+    });
+    match (from_clock, move_time) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl EnginePlayer for MinimaxPlayer {
+    fn set_clock(&mut self, clock :Clock) {
+        self.clock = Some(clock);
+    }
+
+    fn set_position(&mut self, board :Board) {
+        self.board = board;
+    }
+
+    fn set_stop(&mut self, stop :Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
+
+    fn search_info(&self) -> Option<SearchInfo> {
+        self.search_info.clone()
+    }
 }
 
 impl Player for MinimaxPlayer {
 
-    fn reset(&mut self) {}  // Keep the table until next game.
+    fn reset(&mut self) {  // Keep the table until next game.
+        self.board = Default::default();
+        self.clock = self.config.game_time.map(|t| Clock { remaining: t, increment: Duration::from_secs(0), move_time: None });
+    }
 
     fn mv(&mut self, mv :Option<Move>) -> Move {
-        let depth = 5;
+        let start = Instant::now();
         if let Some(mv) = mv { self.board.integrate(mv).unwrap(); }
-        let best_move = negamax_root(&mut self.memory, &self.board, depth);
+        let best_move = match self.endgame.best_move(&self.board) {
+            Some(mv) => {
+                info!("MinimaxPlayer race move {}", printer(&mv));
+                self.search_info = None;
+                mv
+            },
+            None => {
+                let budget = self.time_budget();
+                debug!("MinimaxPlayer time budget {:?}", budget);
+                let mut search = Search::new(&mut self.memory, budget.map(|b| start + b));
+                search.move_ordering = self.config.move_ordering;
+                search.prune_walls = self.config.prune_walls;
+                search.evaluator = self.config.evaluator.clone();
+                search.stop = self.stop.clone();
+                search.helper_tables = self.helper_tables.iter_mut().collect();
+                let result = iterative_deepening(&mut search, &self.board, self.config.max_depth)
+                    .expect("MinimaxPlayer has no legal moves");
+                info!("MinimaxPlayer search: {}", result);
+                if self.config.print_stats { print_search_result(&result); }
+                debug!("Transposition table ({} entries): {:?}", self.memory.len(), self.memory.stats);
+                self.search_info = Some(result.info());
+                result.best_move
+            },
+        };
         self.board.integrate(best_move).unwrap();
+
+        // Count down our own clock, in case the referee does not send a new one.
+        if let Some(clock) = &mut self.clock {
+            clock.remaining = (clock.remaining + clock.increment)
+                .checked_sub(start.elapsed()).unwrap_or(Duration::from_secs(0));
+        }
         best_move
     }
 }
 
+#[test]
+fn test_timed_search() {
+    let config = MinimaxConfig { move_time: Some(Duration::from_millis(200)), .. Default::default() };
+    let mut player = MinimaxPlayer::with_config(config);
+    let board :Board = Default::default();
+    let start = Instant::now();
+    let mv = player.mv(None);
+    assert!(board.is_valid_move(&mv));
+    println!("timed search took {:?}", start.elapsed());
+
+    // Without any time limit, the search stops at the maximum depth.
+    let config = MinimaxConfig { move_time: None, max_depth: 1, .. Default::default() };
+    let mut player = MinimaxPlayer::with_config(config);
+    assert!(player.time_budget().is_none());
+    let mv = player.mv(None);
+    assert!(board.is_valid_move(&mv));
+
+    // The budget is a fraction of the remaining game time.
+    player.set_clock(Clock { remaining: Duration::from_secs(60), increment: Duration::from_secs(0), move_time: None });
+    assert_eq!(Some(Duration::from_secs(3)), player.time_budget());
+    // Most of a fixed time per move is used.
+    player.set_clock(Clock { remaining: Duration::from_secs(10), increment: Duration::from_secs(0),
+                             move_time: Some(Duration::from_secs(10)) });
+    assert_eq!(Some(Duration::from_secs(9)), player.time_budget());
+}
+
+#[test]
+fn test_search_result() {
+    // Without walls, the search is fast and reaches the maximum depth.
+    let mut board :Board = Default::default();
+    board.walls_left = [0,0];
+    let mut table = TranspositionTable::new(1);
+    let result = iterative_deepening(&mut Search::new(&mut table, None), &board, 4).unwrap();
+    println!("{}", result);
+    assert_eq!(4, result.depth);
+    assert_eq!(result.best_move, result.pv[0]);
+    assert!(result.nodes > 4 && result.cutoffs > 0);
+
+    // The principal variation is a sequence of legal moves.
+    let mut b = board;
+    for mv in &result.pv { b.integrate(*mv).unwrap(); }
+
+    // Winning in one move ends the search early.
+    board.set_position(0, Position { x: 1, y: 8 });
+    let result = iterative_deepening(&mut Search::new(&mut table, None), &board, 4).unwrap();
+    assert_eq!(Move::PawnTo(Position { x: 1, y: 9 }), result.best_move);
+    assert_eq!(std::f32::INFINITY, result.score);
+    assert_eq!(1, result.depth);
+    assert_eq!(1, result.pv.len());
+
+    // Out of time before depth 1 is completed, the first ordered move is played.
+    let board :Board = Default::default();
+    let result = iterative_deepening(&mut Search::new(&mut table, Some(Instant::now())), &board, 4).unwrap();
+    assert_eq!((0, Move::PawnTo(Position { x: 5, y: 2 })), (result.depth, result.best_move));
+
+    // No legal moves: player 1 is in the corner behind player 2, without walls.
+    let mut board :Board = Default::default();
+    board.walls_left = [0,0];
+    board.add_wall(Orientation::Horizontal, Position { x: 1, y: 1 });
+    board.add_wall(Orientation::Vertical, Position { x: 2, y: 1 });
+    board.set_position(0, Position { x: 1, y: 1 });
+    board.set_position(1, Position { x: 2, y: 1 });
+    assert!(board.legal_moves().is_empty());
+    assert!(iterative_deepening(&mut Search::new(&mut table, None), &board, 4).is_none());
+}
+
+#[test]
+fn test_move_ordering_nodes() {
+    // Move ordering finds the same score at a fixed depth with fewer nodes.
+    let search = |board :&Board, move_ordering| {
+        let mut table = TranspositionTable::new(1);
+        let mut search = Search::new(&mut table, None);
+        search.move_ordering = move_ordering;
+        iterative_deepening(&mut search, board, 2).unwrap()
+    };
+    let mut board :Board = Default::default();
+    for mv in &["e2", "e8"] { board.integrate(parse(mv).unwrap()).unwrap(); }
+    let unordered = search(&board, false);
+    let ordered = search(&board, true);
+    println!("unordered: {}\nordered: {}", unordered, ordered);
+    assert_eq!(unordered.score, ordered.score);
+    assert!(ordered.nodes * 4 < unordered.nodes * 3);
+}
+
+#[test]
+fn test_parallel_search() {
+    let search = |board :&Board, depth, threads :usize, prune_walls| {
+        let mut table = TranspositionTable::new(1);
+        let mut helper_tables = (1..threads).map(|_| TranspositionTable::new(1)).collect::<Vec<_>>();
+        let mut search = Search::new(&mut table, None);
+        search.move_ordering = true;
+        search.prune_walls = prune_walls;
+        search.helper_tables = helper_tables.iter_mut().collect();
+        iterative_deepening(&mut search, board, depth).unwrap()
+    };
+
+    let mut board :Board = Default::default();
+    board.walls_left = [0,0];
+    board.set_position(1, Position { x: 4, y: 6 });
+    let single = search(&board, 3, 1, false);
+    let parallel = search(&board, 3, 4, false);
+    assert!(board.is_valid_move(&parallel.best_move));
+    assert_eq!(single.score, parallel.score);
+
+    // With walls, using the pruning to keep the test fast.
+    let mut board :Board = Default::default();
+    board.walls_left = [1,1];
+    board.add_wall(Orientation::Horizontal, Position { x: 4, y: 3 });
+    let single = search(&board, 2, 1, true);
+    let parallel = search(&board, 2, 3, true);
+    assert!(board.is_valid_move(&parallel.best_move));
+    assert_eq!(single.score, parallel.score);
+}
+
 pub fn for_each_move(board :&Board, f :&mut dyn FnMut(Move) -> bool) {
     if !for_each_pawn_move(board, f) { return; }
     if !for_each_wall_move(board, f) { return; }
@@ -85,39 +366,9 @@ pub fn for_each_move(board :&Board, f :&mut dyn FnMut(Move) -> bool) {
 
 /// available pawn moves
 pub fn for_each_pawn_move(board :&Board,  f :&mut dyn FnMut(Move)->bool) -> bool {
-    let current_pos = board.positions[board.player];
-    let other_pos   = board.positions[1 - board.player];
-
-    let candidates = 
-        vec! [ 
-        Position { x: current_pos.x + 1, y: current_pos.y },
-        Position { x: current_pos.x - 1, y: current_pos.y },
-        Position { x: current_pos.x , y: current_pos.y + 1},
-        Position { x: current_pos.x , y: current_pos.y - 1},
-        ];
-
-    for candidate in candidates {
-        if in_bounds1to9(&candidate) && !board.wall_between(&current_pos, &candidate) {
-            if candidate != other_pos {
-                let cont = f(Move::PawnTo(candidate));
-                if !cont { return false; }
-            } else {
-                let other_side = Position { x: other_pos.x + (other_pos.x - current_pos.x),
-                                            y: other_pos.y + (other_pos.y - current_pos.y)};
-                if board.wall_between(&&candidate, &other_side) {
-                    // There is a back wall after the other player, diagonal jumps allowed
-
-                    for sign in vec![-1,1] {
-                        let diag = Position { x: other_pos.x + sign*(other_pos.y - current_pos.y),
-                                               y: other_pos.y + sign*(other_pos.x - current_pos.x)};
-                        if in_bounds1to9(&diag) && !board.wall_between(&diag, &other_pos) {
-                            let cont = f(Move::PawnTo(diag));
-                            if !cont { return false; }
-                        }
-                    }
-                }
-            }
-        }
+    for mv in &board.legal_pawn_moves() {
+        let cont = f(*mv);
+        if !cont { return false; }
     }
 
     return true; // continue outer loop 
@@ -125,15 +376,9 @@ pub fn for_each_pawn_move(board :&Board,  f :&mut dyn FnMut(Move)->bool) -> bool
 
 /// Available wall moves
 pub fn for_each_wall_move(board :&Board, f : &mut dyn FnMut(Move)->bool) -> bool{
-    for orientation in vec![Orientation::Horizontal, Orientation::Vertical] {
-        for x in 0..=8 {
-            for y in 0..=8 {
-                if board.can_add_wall(orientation, Position { x, y }) {
-                    let cont = f(Move::WallAt(orientation, Position { x,  y }));
-                    if !cont { return false; }
-                }
-            }
-        }
+    for mv in &board.legal_wall_moves() {
+        let cont = f(*mv);
+        if !cont { return false; }
     }
 
     return true; // continue outer loop
@@ -161,15 +406,14 @@ fn test_effective_resistance() {
 
     let player = 0;
     let mut board :Board = Default::default();
-    board.positions[player].y = 1;
-    board.positions[player].x = 5;
+    board.set_position(player, Position { x: 5, y: 1 });
 
-    board.walls.push((Orientation::Horizontal, Position { x: 1, y: 1 }));
-    board.walls.push((Orientation::Horizontal, Position { x: 2, y: 1 }));
-    board.walls.push((Orientation::Horizontal, Position { x: 3, y: 1 }));
-    board.walls.push((Orientation::Horizontal, Position { x: 4, y: 1 }));
-    board.walls.push((Orientation::Horizontal, Position { x: 5, y: 1 }));
-    board.walls.push((Orientation::Horizontal, Position { x: 6, y: 1 }));
+    board.add_wall(Orientation::Horizontal, Position { x: 1, y: 1 });
+    board.add_wall(Orientation::Horizontal, Position { x: 2, y: 1 });
+    board.add_wall(Orientation::Horizontal, Position { x: 3, y: 1 });
+    board.add_wall(Orientation::Horizontal, Position { x: 4, y: 1 });
+    board.add_wall(Orientation::Horizontal, Position { x: 5, y: 1 });
+    board.add_wall(Orientation::Horizontal, Position { x: 6, y: 1 });
 
     let x = effective_resistances(&board, player);
 
@@ -205,7 +449,7 @@ pub fn effective_resistance(board :&Board, player :usize) -> f64 {
         else { 1+ encode9(cell.x,cell.y) };
 
     let resistances = effective_resistances(board, player);
-    resistances[encode_lsqr(board.positions[player])]
+    resistances[encode_lsqr(board.position(player))]
 }
 
 pub fn effective_resistances(board :&Board, player :usize) -> Vec<f64> {
@@ -240,7 +484,7 @@ pub fn effective_resistances(board :&Board, player :usize) -> Vec<f64> {
 
     let mut rhs = vec![0.0;n_rows];
     rhs[0] = -1.0;
-    rhs[encode_lsqr(board.positions[player])] = 1.0;
+    rhs[encode_lsqr(board.position(player))] = 1.0;
 
     let aprod = |mode : lsqr::Product| {
         match mode {
@@ -293,7 +537,7 @@ pub fn effective_resistances(board :&Board, player :usize) -> Vec<f64> {
         condlim: 0.0, damp: 0.0, iterlim: 10000, rel_mat_err: 0.0, rel_rhs_err: 0.0
     };
 
-    let (sol,statistics)  = lsqr::lsqr(|msg| println!("{}", msg),
+    let (sol,statistics)  = lsqr::lsqr(|msg| trace!("{}", msg),
         n_rows, n_cols, params, aprod, &mut rhs);
 
     sol
@@ -324,6 +568,8 @@ pub fn board_heuristic(board :&Board) -> f32 {
     //  5. if the number of opponent's walls left is not enough
     //     to block your path, give a very high score on the difference
     //     between shortest path for each player.
+    //     (`Endgame::guaranteed_winner` proves this when the opponent has
+    //     few walls left)
     //
     //  6. irrelevant wall moves could maybe be eliminated by a heuristic.
     //     because there are some obvious bad choices, for example setting
@@ -377,7 +623,7 @@ pub fn for_each_adjacent_cell(board :&Board, pos :Position,mut f:impl FnMut(Posi
 pub fn player_flow(board :&Board, player :usize) -> u64 {
     // USE only encode9 positions inside this function with type isize
     //
-    let pos = board.positions[player];
+    let pos = board.position(player);
     debug!("player_flow, pos= {:?}", pos);
     let pos = encode9(pos.x, pos.y) as isize;
 
@@ -478,69 +724,332 @@ pub fn player_flow(board :&Board, player :usize) -> u64 {
     flow
 }
 
-#[repr(u16)]
-pub enum BoardFlag { Exact, LowerBound, UpperBound }
-pub struct BoardInfo {
-    pub value: f32,
-    pub depth: u16,
-    pub flag :BoardFlag,
-} // size should be 64bit
+/// State of a search for one move: the transposition table, the time limit,
+/// move ordering and statistics.
+pub struct Search<'a> {
+    pub table :&'a mut TranspositionTable,
+    deadline :Option<Instant>,
+    /// Flag set by another thread to end the search before the deadline.
+    pub stop :Option<Arc<AtomicBool>>,
+    aborted :bool,
+    ordering :MoveOrdering,
+    /// Sort moves before searching them.
+    pub move_ordering :bool,
+    /// Skip walls far from the pawns' paths and other walls.
+    pub prune_walls :bool,
+    pub evaluator :Arc<dyn Evaluator>,
+    /// Number of positions visited.
+    pub nodes :u64,
+    /// Number of positions found in the transposition table.
+    pub tt_hits :u64,
+    /// Number of beta cutoffs.
+    pub cutoffs :u64,
+    /// Transposition tables for extra threads. If there are any, the moves
+    /// at the root are split between threads, each with its own table.
+    pub helper_tables :Vec<&'a mut TranspositionTable>,
+}
 
-pub fn negamax_root(table :&mut HashMap<Board, BoardInfo>, board :&Board,
-                    depth: u16) -> Move {
+impl<'a> Search<'a> {
+    pub fn new(table :&'a mut TranspositionTable, deadline :Option<Instant>) -> Search<'a> {
+        Search {
+            table, deadline, stop: None, aborted: false,
+            ordering: MoveOrdering::new(), move_ordering: false, prune_walls: false,
+            evaluator: default_evaluator(),
+            nodes: 0, tt_hits: 0, cutoffs: 0,
+            helper_tables: Vec::new(),
+        }
+    }
 
-    let (mut score, mut mv) = (-std::f32::INFINITY, None);
-    for_each_move(&board, &mut |m| {
-        let mut new_board = board.clone();
-        new_board.integrate(m).unwrap();
-        let new_score = -negamax(table, &new_board, depth, -std::f32::INFINITY, std::f32::INFINITY);
-        if new_score >= score {
+    /// Check whether the search has run out of time or has been stopped.
+    fn out_of_time(&mut self) -> bool {
+        if !self.aborted {
+            if let Some(deadline) = self.deadline {
+                self.aborted = Instant::now() >= deadline;
+            }
+            if let Some(stop) = &self.stop {
+                self.aborted |= stop.load(atomic::Ordering::Relaxed);
+            }
+        }
+        self.aborted
+    }
+}
+
+/// Result of a search, with statistics for tuning and bug reports.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move :Move,
+    /// Score from the perspective of the player to move.
+    pub score :f32,
+    /// Principal variation: the expected line of play, starting with `best_move`.
+    pub pv :Vec<Move>,
+    /// Depth of the deepest completed iteration.
+    pub depth :u16,
+    pub nodes :u64,
+    pub tt_hits :u64,
+    pub cutoffs :u64,
+    pub time :Duration,
+}
+
+impl SearchResult {
+    /// The result in the form reported to the referee.
+    pub fn info(&self) -> SearchInfo {
+        SearchInfo {
+            depth: Some(self.depth),
+            score: Some(self.score),
+            nodes: Some(self.nodes),
+            time: Some(self.time),
+            pv: self.pv.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for SearchResult {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        let pv = self.pv.iter().map(printer).collect::<Vec<_>>().join(" ");
+        write!(f, "depth {} score {:.3} nodes {} tt hits {} cutoffs {} time {:.3}s pv {}",
+               self.depth, self.score, self.nodes, self.tt_hits, self.cutoffs,
+               self.time.as_secs_f64(), pv)
+    }
+}
+
+pub fn print_search_result(result :&SearchResult) {
+    let mut tbl = prettytable::Table::new();
+    let pv = result.pv.iter().map(printer).collect::<Vec<_>>().join(" ");
+    let rows = [
+        ("best move", printer(&result.best_move)),
+        ("score", format!("{:.3}", result.score)),
+        ("depth", result.depth.to_string()),
+        ("principal variation", pv),
+        ("nodes", result.nodes.to_string()),
+        ("tt hits", result.tt_hits.to_string()),
+        ("cutoffs", result.cutoffs.to_string()),
+        ("time", format!("{:.3}s", result.time.as_secs_f64())),
+    ];
+    for (name, value) in rows.iter() {
+        let mut row = prettytable::Row::empty();
+        row.add_cell(prettytable::Cell::new(name));
+        row.add_cell(prettytable::Cell::new(value));
+        tbl.add_row(row);
+    }
+    tbl.printstd();
+}
+
+/// Search with increasing depth until the deadline, and return the result
+/// of the deepest completed search, or `None` if there are no legal moves.
+/// If not even the depth 1 search is completed in time, the result has depth 0
+/// and the first move in the move ordering.
+pub fn iterative_deepening(search :&mut Search, board :&Board, max_depth :u16) -> Option<SearchResult> {
+    let start = Instant::now();
+    search.table.new_search();
+    for table in &mut search.helper_tables { table.new_search(); }
+    let first_ordered = *search.ordering.moves(board, 1, None, true, false).first()?;
+    let (mut score, mut pv, mut depth) = match negamax_root(search, board, 1, None) {
+        Some((score, pv)) => {
+            debug!("depth 1 score {} pv {:?}", score, pv);
+            (score, pv, 1)
+        },
+        None => {
+            debug!("depth 1 not completed");
+            let score = ((1-2*board.player() as i64) as f32)*search.evaluator.evaluate(board);
+            (score, vec![first_ordered], 0)
+        },
+    };
+    while depth > 0 && depth < max_depth {
+        // A won or lost game does not change with more search.
+        if score.is_infinite() { break; }
+        match negamax_root(search, board, depth + 1, Some(pv[0])) {
+            Some((new_score, new_pv)) => {
+                depth += 1;
+                score = new_score;
+                pv = new_pv;
+                debug!("depth {} score {} pv {:?}", depth, score, pv);
+            },
+            None => {
+                debug!("depth {} not completed", depth + 1);
+                break;
+            },
+        }
+    }
+
+    Some(SearchResult {
+        best_move: pv[0],
+        score,
+        pv,
+        depth,
+        nodes: search.nodes,
+        tt_hits: search.tt_hits,
+        cutoffs: search.cutoffs,
+        time: start.elapsed(),
+    })
+}
+
+/// Search all moves to the given depth, trying `first_move` first, and return the
+/// score and principal variation, or None if the search ran out of time.
+///
+/// If the search has helper tables, the first move is searched alone, and then
+/// the rest of the moves are split between threads, which share the best score
+/// found so far. The score is the same as for a single thread, but with equal
+/// scores the chosen move may depend on timing.
+pub fn negamax_root(search :&mut Search, board :&Board,
+                    depth: u16, first_move :Option<Move>) -> Option<(f32,Vec<Move>)> {
+
+    search.nodes += 1;
+    let (mut score, mut pv) = (-std::f32::INFINITY, Vec::new());
+    let moves = search.ordering.moves(board, depth, first_move,
+                                      search.move_ordering, search.prune_walls);
+    // The first move is searched first even without move ordering.
+    let first = first_move.into_iter().filter(|m| moves.contains(m));
+    let moves = first.chain(moves.iter().cloned().filter(|m| Some(*m) != first_move))
+        .collect::<Vec<_>>();
+
+    let serial = if search.helper_tables.is_empty() { moves.len() } else { 1 };
+    for m in moves.iter().take(serial) {
+        let (new_score, new_pv) = search_root_move(search, board, depth, *m, score);
+        if search.aborted { break; }
+        if new_score > score || pv.is_empty() {
             score = new_score;
-            mv = Some(m);
+            pv = new_pv;
         }
-        true
-    });
+    }
+
+    if !search.aborted && serial < moves.len() {
+        let best = search_root_parallel(search, board, depth, &moves[serial..], (score,pv));
+        score = best.0;
+        pv = best.1;
+    }
+
+    if search.aborted || pv.is_empty() { return None; }
+    Some((score,pv))
+}
 
-    mv.unwrap()
+/// Search a move at the root, given the score to beat.
+/// Returns the score and principal variation starting with the move.
+fn search_root_move(search :&mut Search, board :&Board, depth :u16,
+                    mv :Move, alpha :f32) -> (f32,Vec<Move>) {
+    let mut new_board = *board;
+    new_board.integrate(mv).unwrap();
+    let mut child_pv = Vec::new();
+    let score = -negamax(search, &new_board, depth - 1,
+                         -std::f32::INFINITY, -alpha, &mut child_pv);
+    let mut pv = vec![mv];
+    pv.extend(child_pv);
+    (score, pv)
 }
 
-pub fn negamax(table :&mut HashMap<Board, BoardInfo>, board :&Board,
-               depth: u16, mut alpha :f32, mut beta :f32) -> f32 {
+/// Search root moves in parallel, one thread for the search itself and one
+/// for each helper table. Threads take the next unsearched move until all
+/// moves are done, and update the shared best score and principal variation.
+fn search_root_parallel(search :&mut Search, board :&Board, depth :u16,
+                        moves :&[Move], best :(f32,Vec<Move>)) -> (f32,Vec<Move>) {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let next = AtomicUsize::new(0);
+    let best = Mutex::new(best);
+    let work = |search :&mut Search| {
+        loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            if i >= moves.len() { break; }
+            let alpha = best.lock().unwrap().0;
+            let (score, pv) = search_root_move(search, board, depth, moves[i], alpha);
+            if search.aborted { break; }
+            let mut best = best.lock().unwrap();
+            if score > best.0 || best.1.is_empty() {
+                *best = (score, pv);
+            }
+        }
+    };
+
+    let mut helper_tables = std::mem::take(&mut search.helper_tables);
+    std::thread::scope(|scope| {
+        let helpers = helper_tables.iter_mut().map(|table| {
+            let mut helper = Search::new(&mut **table, search.deadline);
+            helper.move_ordering = search.move_ordering;
+            helper.prune_walls = search.prune_walls;
+            helper.evaluator = search.evaluator.clone();
+            helper.stop = search.stop.clone();
+            let work = &work;
+            scope.spawn(move || {
+                work(&mut helper);
+                (helper.nodes, helper.tt_hits, helper.cutoffs, helper.aborted)
+            })
+        }).collect::<Vec<_>>();
+
+        work(search);
+        for helper in helpers {
+            let (nodes, tt_hits, cutoffs, aborted) = helper.join().unwrap();
+            search.nodes += nodes;
+            search.tt_hits += tt_hits;
+            search.cutoffs += cutoffs;
+            search.aborted |= aborted;
+        }
+    });
+    search.helper_tables = helper_tables;
+    best.into_inner().unwrap()
+}
+
+/// Negamax search with alpha-beta pruning. The principal variation
+/// (the best line found from this position) is written to `pv`.
+pub fn negamax(search :&mut Search, board :&Board,
+               depth: u16, mut alpha :f32, mut beta :f32, pv :&mut Vec<Move>) -> f32 {
+    pv.clear();
+    if search.out_of_time() { return 0.0; }
+    search.nodes += 1;
     let alpha_original = alpha;
 
     // check the table
-    if let Some(info) = table.get(board) {
+    let mut tt_move = None;
+    if let Some(info) = search.table.get(board) {
+        search.tt_hits += 1;
+        tt_move = info.best_move;
         if info.depth >= depth {
             match info.flag {
-                BoardFlag::Exact => { return info.value; },
+                BoardFlag::Exact => {
+                    pv.extend(info.best_move);
+                    return info.value;
+                },
                 BoardFlag::LowerBound => { alpha = alpha.max(info.value); },
                 BoardFlag::UpperBound => { beta = beta.min(info.value); },
             };
 
-            if alpha >= beta { return info.value; }
+            if alpha >= beta {
+                pv.extend(info.best_move);
+                return info.value;
+            }
         }
     }
 
     if depth == 0 || board.get_winner().is_some() {
-        return ((1-2*board.player) as f32)*board_heuristic(board); // board heuristic always
+        return ((1-2*board.player() as i64) as f32)*search.evaluator.evaluate(board); // evaluation always
         // takes the perspective of player 1 (first), so we multiply by the current player we
         // are looking at.
     }
 
     let mut value = - std::f32::INFINITY;
-    // TODO order moves by heuristic?
-    for_each_move(&board, &mut |m| {
-        let mut new_board = board.clone();
+    let mut best_move = None;
+    let moves = search.ordering.moves(board, depth, tt_move,
+                                      search.move_ordering, search.prune_walls);
+    for m in &moves {
+        let m = *m;
+        let mut new_board = *board;
         new_board.integrate(m).unwrap(); // panic if we generated an invalid move
-        let new_value = -negamax(table, &new_board, depth -1, -beta, -alpha);
-        value = value.max(new_value);
+        let mut child_pv = Vec::new();
+        let new_value = -negamax(search, &new_board, depth -1, -beta, -alpha, &mut child_pv);
+        if search.aborted { break; }
+        if new_value > value || best_move.is_none() {
+            value = new_value;
+            best_move = Some(m);
+            pv.clear();
+            pv.push(m);
+            pv.extend(child_pv);
+        }
         alpha = alpha.max(value);
         if alpha >= beta {
-            false // break for_each_move
-        } else {
-            true // continue for_each_move
+            search.cutoffs += 1;
+            search.ordering.record_cutoff(board.player(), m, depth);
+            break;
         }
-    });
+    }
 
 
     // TODO: merge boards with similar numbre of remaining walls
@@ -557,10 +1066,12 @@ pub fn negamax(table :&mut HashMap<Board, BoardInfo>, board :&Board,
             BoardFlag::LowerBound
         } else {
             BoardFlag::Exact
-        }
+        },
+        best_move: best_move,
     };
 
-    table.insert(board.clone(), new_info);
+    if search.aborted { return value; }
+    search.table.insert(board, new_info);
     value
 }
 
@@ -0,0 +1,276 @@
+use std::sync::Arc;
+use std::time::Duration;
+use crate::minimax::*;
+use crate::mcts::*;
+use crate::eval::*;
+use crate::book::*;
+use crate::engine::EnginePlayer;
+
+pub const USAGE :&str = "\
+Usage: bot [options]
+       bot perft <depth> [move ...]
+       bot bench <depth> [move ...]
+       bot tune <games> <output file> [initial weights file]
+       bot book <games> <plies> <output file> [search depth]
+
+Options:
+  --engine <name>       heuristic (default), minimax or mcts
+  --depth <plies>       maximum search depth for minimax; without --time,
+                        the search is not limited by time
+  --time <seconds>      time per move for minimax and mcts
+  --iterations <n>      iterations per move for mcts
+  --playout <kind>      playouts for mcts: random, shortest:<p> to follow the
+                        shortest path with probability p (default shortest:0.7),
+                        or cutoff:<n> to evaluate the position after n moves
+  --evaluator <name>    path, resistance (default), resistance-lsqr or flow,
+                        or a weighted sum of them such as 2*path+resistance
+  --weights <file>      use a linear evaluator with weights written by `bot tune`
+  --tt-size <MB>        transposition table size for minimax
+  --threads <n>         search threads for minimax
+  --prune-walls         only search walls near the paths and other walls
+  --seed <n>            random seed for mcts and the opening book
+  --book <file>         play moves from an opening book written by `bot book`
+  --log-level <level>   off, error, warn, info (default), debug or trace
+  --legacy              use the legacy protocol of bare moves instead of the
+                        engine protocol
+  --help                show this message";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Engine { Heuristic, Minimax, Mcts }
+
+/// Options for the bot player, from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub engine :Engine,
+    pub depth :Option<u16>,
+    pub move_time :Option<Duration>,
+    pub iterations :Option<u32>,
+    pub playout :Option<Playout>,
+    pub evaluator :Option<String>,
+    pub weights :Option<String>,
+    pub table_size :Option<usize>,
+    pub threads :Option<usize>,
+    pub prune_walls :bool,
+    pub seed :Option<u64>,
+    pub book :Option<String>,
+    pub log_level :Option<String>,
+    /// Use the legacy line protocol (see `bot::run_bot`) instead of the engine protocol.
+    pub legacy :bool,
+    pub help :bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            engine: Engine::Heuristic, depth: None, move_time: None, iterations: None, playout: None,
+            evaluator: None, weights: None, table_size: None, threads: None,
+            prune_walls: false, seed: None, book: None, log_level: None, legacy: false, help: false,
+        }
+    }
+}
+
+fn parse_value<T :std::str::FromStr>(option :&str, value :Option<&String>) -> Result<T,String>
+    where T::Err :std::fmt::Display {
+    let value = value.ok_or_else(|| format!("{} requires a value", option))?;
+    value.parse().map_err(|e| format!("Could not parse {} {:?}: {}", option, value, e))
+}
+
+impl Options {
+    pub fn parse(args :&[String]) -> Result<Options,String> {
+        let mut options :Options = Default::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            options.apply(arg, &mut args)?;
+        }
+        Ok(options)
+    }
+
+    /// Set an option from the engine protocol's `setoption`, using the names
+    /// of the command-line options without the dashes.
+    pub fn set_option(&mut self, name :&str, value :&str) -> Result<(),String> {
+        match name {
+            "prune-walls" => {
+                self.prune_walls = parse_value(name, Some(&value.to_string()))?;
+                Ok(())
+            },
+            "log-level" | "legacy" | "help" => Err(format!("Option {:?} cannot be set by the referee", name)),
+            _ => self.apply(&format!("--{}", name), &mut std::iter::once(&value.to_string())),
+        }
+    }
+
+    fn apply<'a>(&mut self, arg :&str, args :&mut impl Iterator<Item = &'a String>) -> Result<(),String> {
+        match arg {
+            "--engine" => {
+                self.engine = match parse_value::<String>(arg, args.next())?.as_str() {
+                    "heuristic" => Engine::Heuristic,
+                    "minimax" => Engine::Minimax,
+                    "mcts" => Engine::Mcts,
+                    x => return Err(format!("Unknown engine {:?}", x)),
+                };
+            },
+            "--depth" => { self.depth = Some(parse_value(arg, args.next())?); },
+            "--time" => {
+                let seconds :f64 = parse_value(arg, args.next())?;
+                if !(seconds > 0.0) { return Err("--time must be positive".to_string()); }
+                self.move_time = Some(Duration::from_secs_f64(seconds));
+            },
+            "--iterations" => { self.iterations = Some(parse_value(arg, args.next())?); },
+            "--playout" => { self.playout = Some(parse_value(arg, args.next())?); },
+            "--evaluator" => {
+                let name :String = parse_value(arg, args.next())?;
+                evaluator_by_name(&name)?;
+                self.evaluator = Some(name);
+            },
+            "--weights" => { self.weights = Some(parse_value(arg, args.next())?); },
+            "--tt-size" => { self.table_size = Some(parse_value(arg, args.next())?); },
+            "--threads" => {
+                let threads :usize = parse_value(arg, args.next())?;
+                if threads == 0 { return Err("--threads must be at least 1".to_string()); }
+                self.threads = Some(threads);
+            },
+            "--prune-walls" => { self.prune_walls = true; },
+            "--seed" => { self.seed = Some(parse_value(arg, args.next())?); },
+            "--book" => { self.book = Some(parse_value(arg, args.next())?); },
+            "--log-level" => { self.log_level = Some(parse_value(arg, args.next())?); },
+            "--legacy" => { self.legacy = true; },
+            "--help" | "-h" => { self.help = true; },
+            x => return Err(format!("Unrecognized argument {:?}", x)),
+        }
+        Ok(())
+    }
+
+    /// The evaluator selected by `--weights` or `--evaluator`.
+    pub fn evaluator(&self) -> Result<Arc<dyn Evaluator>,String> {
+        if let Some(path) = &self.weights {
+            return Ok(Arc::new(Linear { weights: Weights::load(path)? }));
+        }
+        match &self.evaluator {
+            Some(name) => evaluator_by_name(name),
+            None => Ok(default_evaluator()),
+        }
+    }
+
+    /// Create the player for the options, reading weights and book files.
+    pub fn player(&self) -> Result<Box<dyn EnginePlayer>,String> {
+        let evaluator = self.evaluator()?;
+        let player :Box<dyn EnginePlayer> = match self.engine {
+            Engine::Heuristic => Box::new(HeuristicBot::with_evaluator(Default::default(), evaluator)),
+            Engine::Minimax => {
+                let mut config = MinimaxConfig { evaluator, prune_walls: self.prune_walls, .. Default::default() };
+                if let Some(depth) = self.depth {
+                    config.max_depth = depth;
+                    config.move_time = None;
+                }
+                if let Some(t) = self.move_time { config.move_time = Some(t); }
+                if let Some(size) = self.table_size { config.table_size = size; }
+                if let Some(threads) = self.threads { config.threads = threads; }
+                Box::new(MinimaxPlayer::with_config(config))
+            },
+            Engine::Mcts => {
+                let mut config = MctsConfig { evaluator, .. Default::default() };
+                if let Some(n) = self.iterations {
+                    config.iterations = Some(n);
+                    config.move_time = None;
+                }
+                if let Some(t) = self.move_time { config.move_time = Some(t); }
+                if let Some(playout) = self.playout { config.playout = playout; }
+                if let Some(seed) = self.seed { config.seed = seed; }
+                Box::new(MctsPlayer::with_config(config))
+            },
+        };
+
+        match &self.book {
+            Some(path) => {
+                let book = Book::load(path)?;
+                let seed = self.seed.unwrap_or_else(|| {
+                    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                        .map(|d| d.as_secs()).unwrap_or(0)
+                });
+                Ok(Box::new(BookPlayer::new(book, player, seed)))
+            },
+            None => Ok(player),
+        }
+    }
+}
+
+/// The evaluator with the given name, or a weighted sum of evaluators
+/// written as `<weight>*<name>+<name>...`, where the weights default to 1.
+fn evaluator_by_name(name :&str) -> Result<Arc<dyn Evaluator>,String> {
+    if !name.contains(&['+', '*'][..]) { return single_evaluator(name); }
+    let terms = name.split('+').map(|term| {
+        let (weight, name) = match term.find('*') {
+            Some(i) => (term[..i].trim().parse::<f32>()
+                            .map_err(|e| format!("Invalid weight in {:?}: {}", term, e))?,
+                        &term[i+1..]),
+            None => (1.0, term),
+        };
+        Ok((weight, single_evaluator(name.trim())?))
+    }).collect::<Result<Vec<_>,String>>()?;
+    Ok(Arc::new(Weighted(terms)))
+}
+
+fn single_evaluator(name :&str) -> Result<Arc<dyn Evaluator>,String> {
+    match name {
+        "path" => Ok(Arc::new(PathDifference { wall_weight: 0.05 })),
+        "resistance" => Ok(default_evaluator()),
+        "resistance-lsqr" => Ok(Arc::new(Resistance { solver: Solver::Lsqr, wall_weight: 0.05 })),
+        "flow" => Ok(Arc::new(Flow { wall_weight: 0.05 })),
+        x => Err(format!("Unknown evaluator {:?}", x)),
+    }
+}
+
+#[test]
+fn test_parse_options() {
+    let args = |s :&str| s.split_whitespace().map(|a| a.to_string()).collect::<Vec<_>>();
+    assert_eq!(Options::default(), Options::parse(&[]).unwrap());
+
+    let options = Options::parse(&args("--engine minimax --depth 4 --tt-size 16 --threads 2 \
+                                        --evaluator flow --seed 7 --log-level debug")).unwrap();
+    assert_eq!(Engine::Minimax, options.engine);
+    assert_eq!(Some(4), options.depth);
+    assert_eq!(Some(16), options.table_size);
+    assert_eq!(Some(2), options.threads);
+    assert_eq!(Some("flow".to_string()), options.evaluator);
+    assert_eq!(Some(7), options.seed);
+    assert_eq!(Some("debug".to_string()), options.log_level);
+    assert!(options.player().is_ok());
+
+    let options = Options::parse(&args("--engine mcts --time 0.5 --iterations 100")).unwrap();
+    assert_eq!(Some(Duration::from_millis(500)), options.move_time);
+    assert_eq!(Some(100), options.iterations);
+
+    for (arg, playout) in &[("random", Playout::Random), ("shortest:0.5", Playout::ShortestPath(0.5)),
+                            ("cutoff:4", Playout::HeuristicCutoff(4))] {
+        let options = Options::parse(&args(&format!("--engine mcts --playout {}", arg))).unwrap();
+        assert_eq!(Some(*playout), options.playout);
+        assert!(options.player().is_ok());
+    }
+    let mut options = Options::default();
+    options.set_option("playout", "cutoff:2").unwrap();
+    assert_eq!(Some(Playout::HeuristicCutoff(2)), options.playout);
+    for arg in &["--playout", "--playout shortest", "--playout shortest:2",
+                 "--playout cutoff:x", "--playout best"] {
+        assert!(Options::parse(&args(arg)).is_err(), "{}", arg);
+    }
+
+    assert!(Options::parse(&args("--engine alphazero")).is_err());
+    assert!(Options::parse(&args("--depth")).is_err());
+    assert!(Options::parse(&args("--depth deep")).is_err());
+    assert!(Options::parse(&args("--evaluator magic")).is_err());
+    let options = Options::parse(&args("--evaluator 2*path+resistance")).unwrap();
+    assert_eq!(Some("2*path+resistance".to_string()), options.evaluator);
+    let mut board :model::Board = Default::default();
+    board.integrate(model::Move::PawnTo(model::Position { x: 5, y: 2 })).unwrap();
+    let combined = options.evaluator().unwrap().evaluate(&board);
+    let sum = 2.0 * single_evaluator("path").unwrap().evaluate(&board)
+        + single_evaluator("resistance").unwrap().evaluate(&board);
+    assert!((combined - sum).abs() < 1e-4, "{} != {}", combined, sum);
+    assert!(Options::parse(&args("--evaluator flow+0.5*path")).is_ok());
+    assert!(Options::parse(&args("--evaluator 2*path+magic")).is_err());
+    assert!(Options::parse(&args("--evaluator x*path")).is_err());
+    assert!(Options::parse(&args("--evaluator path+")).is_err());
+    assert!(Options::parse(&args("--threads 0")).is_err());
+    assert!(Options::parse(&args("--time -1")).is_err());
+    assert!(Options::parse(&args("minimax")).is_err());
+    assert!(Options::parse(&args("--weights /nonexistent")).unwrap().player().is_err());
+}
@@ -0,0 +1,204 @@
+use model::*;
+
+/// Number of distinct moves: 81 pawn target cells and 2*64 walls.
+const NUM_MOVE_INDICES :usize = 81 + 2*64;
+
+// Score levels for ordering, from first to last.
+const SCORE_TT_MOVE :u32 = u32::MAX;
+const SCORE_PAWN_FORWARD :u32 = 3 << 28;
+const SCORE_KILLER :u32 = 2 << 28;
+const SCORE_WALL_ON_PATH :u32 = 1 << 28;
+const MAX_HISTORY :u32 = (1 << 28) - 1;
+
+fn move_index(mv :Move) -> usize {
+    match mv {
+        Move::PawnTo(p) => encode9(p.x,p.y),
+        Move::WallAt(Orientation::Horizontal, p) => 81 + encode8(p.x-1,p.y-1),
+        Move::WallAt(Orientation::Vertical, p) => 81 + 64 + encode8(p.x-1,p.y-1),
+    }
+}
+
+/// The two walls that can separate a cell from its neighbor.
+fn walls_between(a :Position, b :Position) -> [(Orientation,Position);2] {
+    if a.y == b.y {
+        let left = a.x.min(b.x);
+        [(Orientation::Vertical, Position { x: left, y: a.y }),
+         (Orientation::Vertical, Position { x: left, y: a.y - 1 })]
+    } else {
+        let top = a.y.min(b.y);
+        [(Orientation::Horizontal, Position { x: a.x, y: top }),
+         (Orientation::Horizontal, Position { x: a.x - 1, y: top })]
+    }
+}
+
+/// Set of walls, as a bit for each move index.
+#[derive(Copy, Clone, Default)]
+struct WallSet([u64;2]);
+
+impl WallSet {
+    fn insert(&mut self, ori :Orientation, pos :Position) {
+        if in_bounds1to8(&pos) {
+            let idx = move_index(Move::WallAt(ori,pos)) - 81;
+            self.0[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    fn contains(&self, ori :Orientation, pos :Position) -> bool {
+        let idx = move_index(Move::WallAt(ori,pos)) - 81;
+        (self.0[idx / 64] >> (idx % 64)) & 1 == 1
+    }
+
+    /// Walls that cut a step along the path.
+    fn cutting_path(start :Position, path :&[Position]) -> WallSet {
+        let mut set = WallSet::default();
+        let mut prev = start;
+        for cell in path {
+            for (ori,pos) in walls_between(prev, *cell).iter() {
+                set.insert(*ori, *pos);
+            }
+            prev = *cell;
+        }
+        set
+    }
+
+    /// Walls that touch any of the cells on the path, including the start cell.
+    fn touching_path(start :Position, path :&[Position]) -> WallSet {
+        let mut set = WallSet::default();
+        for cell in std::iter::once(&start).chain(path.iter()) {
+            for (dx,dy) in &[(0,0),(-1,0),(0,-1),(-1,-1)] {
+                let pos = Position { x: cell.x + dx, y: cell.y + dy };
+                set.insert(Orientation::Horizontal, pos);
+                set.insert(Orientation::Vertical, pos);
+            }
+        }
+        set
+    }
+}
+
+/// Move ordering heuristics for the alpha-beta search.
+///
+/// Moves are tried in this order: the best move from the transposition table,
+/// pawn moves that shorten the player's path to the goal, killer moves (moves
+/// that recently caused a cutoff at the same depth), walls that cut the opponent's
+/// shortest path, and then the rest sorted by the history heuristic
+/// (how often the move has caused cutoffs anywhere in the search).
+pub struct MoveOrdering {
+    killers :Vec<[Option<Move>;2]>,
+    history :[[u32; NUM_MOVE_INDICES];2],
+}
+
+impl MoveOrdering {
+    pub fn new() -> MoveOrdering {
+        MoveOrdering {
+            killers: Vec::new(),
+            history: [[0; NUM_MOVE_INDICES];2],
+        }
+    }
+
+    /// Record a move that caused a beta cutoff.
+    pub fn record_cutoff(&mut self, player :usize, mv :Move, depth :u16) {
+        let depth = depth as usize;
+        if self.killers.len() <= depth {
+            self.killers.resize(depth + 1, [None,None]);
+        }
+        let killers = &mut self.killers[depth];
+        if killers[0] != Some(mv) {
+            killers[1] = killers[0];
+            killers[0] = Some(mv);
+        }
+
+        let h = &mut self.history[player][move_index(mv)];
+        *h = (*h + (depth*depth) as u32).min(MAX_HISTORY);
+    }
+
+    /// Legal moves, sorted with the most promising moves first if `order` is set.
+    /// If `prune_walls` is set, only walls touching either player's shortest path
+    /// or a wall that is already on the board are included.
+    pub fn moves(&self, board :&Board, depth :u16, tt_move :Option<Move>,
+                 order :bool, prune_walls :bool) -> MoveList {
+        let mut moves = board.legal_moves();
+        if !order && !prune_walls { return moves; }
+
+        let me = board.player();
+        let my_pos = board.position(me);
+        let other_pos = board.position(1 - me);
+        let other_path = board.shortest_path(1 - me).unwrap_or_default();
+        if prune_walls {
+            let my_path = board.shortest_path(me).unwrap_or_default();
+            let near_paths = WallSet::touching_path(my_pos, &my_path);
+            let near_other_path = WallSet::touching_path(other_pos, &other_path);
+            let pruned = moves.iter().cloned().filter(|mv| match mv {
+                Move::PawnTo(_) => true,
+                Move::WallAt(o,p) => near_paths.contains(*o,*p) ||
+                    near_other_path.contains(*o,*p) || board.touches_wall(*o,*p),
+            }).collect::<Vec<_>>();
+            moves = MoveList::new();
+            for mv in pruned { moves.push(mv); }
+        }
+        if !order { return moves; }
+
+        let distances = board.distance_map(me);
+        let distance = |p :Position| distances[encode9(p.x,p.y)] as u32;
+        let killers = self.killers.get(depth as usize).cloned().unwrap_or([None,None]);
+        let cuts_other_path = WallSet::cutting_path(other_pos, &other_path);
+        let history = &self.history[me];
+
+        let score = |mv :Move| {
+            if Some(mv) == tt_move { return SCORE_TT_MOVE; }
+            if let Move::PawnTo(p) = mv {
+                if distance(p) < distance(my_pos) {
+                    return SCORE_PAWN_FORWARD + distance(my_pos) - distance(p);
+                }
+            }
+            if killers[0] == Some(mv) { return SCORE_KILLER + 1; }
+            if killers[1] == Some(mv) { return SCORE_KILLER; }
+            let h = history[move_index(mv)];
+            match mv {
+                Move::WallAt(o,p) if cuts_other_path.contains(o,p) => SCORE_WALL_ON_PATH + h,
+                _ => h,
+            }
+        };
+
+        let mut scored = moves.iter().map(|mv| (score(*mv), *mv)).collect::<Vec<_>>();
+        scored.sort_by_key(|(s,_)| std::cmp::Reverse(*s));
+        let mut ordered = MoveList::new();
+        for (_,mv) in scored { ordered.push(mv); }
+        ordered
+    }
+}
+
+#[test]
+fn test_move_ordering() {
+    let mut board :Board = Default::default();
+    let ordering = MoveOrdering::new();
+
+    // Without ordering, moves are in generation order.
+    assert_eq!(board.legal_moves().as_slice(), ordering.moves(&board, 1, None, false, false).as_slice());
+
+    // Forward pawn move first, then walls cutting the opponent's path (e9 to e1).
+    let moves = ordering.moves(&board, 1, None, true, false);
+    assert_eq!(131, moves.len());
+    assert_eq!(Move::PawnTo(Position { x: 5, y: 2 }), moves[0]);
+    match moves[1] {
+        Move::WallAt(Orientation::Horizontal, p) => assert!(p.x == 4 || p.x == 5),
+        mv => panic!("expected a horizontal wall across the e file, got {:?}", mv),
+    }
+
+    // The table move comes first, killers come before other walls.
+    let far_wall = Move::WallAt(Orientation::Vertical, Position { x: 1, y: 8 });
+    let tt_move = Move::WallAt(Orientation::Vertical, Position { x: 8, y: 1 });
+    let mut ordering = MoveOrdering::new();
+    ordering.record_cutoff(0, far_wall, 2);
+    let moves = ordering.moves(&board, 2, Some(tt_move), true, false);
+    assert_eq!(tt_move, moves[0]);
+    assert_eq!(far_wall, moves[2]);
+
+    // Pruning keeps pawn moves and walls near paths or other walls.
+    board.add_wall(Orientation::Horizontal, Position { x: 1, y: 5 });
+    let moves = ordering.moves(&board, 1, None, false, true);
+    assert!(moves.len() < board.legal_moves().len());
+    assert!(moves.contains(&Move::PawnTo(Position { x: 5, y: 2 })));
+    assert!(moves.contains(&Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 1 })));
+    assert!(moves.contains(&Move::WallAt(Orientation::Vertical, Position { x: 2, y: 5 })));
+    assert!(!moves.contains(&Move::WallAt(Orientation::Vertical, Position { x: 8, y: 2 })));
+}
@@ -0,0 +1,139 @@
+use model::*;
+
+#[repr(u16)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoardFlag { Exact, LowerBound, UpperBound }
+
+#[derive(Copy, Clone, Debug)]
+pub struct BoardInfo {
+    pub value: f32,
+    pub depth: u16,
+    pub flag :BoardFlag,
+    pub best_move :Option<Move>,
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    key :u64,
+    generation :u8,
+    info :BoardInfo,
+}
+
+/// Counters for transposition table usage.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TableStats {
+    /// Number of lookups.
+    pub probes :u64,
+    /// Lookups that found an entry for the same position.
+    pub hits :u64,
+    /// Lookups that found an entry for a different position in the same slot.
+    pub collisions :u64,
+    /// Number of entries stored.
+    pub stores :u64,
+    /// Stores that replaced an entry for a different position.
+    pub overwrites :u64,
+}
+
+/// Fixed-size hash table from board Zobrist keys to search results.
+/// When two positions map to the same slot, the entry with the deepest
+/// search is kept, unless the existing entry is from an earlier search.
+pub struct TranspositionTable {
+    entries :Vec<Option<Entry>>,
+    generation :u8,
+    pub stats :TableStats,
+}
+
+impl TranspositionTable {
+    /// Create a table using approximately the given number of megabytes.
+    pub fn new(megabytes :usize) -> TranspositionTable {
+        let entry_size = std::mem::size_of::<Option<Entry>>();
+        let n = (megabytes * 1024 * 1024 / entry_size).max(1);
+        TranspositionTable {
+            entries: vec![None; n],
+            generation: 0,
+            stats: Default::default(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn index(&self, key :u64) -> usize {
+        (key % self.entries.len() as u64) as usize
+    }
+
+    pub fn get(&mut self, board :&Board) -> Option<BoardInfo> {
+        let key = board.zobrist_key();
+        self.stats.probes += 1;
+        match &self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => {
+                self.stats.hits += 1;
+                Some(entry.info)
+            },
+            Some(_) => {
+                self.stats.collisions += 1;
+                None
+            },
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, board :&Board, info :BoardInfo) {
+        let key = board.zobrist_key();
+        let idx = self.index(key);
+        let generation = self.generation;
+        let replace = match &self.entries[idx] {
+            None => true,
+            Some(entry) => entry.key == key || entry.generation != generation ||
+                info.depth >= entry.info.depth,
+        };
+
+        if replace {
+            if let Some(entry) = &self.entries[idx] {
+                if entry.key != key { self.stats.overwrites += 1; }
+            }
+            self.stats.stores += 1;
+            self.entries[idx] = Some(Entry { key, generation, info });
+        }
+    }
+
+    /// Start a new search. Entries from earlier searches are kept, but
+    /// can be replaced by any new entry.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+}
+
+#[test]
+fn test_transposition_table() {
+    let mut table = TranspositionTable::new(1);
+    assert!(table.len() > 1000);
+
+    let board :Board = Default::default();
+    let info = |depth, value| BoardInfo { value, depth, flag: BoardFlag::Exact, best_move: None };
+    assert!(table.get(&board).is_none());
+    table.insert(&board, info(3, 1.0));
+    assert_eq!(1.0, table.get(&board).unwrap().value);
+
+    // With a single entry, all boards map to the same slot.
+    let mut table = TranspositionTable::new(0);
+    assert_eq!(1, table.len());
+    let mut other = board;
+    other.integrate(Move::PawnTo(Position { x: 5, y: 2 })).unwrap();
+    table.insert(&board, info(3, 1.0));
+
+    // Shallower search does not replace the entry.
+    assert!(table.get(&other).is_none());
+    assert_eq!(1, table.stats.collisions);
+    table.insert(&other, info(2, 2.0));
+    assert_eq!(1.0, table.get(&board).unwrap().value);
+
+    // Deeper search, or any search after a new search is started, replaces it.
+    table.insert(&other, info(4, 3.0));
+    assert!(table.get(&board).is_none());
+    table.new_search();
+    table.insert(&board, info(1, 4.0));
+    assert_eq!(4.0, table.get(&board).unwrap().value);
+    assert_eq!(2, table.stats.overwrites);
+}
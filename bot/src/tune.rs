@@ -0,0 +1,206 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use model::*;
+use log::*;
+use crate::eval::*;
+
+/// Features of a position from a self-play game, and the result of the game
+/// for player 1 (index 0): 1 for a win and 0 for a loss.
+#[derive(Copy, Clone, Debug)]
+pub struct Sample {
+    pub features :[f32; NUM_FEATURES],
+    pub result :f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TuneConfig {
+    /// Number of self-play games.
+    pub games :usize,
+    /// Games that are not finished after this many moves are not used.
+    pub max_plies :usize,
+    /// Probability of making a random move instead of the best move,
+    /// to get a variety of positions.
+    pub random_move_prob :f64,
+    pub seed :u64,
+}
+
+impl Default for TuneConfig {
+    fn default() -> TuneConfig {
+        TuneConfig { games: 100, max_plies: 200, random_move_prob: 0.1, seed: 0 }
+    }
+}
+
+/// Play a game where both players choose the move with the best evaluation
+/// after the move, or sometimes a random move. Returns the positions
+/// and moves of the game, and the winner if the game finished within `max_plies` moves.
+pub fn self_play_game(evaluator :&dyn Evaluator, rng :&mut impl Rng,
+                      random_move_prob :f64, max_plies :usize) -> (Vec<Board>, Vec<Move>, Option<usize>) {
+    let mut board :Board = Default::default();
+    let mut positions = vec![board];
+    let mut moves_played = Vec::new();
+    for _ in 0..max_plies {
+        if board.get_winner().is_some() { break; }
+        let moves = board.legal_moves();
+        if moves.is_empty() { break; }
+        let mv = if rng.gen_bool(random_move_prob) {
+            moves[rng.gen_range(0, moves.len())]
+        } else {
+            let sign = if board.player() == 0 { 1.0 } else { -1.0 };
+            let score = |mv :&Move| {
+                let mut b = board;
+                b.integrate(*mv).unwrap();
+                sign * evaluator.evaluate(&b)
+            };
+            *moves.iter().max_by(|a,b| score(a).partial_cmp(&score(b)).unwrap()).unwrap()
+        };
+        board.integrate(mv).unwrap();
+        positions.push(board);
+        moves_played.push(mv);
+    }
+    (positions, moves_played, board.get_winner())
+}
+
+/// Play self-play games and collect the positions of finished games.
+pub fn generate_samples(evaluator :&dyn Evaluator, config :&TuneConfig) -> Vec<Sample> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut samples = Vec::new();
+    for game in 0..config.games {
+        let (positions, _, winner) = self_play_game(evaluator, &mut rng,
+                                                 config.random_move_prob, config.max_plies);
+        info!("Self-play game {}: {} moves, winner {:?}", game + 1, positions.len() - 1, winner);
+        let winner = match winner {
+            Some(w) => w,
+            None => continue,
+        };
+        let result = if winner == 0 { 1.0 } else { 0.0 };
+        samples.extend(positions.iter()
+                       .filter(|b| b.get_winner().is_none())
+                       .map(|b| Sample { features: features(b), result }));
+    }
+    samples
+}
+
+fn sigmoid(x :f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// Mean cross-entropy of the game results, predicting the result with
+/// `sigmoid(weights * features)`.
+pub fn loss(samples :&[Sample], weights :&Weights) -> f64 {
+    let w = weights.to_array();
+    let total :f64 = samples.iter().map(|s| {
+        let x :f32 = w.iter().zip(s.features.iter()).map(|(w,f)| w*f).sum();
+        let p = sigmoid(x as f64).max(1e-12).min(1.0 - 1e-12);
+        let y = s.result as f64;
+        -(y * p.ln() + (1.0 - y) * (1.0 - p).ln())
+    }).sum();
+    total / samples.len().max(1) as f64
+}
+
+/// Fit weights by logistic regression of the results on the features, using
+/// Newton's method. A small ridge penalty keeps the weights finite when
+/// the results are perfectly predictable, and fixes features that never vary to zero.
+pub fn fit_weights(samples :&[Sample], initial :Weights) -> Weights {
+    const N :usize = NUM_FEATURES;
+    const RIDGE :f64 = 1e-3;
+    let mut w = [0.0f64; N];
+    for (w,x) in w.iter_mut().zip(initial.to_array().iter()) { *w = *x as f64; }
+    let objective = |w :&[f64; N]| {
+        let mut weights = [0.0f32; N];
+        for (a,b) in weights.iter_mut().zip(w.iter()) { *a = *b as f32; }
+        loss(samples, &Weights::from_array(weights)) + 0.5 * RIDGE * w.iter().map(|w| w*w).sum::<f64>()
+    };
+
+    for _ in 0..50 {
+        let mut gradient = [0.0f64; N];
+        let mut hessian = [[0.0f64; N]; N];
+        for s in samples {
+            let f = s.features.iter().map(|f| *f as f64).collect::<Vec<_>>();
+            let p = sigmoid(w.iter().zip(f.iter()).map(|(w,f)| w*f).sum());
+            for i in 0..N {
+                gradient[i] += (p - s.result as f64) * f[i];
+                for j in 0..N {
+                    hessian[i][j] += p * (1.0 - p) * f[i] * f[j];
+                }
+            }
+        }
+        for i in 0..N {
+            gradient[i] += RIDGE * samples.len() as f64 * w[i];
+            hessian[i][i] += RIDGE * samples.len() as f64;
+        }
+
+        // Newton steps can overshoot far from the optimum, so halve the
+        // step until the penalized loss decreases.
+        let step = solve(hessian, gradient);
+        let current = objective(&w);
+        let mut t = 1.0;
+        while t > 1e-6 {
+            let mut candidate = w;
+            for i in 0..N { candidate[i] -= t * step[i]; }
+            if objective(&candidate) <= current {
+                w = candidate;
+                break;
+            }
+            t /= 2.0;
+        }
+        if step.iter().all(|s| (t * s).abs() < 1e-9) { break; }
+    }
+
+    let mut result = [0.0f32; N];
+    for (r,w) in result.iter_mut().zip(w.iter()) { *r = *w as f32; }
+    Weights::from_array(result)
+}
+
+/// Solve a linear system by Gaussian elimination with partial pivoting.
+fn solve(mut a :[[f64; NUM_FEATURES]; NUM_FEATURES], mut b :[f64; NUM_FEATURES]) -> [f64; NUM_FEATURES] {
+    const N :usize = NUM_FEATURES;
+    for col in 0..N {
+        let pivot = (col..N).max_by(|i,j| a[*i][col].abs().partial_cmp(&a[*j][col].abs()).unwrap()).unwrap();
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col+1..N {
+            let factor = a[row][col] / a[col][col];
+            for k in col..N { a[row][k] -= factor * a[col][k]; }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let sum :f64 = (row+1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x
+}
+
+#[test]
+fn test_fit_weights() {
+    // Results drawn from a known logistic model are fitted back.
+    let truth = Weights { path: 0.8, walls: 0.2, resistance: 0.0, flow: 0.0, mobility: -0.5 };
+    let mut rng = StdRng::seed_from_u64(1);
+    let samples = (0..5000).map(|_| {
+        let mut features = [0.0; NUM_FEATURES];
+        for f in features.iter_mut() { *f = rng.gen_range(-5.0, 5.0); }
+        let x :f32 = truth.to_array().iter().zip(features.iter()).map(|(w,f)| w*f).sum();
+        let result = if rng.gen_bool(sigmoid(x as f64)) { 1.0 } else { 0.0 };
+        Sample { features, result }
+    }).collect::<Vec<_>>();
+
+    let fitted = fit_weights(&samples, Default::default());
+    for (t,f) in truth.to_array().iter().zip(fitted.to_array().iter()) {
+        assert!((t-f).abs() < 0.1, "fitted {:?}, expected {:?}", fitted, truth);
+    }
+    assert!(loss(&samples, &fitted) < loss(&samples, &Default::default()));
+}
+
+#[test]
+fn test_self_play_samples() {
+    let evaluator = PathDifference { wall_weight: 0.1 };
+    let config = TuneConfig { games: 2, max_plies: 100, random_move_prob: 0.2, seed: 3 };
+    let samples = generate_samples(&evaluator, &config);
+    assert!(!samples.is_empty());
+    assert!(samples.iter().all(|s| s.result == 0.0 || s.result == 1.0));
+
+    let (positions, moves, winner) = self_play_game(&evaluator, &mut StdRng::seed_from_u64(0), 0.0, 200);
+    assert_eq!(winner, positions.last().unwrap().get_winner());
+    assert_eq!(positions.len(), moves.len() + 1);
+}
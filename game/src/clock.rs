@@ -0,0 +1,127 @@
+use model::*;
+use std::time::Duration;
+
+/// Time control for a game, written as `<seconds>/move` for a fixed time
+/// per move, `<seconds>` for sudden death, or `<seconds>+<seconds>` for a
+/// time for the game with an increment after each move.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimeControl {
+    /// A fixed time for each move. Unused time is not carried over.
+    PerMove(Duration),
+    /// A time for the whole game, with an increment added after each move.
+    /// Sudden death has no increment.
+    Fischer { base :Duration, increment :Duration },
+}
+
+fn parse_seconds(s :&str) -> Result<Duration,String> {
+    let seconds :f64 = s.trim().parse().map_err(|e| format!("Could not parse {:?} as seconds: {}", s, e))?;
+    if !seconds.is_finite() || seconds < 0.0 { return Err(format!("Invalid number of seconds {:?}", s)); }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+impl std::str::FromStr for TimeControl {
+    type Err = String;
+    fn from_str(s :&str) -> Result<TimeControl,String> {
+        let control = if let Some(t) = s.strip_suffix("/move") {
+            TimeControl::PerMove(parse_seconds(t)?)
+        } else {
+            let mut parts = s.splitn(2, '+');
+            let base = parse_seconds(parts.next().unwrap())?;
+            let increment = parts.next().map(parse_seconds).transpose()?.unwrap_or_default();
+            TimeControl::Fischer { base, increment }
+        };
+        match control {
+            TimeControl::PerMove(t) | TimeControl::Fischer { base: t, .. } if t == Duration::default() =>
+                Err(format!("Time control {:?} gives no time", s)),
+            _ => Ok(control),
+        }
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TimeControl::PerMove(t) => write!(f, "{}/move", t.as_secs_f64()),
+            TimeControl::Fischer { base, increment } if *increment == Duration::default() =>
+                write!(f, "{}", base.as_secs_f64()),
+            TimeControl::Fischer { base, increment } =>
+                write!(f, "{}+{}", base.as_secs_f64(), increment.as_secs_f64()),
+        }
+    }
+}
+
+/// The players' clocks during a game, indexed by player.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Clocks {
+    pub control :TimeControl,
+    /// Time left on each clock. With a fixed time per move, this is the time for the next move.
+    pub remaining :[Duration;2],
+}
+
+impl Clocks {
+    pub fn new(control :TimeControl) -> Clocks {
+        let t = match control {
+            TimeControl::PerMove(t) => t,
+            TimeControl::Fischer { base, .. } => base,
+        };
+        Clocks { control, remaining: [t, t] }
+    }
+
+    /// Stop the player's clock after a move that took `elapsed`. Returns
+    /// false if the player ran out of time.
+    pub fn record(&mut self, player :usize, elapsed :Duration) -> bool {
+        if elapsed > self.remaining[player] { return false; }
+        if let TimeControl::Fischer { increment, .. } = self.control {
+            self.remaining[player] = self.remaining[player] - elapsed + increment;
+        }
+        true
+    }
+
+    /// The clocks as sent to engines with `go`.
+    pub fn go_params(&self) -> GoParams {
+        match self.control {
+            TimeControl::PerMove(t) => GoParams { move_time: Some(t), .. Default::default() },
+            TimeControl::Fischer { increment, .. } => GoParams {
+                time: [Some(self.remaining[0]), Some(self.remaining[1])],
+                increment: [Some(increment), Some(increment)],
+                move_time: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_controls() {
+        let secs = Duration::from_secs;
+        assert_eq!(Ok(TimeControl::PerMove(secs(5))), "5/move".parse());
+        assert_eq!(Ok(TimeControl::Fischer { base: secs(60), increment: secs(0) }), "60".parse());
+        assert_eq!(Ok(TimeControl::Fischer { base: secs(60), increment: Duration::from_millis(500) }), "60+0.5".parse());
+        for s in &["5/move", "60", "60+0.5"] {
+            assert_eq!(*s, s.parse::<TimeControl>().unwrap().to_string());
+        }
+        for s in &["", "0", "-1", "a+1", "1+", "/move", "0/move"] {
+            assert!(s.parse::<TimeControl>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn clocks() {
+        let secs = Duration::from_secs;
+        let mut clocks = Clocks::new("10+2".parse().unwrap());
+        assert!(clocks.record(0, secs(3)));
+        assert_eq!([secs(9), secs(10)], clocks.remaining);
+        let go = clocks.go_params();
+        assert_eq!(Some(Clock { remaining: secs(10), increment: secs(2), move_time: None }), go.clock(1));
+        assert!(!clocks.record(1, secs(11)));
+
+        let mut clocks = Clocks::new("2/move".parse().unwrap());
+        assert!(clocks.record(0, secs(2)));
+        assert_eq!([secs(2), secs(2)], clocks.remaining);
+        assert!(!clocks.record(0, Duration::from_millis(2001)));
+        assert_eq!(Some(secs(2)), clocks.go_params().clock(0).unwrap().move_time);
+    }
+}
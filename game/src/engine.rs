@@ -0,0 +1,162 @@
+use model::*;
+use std::sync::mpsc;
+use std::io::{BufRead, Write};
+use crate::referee::ForfeitReason;
+
+/// How the referee talks to a program playing the game.
+#[derive(Clone, Debug)]
+pub enum Protocol {
+    /// The original protocol: `start` or the opponent's move on one line,
+    /// answered by a move.
+    Legacy,
+    /// The engine protocol described in `model::protocol`, with options
+    /// sent to the engine before the first game.
+    Engine(Vec<(String,String)>),
+}
+
+/// Request from the referee to the thread talking to a player.
+#[derive(Clone, Debug)]
+pub enum Request {
+    NewGame,
+    /// The opponent's move, or None for the first move of the game,
+    /// and the clocks.
+    Move(Option<Move>, GoParams),
+}
+
+/// Answer from the thread talking to a player.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Answer {
+    /// The player is ready for a new game.
+    Ready,
+    /// The player's move, with the last search information it reported.
+    Move(Move, Option<SearchInfo>),
+}
+
+pub type Reply = Result<Answer,ForfeitReason>;
+
+/// Write a line in one piece, so that lines written by the referee
+/// (see `RemotePlayer`) are not mixed with the protocol thread's lines.
+fn write_line(w :&mut impl Write, line :&str) -> Result<(),ForfeitReason> {
+    w.write_all(format!("{}\n", line).as_bytes()).and_then(|_| w.flush()).map_err(|_| ForfeitReason::Disconnected)
+}
+
+/// Read the next non-empty line.
+fn read_line(r :&mut impl BufRead) -> Result<String,ForfeitReason> {
+    loop {
+        let mut line = String::new();
+        match r.read_line(&mut line) {
+            Ok(0) | Err(_) => return Err(ForfeitReason::Disconnected),
+            Ok(_) => {},
+        }
+        let line = line.trim();
+        if !line.is_empty() { return Ok(line.to_string()); }
+    }
+}
+
+/// Run a protocol on a reader and writer connected to the player, answering
+/// requests on `output`. When the protocol fails, the reason is sent
+/// as the answer instead.
+pub fn run_protocol(protocol :&Protocol, r :impl BufRead, w :impl Write,
+                    input :&mpsc::Receiver<Request>, output :&mpsc::Sender<Reply>) {
+    let result = match protocol {
+        Protocol::Legacy => legacy_protocol(r, w, input, output),
+        Protocol::Engine(options) => engine_protocol(r, w, options, input, output),
+    };
+    if let Err(reason) = result {
+        let _ = output.send(Err(reason));
+    }
+}
+
+/// The original line protocol. New games are not supported, so the
+/// player must be restarted for each game, and clocks are not sent.
+pub fn legacy_protocol(mut r :impl BufRead, mut w :impl Write,
+                       input :&mpsc::Receiver<Request>, output :&mpsc::Sender<Reply>) -> Result<(),ForfeitReason> {
+    while let Ok(request) = input.recv() {
+        let mv = match request {
+            Request::NewGame => {
+                if output.send(Ok(Answer::Ready)).is_err() { break; }
+                continue;
+            },
+            Request::Move(mv, _) => mv,
+        };
+        match mv {
+            None => write_line(&mut w, "start")?,
+            Some(mv) => write_line(&mut w, &printer(&mv))?,
+        };
+
+        let line = read_line(&mut r)?;
+        let mv = parse(&line).map_err(|e|
+            ForfeitReason::InvalidOutput(format!("could not parse move {:?}: {}", line, e)))?;
+        if output.send(Ok(Answer::Move(mv, None))).is_err() { break; }
+    }
+    Ok(())
+}
+
+pub fn send(w :&mut impl Write, command :Command) -> Result<(),ForfeitReason> {
+    write_line(w, &command.to_string())
+}
+
+fn receive(r :&mut impl BufRead) -> Result<Response,ForfeitReason> {
+    let line = read_line(r)?;
+    line.parse().map_err(|e|
+        ForfeitReason::InvalidOutput(format!("could not parse response {:?}: {}", line, e)))
+}
+
+/// Referee side of the engine protocol: handshake and options, then
+/// `newgame` and `isready` for each new game, and a `position` and `go`
+/// for each move request. The referee sends `stop` and `quit` itself
+/// (see `RemotePlayer`), since this thread is waiting for the engine then.
+pub fn engine_protocol(mut r :impl BufRead, mut w :impl Write, options :&[(String,String)],
+                       input :&mpsc::Receiver<Request>, output :&mpsc::Sender<Reply>) -> Result<(),ForfeitReason> {
+    send(&mut w, Command::Quoridor)?;
+    let mut name = "engine".to_string();
+    loop {
+        match receive(&mut r)? {
+            Response::Id { key, value } if key == "name" => { name = value; },
+            Response::Protocol(version) if version != PROTOCOL_VERSION => {
+                return Err(ForfeitReason::InvalidOutput(format!("unsupported protocol version {}", version)));
+            },
+            Response::Ok => break,
+            _ => {},
+        }
+    }
+    eprintln!("Engine {:?} connected", name);
+
+    for (option, value) in options {
+        send(&mut w, Command::SetOption { name: option.clone(), value: value.clone() })?;
+    }
+
+    let mut moves = Vec::new();
+    while let Ok(request) = input.recv() {
+        match request {
+            Request::NewGame => {
+                moves.clear();
+                send(&mut w, Command::NewGame)?;
+                send(&mut w, Command::IsReady)?;
+                while receive(&mut r)? != Response::ReadyOk {}
+                if output.send(Ok(Answer::Ready)).is_err() { break; }
+                continue;
+            },
+            Request::Move(mv, clocks) => {
+                if let Some(mv) = mv { moves.push(mv); }
+                send(&mut w, Command::Position(moves.clone()))?;
+                send(&mut w, Command::Go(clocks))?;
+            },
+        }
+        let mut last_info = None;
+        let mv = loop {
+            match receive(&mut r)? {
+                Response::BestMove(mv) => break mv,
+                Response::Info(info) => {
+                    eprintln!("{}: {}", name, info);
+                    last_info = Some(info);
+                },
+                response => return Err(ForfeitReason::InvalidOutput(
+                        format!("unexpected response {:?}", response.to_string()))),
+            }
+        };
+        moves.push(mv);
+        if output.send(Ok(Answer::Move(mv, last_info))).is_err() { break; }
+    }
+    Ok(())
+}
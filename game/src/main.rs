@@ -1,86 +1,20 @@
+mod clock;
+mod engine;
+mod stats;
+mod tournament;
+mod referee;
+
 use model::*;
+use clock::*;
+use engine::Protocol;
+use referee::*;
+use tournament::*;
+use stats::SprtDecision;
 
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::{thread,time};
-use std::io::{BufRead, Write};
-
-pub struct ChannelPlayer {
-    pub tx :mpsc::Sender<Option<Move>>,
-    pub rx :mpsc::Receiver<Move>,
-}
-
-impl Player for ChannelPlayer {
-    fn mv(&mut self, mv:Option<Move>) -> Move {
-        self.tx.send(mv).unwrap();
-        self.rx.recv().unwrap()
-    }
-
-    fn reset(&mut self) {}
-}
-
-impl ChannelPlayer {
-    pub fn from_thread<F2: FnOnce() + Send + 'static>
-        (f :impl FnOnce(mpsc::Receiver<Option<Move>>, mpsc::Sender<Move>) -> F2) -> Self {
-        let (input_tx,input_rx) = mpsc::channel();
-        let (output_tx,output_rx) = mpsc::channel();
-        thread::spawn(f(input_rx, output_tx));
-        ChannelPlayer {
-            tx: input_tx,
-            rx: output_rx,
-        }
-    }
-}
-
-pub fn protocol(mut r :impl BufRead, mut w: impl Write, 
-                input :&mpsc::Receiver<Option<Move>>, output :&mpsc::Sender<Move>) {
-        loop {
-            // Send move
-            let mv = input.recv().unwrap();
-            match mv {
-                None => w.write("start\n".as_bytes()).unwrap(),
-                Some(mv) => w.write(format!("{}\n",printer(&mv)).as_bytes()).unwrap(),
-            };
-
-            // Receive answer
-            let mut line :String = String::new();
-            let mv_str = r.read_line(&mut line).unwrap();
-            let mv = parse(line.lines().next().unwrap()).unwrap();
-            output.send(mv).unwrap();
-        }
-}
-
-pub fn stdio_player(program :String) -> impl Player {
-    ChannelPlayer::from_thread(|input,output| move || {
-        use std::process::*;
-        let args = shell_words::split(&program).unwrap();
-        eprintln!("Launching process {:?}", args);
-        let proc = Command::new(&args[0]).args(&args[1..])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn().unwrap();
-        let mut reader = std::io::BufReader::new(proc.stdout.unwrap());
-        protocol(reader, proc.stdin.unwrap(), &input, &output);
-    })
-}
-
-pub fn net_player(port :u32) -> impl Player {
-    ChannelPlayer::from_thread(|input,output| move || {
-        let addr = format!("0.0.0.0:{}",port);
-        let listener = std::net::TcpListener::bind(&addr).unwrap();
-        eprintln!("Waiting for connection on {}", addr);
-        match listener.accept() {
-            Ok((stream,addr)) => {
-                eprintln!("New connection: {}", addr);
-                let mut reader = std::io::BufReader::new(&stream);
-                protocol(reader, &stream, &input, &output);
-            },
-            Err(_) => { panic!() },
-        };
-    })
-}
-
 
 static INDEX_HTML :&'static [u8] = include_bytes!("index.html");
 static D3_JS :&'static [u8] = include_bytes!("d3/d3.js");
@@ -96,36 +30,41 @@ impl ws::Handler for ServerThread {
     fn on_message(&mut self, msg :ws::Message) -> ws::Result<()> { 
         eprintln!("MESSAGE from web client {:?}", msg);
         if let ws::Message::Text(txt) = msg {
-            if let Ok(mv) = parse(&txt) {
-                let current_board = self.current_board.lock().unwrap();
-                if current_board.player == 0 {
-                    if let Some(ch) = &self.player1_channel {
-                        if current_board.is_valid_move(&mv) {
-                            ch.send(mv).unwrap();
+            match parse(&txt) {
+                Ok(mv) => {
+                    let current_board = self.current_board.lock().unwrap();
+                    if current_board.player() == 0 {
+                        if let Some(ch) = &self.player1_channel {
+                            match current_board.check_move(&mv) {
+                                Ok(()) => { ch.send(mv).unwrap(); },
+                                Err(e) => {
+                                    self.out.send(serde_json::to_string_pretty(&serde_json::json!({
+                                        "message": format!("Invalid move: {}", e),
+                                    })).unwrap()).unwrap();
+                                },
+                            }
                         } else {
-                            self.out.send(serde_json::to_string_pretty(&serde_json::json!({
-                                "message": "Invalid move",
-                            })).unwrap()).unwrap();
+                            eprintln!("Not expecting this player to move.");
                         }
-                    } else {
-                        eprintln!("Not expecting this player to move.");
                     }
-                }
-                if current_board.player == 1 {
-                    if let Some(ch) = &self.player2_channel {
-                        if current_board.is_valid_move(&mv) {
-                            ch.send(mv).unwrap();
+                    if current_board.player() == 1 {
+                        if let Some(ch) = &self.player2_channel {
+                            match current_board.check_move(&mv) {
+                                Ok(()) => { ch.send(mv).unwrap(); },
+                                Err(e) => {
+                                    self.out.send(serde_json::to_string_pretty(&serde_json::json!({
+                                        "message": format!("Invalid move: {}", e),
+                                    })).unwrap()).unwrap();
+                                },
+                            }
                         } else {
-                            self.out.send(serde_json::to_string_pretty(&serde_json::json!({
-                                "message": "Invalid move",
-                            })).unwrap()).unwrap();
+                            eprintln!("Not expecting this player to move.");
                         }
-                    } else {
-                        eprintln!("Not expecting this player to move.");
                     }
-                }
-            } else {
-                eprintln!("Could not parse move.");
+                },
+                Err(e) => {
+                    eprintln!("Could not parse move {:?}: {}", txt, e);
+                },
             }
         } else {
             eprintln!("Received unexpected message type.");
@@ -143,12 +82,18 @@ impl ws::Handler for ServerThread {
     }
 }
 
+/// Called after each move with the board and the clocks, if the game has a time control.
+type LogMove = Box<dyn FnMut(Move, &Board, Option<&Clocks>)>;
+
 struct Opts {
     show_gui: bool,
     verbose: bool,
-    p1: Box<Player>,
-    p2: Box<Player>,
-    log_move: Box<FnMut(Move,&Board)>,
+    p1: Box<dyn Contestant>,
+    p2: Box<dyn Contestant>,
+    names: [String;2],
+    game: GameConfig,
+    record: Option<String>,
+    log_move: LogMove,
 }
 
 fn get_opts(board :Arc<Mutex<Board>>) -> Result<Opts,&'static str> {
@@ -156,10 +101,16 @@ fn get_opts(board :Arc<Mutex<Board>>) -> Result<Opts,&'static str> {
 
     let mut show_gui = false;
     let mut verbose = false;
-    let mut p1 :Option<Box<Player>> = None;
-    let mut p2 :Option<Box<Player>> = None;
+    let mut p1 :Option<Box<dyn Contestant>> = None;
+    let mut p2 :Option<Box<dyn Contestant>> = None;
+    let mut names = Vec::new();
+    let mut record = None;
+    let mut game = GameConfig { timeout: Some(time::Duration::from_secs(60)), .. Default::default() };
     let mut web_players = (None,None);
     let mut log_move = None;
+    // Protocol settings for the next program player.
+    let mut legacy = false;
+    let mut engine_options = Vec::new();
 
     let mut args = env::args();
     let _exe_name = args.next();
@@ -167,37 +118,74 @@ fn get_opts(board :Arc<Mutex<Board>>) -> Result<Opts,&'static str> {
         match arg.as_str() {
             "-g" => { show_gui = true; },
             "-v" => { verbose = true; },
+            "--legacy" => { legacy = true; },
+            "--record" => { record = Some(args.next().ok_or("Record requires a file name")?); },
+            "--timeout" => {
+                let seconds :f64 = args.next().ok_or("Timeout requires a number of seconds")?
+                    .parse().map_err(|_| "Could not parse timeout.")?;
+                game.timeout = if seconds > 0.0 { Some(time::Duration::from_secs_f64(seconds)) } else { None };
+            },
+            "--max-plies" => {
+                let plies :usize = args.next().ok_or("Max plies requires a number")?
+                    .parse().map_err(|_| "Could not parse max plies.")?;
+                game.max_plies = if plies > 0 { Some(plies) } else { None };
+            },
+            "--time-control" => {
+                let control = args.next().ok_or("Time control requires an argument")?;
+                game.time_control = Some(control.parse::<TimeControl>().map_err(|e| {
+                    eprintln!("{}", e);
+                    "Time control must be <seconds>/move, <seconds> or <seconds>+<increment>"
+                })?);
+            },
+            "-o" => {
+                let option :String = args.next().ok_or("Option requires name=value")?;
+                let mut parts = option.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) => engine_options.push((name.to_string(), value.to_string())),
+                    _ => return Err("Option must be on the form name=value"),
+                }
+            },
             "cli" => {
-                if p1.is_none() { p1 = Some(Box::new(CLIPlayer {name: "Player1" })); }
-                else if p2.is_none() { p2 = Some(Box::new(CLIPlayer {name: "Player2"})); }
+                if p1.is_none() { p1 = Some(Box::new(LocalPlayer(Box::new(CLIPlayer {name: "Player1" })))); }
+                else if p2.is_none() { p2 = Some(Box::new(LocalPlayer(Box::new(CLIPlayer {name: "Player2"})))); }
                 else { return Err("More than two players requested."); }
+                names.push("cli".to_string());
             },
             "gui" => {
                 if p1.is_none() { 
                     let (tx,rx) = mpsc::channel();
                     web_players.0 = Some(tx);
-                    p1 = Some(Box::new(WSPlayer {rx}));
+                    p1 = Some(Box::new(LocalPlayer(Box::new(WSPlayer {rx}))));
 
                 }
                 else if p2.is_none() { 
                     let (tx,rx) = mpsc::channel();
                     web_players.1 = Some(tx);
-                    p2 = Some(Box::new(WSPlayer {rx}));
+                    p2 = Some(Box::new(LocalPlayer(Box::new(WSPlayer {rx}))));
                 }
                 else { return Err("More than two players requested."); }
+                names.push("gui".to_string());
             },
             "run" => {
                 let program : String = args.next().ok_or("Run program requires argument")?;
-                if p1.is_none() { p1 = Some(Box::new(stdio_player(program))); }
-                else if p2.is_none() { p2 = Some(Box::new(stdio_player(program))); }
+                let protocol = if legacy { Protocol::Legacy }
+                    else { Protocol::Engine(std::mem::take(&mut engine_options)) };
+                names.push(program.clone());
+                let player = Box::new(RemotePlayer::new(Connection::Program(program), protocol));
+                if p1.is_none() { p1 = Some(player); }
+                else if p2.is_none() { p2 = Some(player); }
                 else { return Err("More than two players requested."); }
             },
             "net" => {
                 let port :u32 = args.next().ok_or("Net program requires port")?
                     .parse::<u32>().map_err(|_| "Could not parse port number for net player.")?;
-                if p1.is_none() { p1 = Some(Box::new(net_player(port))); }
-                else if p2.is_none() { p2 = Some(Box::new(net_player(port))); }
+                let protocol = if legacy { Protocol::Legacy }
+                    else { Protocol::Engine(std::mem::take(&mut engine_options)) };
+                let player = Box::new(RemotePlayer::new(Connection::Net(port), protocol));
+                if p1.is_none() { p1 = Some(player); }
+                else if p2.is_none() { p2 = Some(player); }
                 else { return Err("More than two players requested."); }
+                names.push(format!("net {}", port));
             },
             x => { eprintln!("Unrecognized arg: {}", x); return Err("Unrecognized argument"); },
         }
@@ -212,7 +200,7 @@ fn get_opts(board :Arc<Mutex<Board>>) -> Result<Opts,&'static str> {
     }
 
     if show_gui {
-        log_move = Some(start_ws(board, web_players.0, web_players.1));
+        log_move = Some(start_ws(board, game.time_control, web_players.0, web_players.1));
     }
 
     Ok(Opts {
@@ -220,18 +208,35 @@ fn get_opts(board :Arc<Mutex<Board>>) -> Result<Opts,&'static str> {
         verbose: verbose,
         p1: p1.unwrap(),
         p2: p2.unwrap(),
-        log_move: log_move.unwrap_or(Box::new(|_,_| {})),
+        names: [names[0].clone(), names[1].clone()],
+        game,
+        record,
+        log_move: log_move.unwrap_or(Box::new(|_,_,_| {})),
     })
 }
 
 
+/// The clocks for the GUI, in milliseconds, with the clock that is running.
+fn clocks_json(clocks :Option<&Clocks>, board :&Board) -> serde_json::Value {
+    match clocks {
+        Some(c) => serde_json::json!({
+            "remaining": [c.remaining[0].as_millis() as u64, c.remaining[1].as_millis() as u64],
+            "running": if board.get_winner().is_none() { Some(board.player()) } else { None },
+        }),
+        None => serde_json::Value::Null,
+    }
+}
+
 pub fn start_ws(board :Arc<Mutex<Board>>, 
+                time_control :Option<TimeControl>,
                 p1 :Option<mpsc::Sender<Move>>, 
-                p2 :Option<mpsc::Sender<Move>>) -> Box<FnMut(Move, &Board)> {
+                p2 :Option<mpsc::Sender<Move>>) -> LogMove {
 
     let port = 9033;
     let addr = format!("localhost:{}", port);
     let board_ws = board.clone();
+    let clocks = Arc::new(Mutex::new(time_control.map(Clocks::new)));
+    let clocks_ws = clocks.clone();
 
     let http = { 
         let (p1,p2) = (p1.clone(),p2.clone());
@@ -239,11 +244,12 @@ pub fn start_ws(board :Arc<Mutex<Board>>,
             eprintln!("New connection ({:?})", out.connection_id());
             let current_board = board_ws.lock().unwrap();
             let b = &*current_board;
-            let gui_send_move = (b.player == 0 && p1.is_some()) || (b.player == 1 && p2.is_some());
+            let gui_send_move = (b.player() == 0 && p1.is_some()) || (b.player() == 1 && p2.is_some());
             out.send(
                 serde_json::to_string_pretty(&serde_json::json!({
                     "board": serde_json::to_value(b).unwrap(),
                     "send_move": gui_send_move,
+                    "clocks": clocks_json(clocks_ws.lock().unwrap().as_ref(), b),
                 })).unwrap()).unwrap();
             ServerThread {
                 current_board: board_ws.clone(),
@@ -256,15 +262,17 @@ pub fn start_ws(board :Arc<Mutex<Board>>,
 
     let broadcaster = http.broadcaster();
     let board_log = board.clone();
-    let log_move :Box<FnMut(Move,&Board)>= Box::new(move |m,b| {
+    let log_move :LogMove = Box::new(move |m,b,c| {
         eprintln!("executed MOVE {:?} -- sending to ws", m);
         let mut board = board_log.lock().unwrap();
-        *board = b.clone();
-        let gui_send_move = (b.player == 0 && p1.is_some()) || (b.player == 1 && p2.is_some());
+        *board = *b;
+        *clocks.lock().unwrap() = c.cloned();
+        let gui_send_move = (b.player() == 0 && p1.is_some()) || (b.player() == 1 && p2.is_some());
         broadcaster.send(
             serde_json::to_string_pretty(&serde_json::json!({
                 "board": serde_json::to_value(b).unwrap(),
                 "send_move": gui_send_move,
+                "clocks": clocks_json(c, b),
             })).unwrap()).unwrap();
     });
 
@@ -286,8 +294,155 @@ pub fn start_ws(board :Arc<Mutex<Board>>,
 }
 
 
+const TOURNAMENT_USAGE :&str = "\
+Usage: game match [options] <games> <program> <program>
+       game roundrobin [options] <games per pair> <program> <program> [program ...]
+       game gauntlet [options] <games per opponent> <program> <program> [program ...]
+
+Options:
+  --concurrency <n>     games played at the same time (default 1)
+  --time-control <tc>   <seconds>/move, <seconds> or <seconds>+<increment>
+  --timeout <seconds>   time limit for each move without a time control
+                        (default 60, 0 for none)
+  --max-plies <n>       plies after which a game is drawn (default 400)
+  --record <file>       append a record of each game to the file
+  --sprt <params>       stop a match when a sequential probability ratio test
+                        of the first program against the second is decided;
+                        elo0=<elo>,elo1=<elo>[,alpha=<p>][,beta=<p>]
+  --name <name>         name of the next program in the results
+  --legacy              use the legacy protocol for the following programs
+  -o <name>=<value>     engine option for the next program";
+
+/// Play a match or tournament between engine programs, and print the results.
+fn tournament_command(format :Format, args :&[String]) -> Result<(),String> {
+    let mut config = TournamentConfig {
+        format, games: 0, concurrency: 1,
+        game: GameConfig {
+            time_control: None, timeout: Some(time::Duration::from_secs(60)), max_plies: Some(400),
+        },
+        sprt: None,
+    };
+    let mut games = None;
+    let mut entrants = Vec::new();
+    // Settings for the next program.
+    let mut legacy = false;
+    let mut engine_options = Vec::new();
+    let mut name = None;
+    let mut record = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |option :&str| args.next().ok_or_else(|| format!("{} requires a value", option));
+        match arg.as_str() {
+            "--concurrency" => {
+                config.concurrency = value(arg)?.parse().map_err(|e| format!("Could not parse --concurrency: {}", e))?;
+                if config.concurrency == 0 { return Err("--concurrency must be at least 1".to_string()); }
+            },
+            "--time-control" => { config.game.time_control = Some(value(arg)?.parse()?); },
+            "--timeout" => {
+                let seconds :f64 = value(arg)?.parse().map_err(|e| format!("Could not parse --timeout: {}", e))?;
+                config.game.timeout = if seconds > 0.0 { Some(time::Duration::from_secs_f64(seconds)) } else { None };
+            },
+            "--max-plies" => {
+                let plies :usize = value(arg)?.parse().map_err(|e| format!("Could not parse --max-plies: {}", e))?;
+                config.game.max_plies = if plies > 0 { Some(plies) } else { None };
+            },
+            "--record" => { record = Some(value(arg)?.clone()); },
+            "--sprt" => { config.sprt = Some(value(arg)?.parse()?); },
+            "--name" => { name = Some(value(arg)?.clone()); },
+            "--legacy" => { legacy = true; },
+            "-o" => {
+                let option = value(arg)?;
+                let mut parts = option.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) => engine_options.push((name.to_string(), value.to_string())),
+                    _ => return Err(format!("Option {:?} must be on the form name=value", option)),
+                }
+            },
+            "--help" | "-h" => {
+                println!("{}", TOURNAMENT_USAGE);
+                return Ok(());
+            },
+            x if x.starts_with('-') => return Err(format!("Unrecognized argument {:?}", x)),
+            x if games.is_none() => {
+                games = Some(x.parse::<u32>().map_err(|e| format!("Could not parse number of games {:?}: {}", x, e))?);
+            },
+            program => {
+                let protocol = if legacy { Protocol::Legacy }
+                    else { Protocol::Engine(std::mem::take(&mut engine_options)) };
+                let name = name.take().unwrap_or_else(|| program.to_string());
+                entrants.push(Entrant { name, program: program.to_string(), protocol });
+            },
+        }
+    }
+
+    config.games = games.ok_or_else(|| format!("Missing number of games\n\n{}", TOURNAMENT_USAGE))?;
+    match (format, entrants.len()) {
+        (Format::Match, 2) => {},
+        (Format::Match, _) => return Err("A match is between two programs".to_string()),
+        (_, n) if n < 2 => return Err("A tournament needs at least two programs".to_string()),
+        _ => {},
+    }
+    if config.sprt.is_some() && format != Format::Match {
+        return Err("--sprt can only be used in a match".to_string());
+    }
+
+    let scheduled = schedule(format, entrants.len(), config.games).len();
+    let mut played = 0;
+    let standings = run_tournament(&entrants, &config, &mut |pairing, result, _| {
+        let names = [entrants[pairing.players[0]].name.as_str(), entrants[pairing.players[1]].name.as_str()];
+        played += 1;
+        let outcome = match (&result.termination, result.winner) {
+            (Termination::Forfeit(reason), Some(w)) => format!("{} won by forfeit, {} {}", names[w], names[1 - w], reason),
+            (_, Some(w)) => format!("{} won in {} plies", names[w], result.moves.len()),
+            (_, None) => format!("draw after {} plies", result.moves.len()),
+        };
+        println!("Game {} ({}/{}): {} vs {}: {}", pairing.game + 1, played, scheduled, names[0], names[1], outcome);
+        if let Some(path) = &record {
+            let mut game = game_record(result, names, &config.game);
+            game.set_tag("Game", &(pairing.game + 1).to_string());
+            if let Err(e) = game.append(path) {
+                eprintln!("Error: {}", e);
+                return false;
+            }
+        }
+        true
+    });
+    println!();
+    print!("{}", standings);
+
+    if let Some(sprt) = &config.sprt {
+        let (llr, decision) = standings.scores[0][1].sprt(sprt);
+        let (lower, upper) = sprt.bounds();
+        let conclusion = match decision {
+            SprtDecision::AcceptH1 => format!("H1 accepted, {} is stronger", entrants[0].name),
+            SprtDecision::AcceptH0 => format!("H0 accepted, {} is not stronger", entrants[0].name),
+            SprtDecision::Continue => "inconclusive".to_string(),
+        };
+        println!();
+        println!("SPRT {}: LLR {:.2} ({:.2}, {:.2}), {}", sprt, llr, lower, upper, conclusion);
+    }
+    Ok(())
+}
+
 fn main() {
     use std::env;
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let format = match args.first().map(|a| a.as_str()) {
+        Some("match") => Some(Format::Match),
+        Some("roundrobin") => Some(Format::RoundRobin),
+        Some("gauntlet") => Some(Format::Gauntlet),
+        _ => None,
+    };
+    if let Some(format) = format {
+        if let Err(e) = tournament_command(format, &args[1..]) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     eprintln!("Quoridor");
 
     let current_board : Arc<Mutex<Board>> = Arc::new(Mutex::new(Default::default()));
@@ -296,12 +451,17 @@ fn main() {
         Err(e) => { eprintln!("Error: {}", e); return; },
     };
 
-    match play_dyn(&mut *opts.p1, &mut *opts.p2, &mut *opts.log_move) {
-        Ok(0) => eprintln!("Blue player won!"),
-        Ok(1) => eprintln!("Red player won!"),
-        Err(0) => eprintln!("Blue player won by move error!"),
-        Err(1) => eprintln!("Red player won by move error!"),
-        _ => panic!(),
+    let names = ["Blue", "Red"];
+    let result = play_game([&mut *opts.p1, &mut *opts.p2], &opts.game, &mut *opts.log_move);
+    match (&result.termination, result.winner) {
+        (Termination::Forfeit(reason), Some(winner)) => eprintln!("{} player won by forfeit! {} player {}",
+                                                                  names[winner], names[1-winner], reason),
+        (_, Some(winner)) => eprintln!("{} player won!", names[winner]),
+        (_, None) => eprintln!("The game was drawn after {} plies.", result.moves.len()),
+    }
+    if let Some(path) = &opts.record {
+        let record = game_record(&result, [&opts.names[0], &opts.names[1]], &opts.game);
+        if let Err(e) = record.append(path) { eprintln!("Error: {}", e); }
     }
 }
 
@@ -310,8 +470,13 @@ impl Player for CLIPlayer {
     fn mv(&mut self, mv :Option<Move>) -> Move {
         eprintln!("{}: received {:?}", self.name, mv);
         use std::io::{self, BufRead};
-        let line1 = io::stdin().lock().lines().next().unwrap().unwrap();
-        parse(&line1).unwrap()
+        loop {
+            let line1 = io::stdin().lock().lines().next().unwrap().unwrap();
+            match parse(&line1) {
+                Ok(mv) => return mv,
+                Err(e) => eprintln!("{}: could not parse move {:?}: {}", self.name, line1, e),
+            }
+        }
     }
 
     fn reset(&mut self) {}
@@ -327,42 +492,3 @@ impl Player for WSPlayer {
     }
     fn reset(&mut self) {}
 }
-
-/// Play two players against each other, returning the color
-/// of the player that won.
-fn play<A: Player, B: Player>(p1 :&mut A, p2: &mut B, mut log :Box<FnMut(Move,&Board)>) -> Result<usize,usize> {
-    let mut board : Board = Default::default();
-
-    // First move
-    let mut last_move = p1.mv(None);
-    loop {
-        board.integrate(last_move).map_err(|_| 1usize)?; // Lose by foul
-        log(last_move, &board);
-        if let Some(winner) = board.get_winner() { return Ok(winner); }
-
-        last_move = p2.mv(Some(last_move));
-        board.integrate(last_move).map_err(|_| 0usize)?; // Lose by foul
-        log(last_move, &board);
-        if let Some(winner) = board.get_winner() { return Ok(winner); }
-
-        last_move = p1.mv(Some(last_move));
-    }
-}
-
-
-fn play_dyn<'a> (p1 :&'a mut dyn Player, 
-                 p2 :&'a mut dyn Player, 
-                 log :&mut dyn FnMut(Move, &Board)) -> Result <usize,usize> {
-    let mut board :Board = Default::default();
-    let mut last_move :Option<Move> = None;
-    let (mut current_player,mut next_player) = ((p1,0usize),(p2,1usize)); // Player 1 starts.
-    loop {
-        last_move = Some(current_player.0.mv(last_move));
-        board.integrate(last_move.unwrap()).map_err(|_| next_player.1)?;
-        log(last_move.unwrap(), &board);
-        if let Some(winner) = board.get_winner() { return Ok(winner); }
-        std::mem::swap(&mut current_player, &mut next_player);
-    }
-}
-
-
@@ -0,0 +1,591 @@
+use model::*;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::thread;
+use crate::engine::*;
+use crate::clock::*;
+
+/// Reason a player lost the game without it being decided on the board.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForfeitReason {
+    /// The player could not be started.
+    StartFailed(String),
+    /// The player's program exited or closed the connection.
+    Disconnected,
+    /// The player wrote something that is not valid in the protocol.
+    InvalidOutput(String),
+    IllegalMove(Move, IllegalMove),
+    /// The player did not move within the time limit.
+    Timeout(Duration),
+    /// The player's clock ran out.
+    OutOfTime,
+}
+
+impl std::fmt::Display for ForfeitReason {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ForfeitReason::StartFailed(e) => write!(f, "could not be started: {}", e),
+            ForfeitReason::Disconnected => write!(f, "crashed or disconnected"),
+            ForfeitReason::InvalidOutput(e) => write!(f, "protocol error: {}", e),
+            ForfeitReason::IllegalMove(mv, e) => write!(f, "illegal move {}: {}", printer(mv), e),
+            ForfeitReason::Timeout(t) => write!(f, "no move within {:.1}s", t.as_secs_f64()),
+            ForfeitReason::OutOfTime => write!(f, "ran out of time"),
+        }
+    }
+}
+
+/// How a game ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Termination {
+    /// The winner reached the goal row.
+    Normal,
+    /// The loser forfeited the game.
+    Forfeit(ForfeitReason),
+    /// The game was drawn by reaching the move limit.
+    MoveLimit,
+}
+
+impl std::fmt::Display for Termination {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Termination::Normal => write!(f, "normal"),
+            Termination::Forfeit(reason) => write!(f, "forfeit, {}", reason),
+            Termination::MoveLimit => write!(f, "move limit"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameResult {
+    /// The winning player, or `None` for a draw.
+    pub winner :Option<usize>,
+    pub termination :Termination,
+    pub moves :Vec<Move>,
+    /// Score reported with each move, from the point of view of the player making it.
+    pub scores :Vec<Option<f32>>,
+}
+
+/// A player as seen by the referee, which may fail to produce a move.
+pub trait Contestant {
+    /// Prepare for a new game, starting the player if necessary. The time
+    /// limit is for the player to become ready.
+    fn new_game(&mut self, timeout :Option<Duration>) -> Result<(),ForfeitReason>;
+    /// Ask for a move, given the opponent's move and the clocks. The time
+    /// limit is only enforced for players that run in another thread or process.
+    fn mv(&mut self, mv :Option<Move>, timeout :Option<Duration>, clocks :&GoParams) -> Result<Move,ForfeitReason>;
+    /// Stop the player after it has forfeited the game, unless it is ready to play again.
+    fn abort(&mut self) {}
+    /// Information about the search for the last move, if the player reported any.
+    fn search_info(&self) -> Option<SearchInfo> { None }
+}
+
+/// A player in the referee's own process, such as a human player.
+pub struct LocalPlayer(pub Box<dyn Player>);
+
+impl Contestant for LocalPlayer {
+    fn new_game(&mut self, _timeout :Option<Duration>) -> Result<(),ForfeitReason> {
+        self.0.reset();
+        Ok(())
+    }
+
+    fn mv(&mut self, mv :Option<Move>, _timeout :Option<Duration>, _clocks :&GoParams) -> Result<Move,ForfeitReason> {
+        Ok(self.0.mv(mv))
+    }
+}
+
+/// Where a remote player runs.
+#[derive(Clone, Debug)]
+pub enum Connection {
+    /// A program started with a command line, using its standard input and output.
+    Program(String),
+    /// A program that connects to this TCP port.
+    Net(u32),
+}
+
+/// Time for an engine to answer `stop` with its move, or to exit after `quit`.
+const STOP_GRACE :Duration = Duration::from_millis(500);
+
+/// Writer to a player, shared between the protocol thread and the referee.
+/// Empty until a net player has connected.
+#[derive(Clone, Default)]
+struct SharedWriter(Arc<Mutex<Option<Box<dyn std::io::Write + Send>>>>);
+
+impl SharedWriter {
+    fn new(w :impl std::io::Write + Send + 'static) -> SharedWriter {
+        SharedWriter(Arc::new(Mutex::new(Some(Box::new(w)))))
+    }
+}
+
+impl std::io::Write for SharedWriter {
+    fn write(&mut self, buf :&[u8]) -> std::io::Result<usize> {
+        match &mut *self.0.lock().unwrap() {
+            Some(w) => w.write(buf),
+            None => Err(std::io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut *self.0.lock().unwrap() {
+            Some(w) => w.flush(),
+            None => Err(std::io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+struct Running {
+    tx :mpsc::Sender<Request>,
+    rx :mpsc::Receiver<Reply>,
+    child :Option<std::process::Child>,
+    /// The connection to a net player, once it has connected.
+    stream :Arc<Mutex<Option<std::net::TcpStream>>>,
+    /// Writer used by the protocol thread, for the referee to send `stop` and `quit`.
+    writer :SharedWriter,
+    /// The player has answered every request, so that it can play on.
+    idle :bool,
+}
+
+/// A player in another process, talking to the referee through a protocol.
+/// The protocol runs in a separate thread, so that the referee can give up
+/// on a player that does not answer in time. An engine that runs out of time
+/// is sent `stop`, and keeps running if it answers. A player that forfeits
+/// without answering is stopped, and started again for the next game.
+pub struct RemotePlayer {
+    connection :Connection,
+    protocol :Protocol,
+    running :Option<Running>,
+    /// A move has been requested since the last new game.
+    in_game :bool,
+    /// Search information sent with the last move.
+    info :Option<SearchInfo>,
+}
+
+impl RemotePlayer {
+    pub fn new(connection :Connection, protocol :Protocol) -> RemotePlayer {
+        RemotePlayer { connection, protocol, running: None, in_game: false, info: None }
+    }
+
+    fn start(&mut self) -> Result<(),ForfeitReason> {
+        let (tx, input) = mpsc::channel();
+        let (output, rx) = mpsc::channel();
+        let protocol = self.protocol.clone();
+        let stream = Arc::new(Mutex::new(None));
+        let mut writer = SharedWriter::default();
+        let child = match &self.connection {
+            Connection::Program(program) => {
+                use std::process::*;
+                let args = shell_words::split(program)
+                    .map_err(|e| ForfeitReason::StartFailed(e.to_string()))?;
+                if args.is_empty() { return Err(ForfeitReason::StartFailed("empty command".to_string())); }
+                eprintln!("Launching process {:?}", args);
+                let mut child = Command::new(&args[0]).args(&args[1..])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn().map_err(|e| ForfeitReason::StartFailed(format!("{}: {}", args[0], e)))?;
+                let reader = std::io::BufReader::new(child.stdout.take().unwrap());
+                writer = SharedWriter::new(child.stdin.take().unwrap());
+                let writer = writer.clone();
+                thread::spawn(move || run_protocol(&protocol, reader, writer, &input, &output));
+                Some(child)
+            },
+            Connection::Net(port) => {
+                let addr = format!("0.0.0.0:{}", port);
+                let listener = std::net::TcpListener::bind(&addr)
+                    .map_err(|e| ForfeitReason::StartFailed(format!("{}: {}", addr, e)))?;
+                let stream = stream.clone();
+                let writer = writer.clone();
+                thread::spawn(move || {
+                    eprintln!("Waiting for connection on {}", addr);
+                    let (connection, client) = match listener.accept() {
+                        Ok(c) => c,
+                        Err(_) => { let _ = output.send(Err(ForfeitReason::Disconnected)); return; },
+                    };
+                    eprintln!("New connection: {}", client);
+                    *stream.lock().unwrap() = connection.try_clone().ok();
+                    *writer.0.lock().unwrap() = connection.try_clone().ok()
+                        .map(|c| Box::new(c) as Box<dyn std::io::Write + Send>);
+                    let reader = std::io::BufReader::new(&connection);
+                    run_protocol(&protocol, reader, writer, &input, &output);
+                });
+                None
+            },
+        };
+        self.running = Some(Running { tx, rx, child, stream, writer, idle: true });
+        Ok(())
+    }
+
+    /// Wait for the protocol thread to answer a request.
+    fn receive(&self, timeout :Option<Duration>) -> Result<Answer,ForfeitReason> {
+        let running = self.running.as_ref().ok_or(ForfeitReason::Disconnected)?;
+        let reply = match timeout {
+            Some(t) => running.rx.recv_timeout(t).map_err(|e| match e {
+                mpsc::RecvTimeoutError::Timeout => ForfeitReason::Timeout(t),
+                mpsc::RecvTimeoutError::Disconnected => ForfeitReason::Disconnected,
+            }),
+            None => running.rx.recv().map_err(|_| ForfeitReason::Disconnected),
+        };
+        reply.and_then(|r| r)
+    }
+
+    /// Ask an engine that has not moved in time to stop searching, and wait
+    /// a short time for the move, which is ignored. An engine that answers
+    /// is idle again and does not need to be restarted.
+    fn stop_search(&mut self) {
+        if !matches!(self.protocol, Protocol::Engine(_)) { return; }
+        let running = match &mut self.running {
+            Some(running) => running,
+            None => return,
+        };
+        if send(&mut running.writer, Command::Stop).is_err() { return; }
+        if let Ok(Answer::Move(..)) = self.receive(Some(STOP_GRACE)) {
+            if let Some(running) = &mut self.running { running.idle = true; }
+        }
+    }
+
+    /// Stop the player's process or close its connection. An idle engine
+    /// is sent `quit` and given a short time to exit by itself.
+    pub fn stop(&mut self) {
+        if let Some(mut running) = self.running.take() {
+            if running.idle && matches!(self.protocol, Protocol::Engine(_))
+                && send(&mut running.writer, Command::Quit).is_ok() {
+                if let Some(child) = &mut running.child {
+                    let start = Instant::now();
+                    while let Ok(None) = child.try_wait() {
+                        if start.elapsed() > STOP_GRACE { break; }
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+            if let Some(child) = &mut running.child {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            if let Some(stream) = running.stream.lock().unwrap().take() {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }
+        }
+    }
+}
+
+impl Drop for RemotePlayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Contestant for RemotePlayer {
+    fn new_game(&mut self, timeout :Option<Duration>) -> Result<(),ForfeitReason> {
+        // Players using the legacy protocol can only play one game.
+        if self.in_game && matches!(self.protocol, Protocol::Legacy) { self.stop(); }
+        self.in_game = false;
+        if self.running.is_none() { self.start()?; }
+        let running = self.running.as_mut().unwrap();
+        running.idle = false;
+        if running.tx.send(Request::NewGame).is_err() { return Err(ForfeitReason::Disconnected); }
+        match self.receive(timeout)? {
+            Answer::Ready => {
+                self.running.as_mut().unwrap().idle = true;
+                Ok(())
+            },
+            Answer::Move(mv, _) => Err(ForfeitReason::InvalidOutput(format!("unexpected move {}", printer(&mv)))),
+        }
+    }
+
+    fn mv(&mut self, mv :Option<Move>, timeout :Option<Duration>, clocks :&GoParams) -> Result<Move,ForfeitReason> {
+        self.in_game = true;
+        let running = self.running.as_mut().ok_or(ForfeitReason::Disconnected)?;
+        running.idle = false;
+        if running.tx.send(Request::Move(mv, clocks.clone())).is_err() { return Err(ForfeitReason::Disconnected); }
+        let answer = match self.receive(timeout) {
+            Err(ForfeitReason::Timeout(t)) => {
+                self.stop_search();
+                return Err(ForfeitReason::Timeout(t));
+            },
+            answer => answer?,
+        };
+        match answer {
+            Answer::Move(mv, info) => {
+                self.running.as_mut().unwrap().idle = true;
+                self.info = info;
+                Ok(mv)
+            },
+            Answer::Ready => Err(ForfeitReason::InvalidOutput("expected a move".to_string())),
+        }
+    }
+
+    fn abort(&mut self) {
+        if !self.running.as_ref().map(|r| r.idle).unwrap_or(false) { self.stop(); }
+    }
+
+    fn search_info(&self) -> Option<SearchInfo> {
+        self.info.clone()
+    }
+}
+
+/// Limits for a game.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GameConfig {
+    /// With a time control, the referee keeps a clock for each player,
+    /// measured around each move, and `timeout` is not used for moves.
+    pub time_control :Option<TimeControl>,
+    /// Time limit for a program or network player to start a game, and for
+    /// each move.
+    pub timeout :Option<Duration>,
+    /// Number of plies after which the game is drawn. Quoridor has no other
+    /// way to end a game where neither player makes progress.
+    pub max_plies :Option<usize>,
+}
+
+/// Play a game between two players, with player 1 (index 0) moving first.
+/// A player that fails to make a legal move in time loses the game, and is aborted.
+pub fn play_game(players :[&mut dyn Contestant; 2], config :&GameConfig,
+                 log :&mut dyn FnMut(Move, &Board, Option<&Clocks>)) -> GameResult {
+    let mut players = players;
+    let mut board :Board = Default::default();
+    let mut clocks = config.time_control.map(Clocks::new);
+    let mut moves = Vec::new();
+    let mut scores = Vec::new();
+    let forfeit = |players :&mut [&mut dyn Contestant; 2], loser :usize, reason, moves, scores| {
+        players[loser].abort();
+        GameResult { winner: Some(1 - loser), termination: Termination::Forfeit(reason), moves, scores }
+    };
+
+    for p in 0..2 {
+        if let Err(reason) = players[p].new_game(config.timeout) {
+            return forfeit(&mut players, p, reason, moves, scores);
+        }
+    }
+
+    let mut last_move = None;
+    loop {
+        let p = board.player();
+        let (limit, go) = match &clocks {
+            Some(c) => (Some(c.remaining[p]), c.go_params()),
+            None => (config.timeout, Default::default()),
+        };
+        let start = Instant::now();
+        let mv = players[p].mv(last_move, limit, &go);
+        let elapsed = start.elapsed();
+        let mv = match (&mut clocks, mv) {
+            (Some(_), Err(ForfeitReason::Timeout(_))) => Err(ForfeitReason::OutOfTime),
+            (Some(c), Ok(mv)) => if c.record(p, elapsed) { Ok(mv) } else { Err(ForfeitReason::OutOfTime) },
+            (_, mv) => mv,
+        };
+        let mv = mv.and_then(|mv|
+            board.check_move(&mv).map(|_| mv).map_err(|e| ForfeitReason::IllegalMove(mv, e)));
+        let mv = match mv {
+            Ok(mv) => mv,
+            Err(reason) => return forfeit(&mut players, p, reason, moves, scores),
+        };
+        board.integrate(mv).unwrap();
+        moves.push(mv);
+        scores.push(players[p].search_info().and_then(|info| info.score));
+        log(mv, &board, clocks.as_ref());
+        if let Some(winner) = board.get_winner() {
+            return GameResult { winner: Some(winner), termination: Termination::Normal, moves, scores };
+        }
+        if config.max_plies.map(|max| moves.len() >= max).unwrap_or(false) {
+            return GameResult { winner: None, termination: Termination::MoveLimit, moves, scores };
+        }
+        last_move = Some(mv);
+    }
+}
+
+/// Record of a played game, with the players' names and the scores they reported.
+pub fn game_record(result :&GameResult, names :[&str;2], config :&GameConfig) -> GameRecord {
+    let mut record = GameRecord::default();
+    record.set_tag("Player1", names[0]);
+    record.set_tag("Player2", names[1]);
+    record.set_tag("Date", &today());
+    let outcome = match result.winner {
+        Some(winner) => GameOutcome::Win(winner),
+        None => GameOutcome::Draw,
+    };
+    record.set_tag("Result", &outcome.to_string());
+    record.set_tag("Termination", &result.termination.to_string());
+    if let Some(control) = config.time_control {
+        record.set_tag("TimeControl", &control.to_string());
+    }
+    record.moves = result.moves.iter().zip(&result.scores).enumerate().map(|(i, (mv, score))| {
+        let eval = score.map(|s| if i % 2 == 0 { s } else { -s });
+        RecordedMove { eval, .. RecordedMove::new(*mv) }
+    }).collect();
+    record
+}
+
+/// Today's date in UTC, as `YYYY.MM.DD`.
+fn today() -> String {
+    let secs = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    // Civil date from days since 1970-01-01, counting in 400 year eras from 0000-03-01.
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Plays the given moves in order.
+    struct Scripted(Vec<&'static str>);
+
+    impl Player for Scripted {
+        fn mv(&mut self, _mv :Option<Move>) -> Move { parse(self.0.remove(0)).unwrap() }
+        fn reset(&mut self) {}
+    }
+
+    fn play_remote(program :&str, protocol :Protocol, timeout :Option<Duration>) -> GameResult {
+        let mut p1 = RemotePlayer::new(Connection::Program(program.to_string()), protocol);
+        let mut p2 = LocalPlayer(Box::new(Scripted(vec!["e8", "e7", "e6"])));
+        play_game([&mut p1, &mut p2], &GameConfig { timeout, .. Default::default() }, &mut |_,_,_| {})
+    }
+
+    #[test]
+    fn illegal_moves() {
+        let mut p1 = LocalPlayer(Box::new(Scripted(vec!["e2", "e4"])));
+        let mut p2 = LocalPlayer(Box::new(Scripted(vec!["e8"])));
+        let result = play_game([&mut p1, &mut p2], &Default::default(), &mut |_,_,_| {});
+        assert_eq!(Some(1), result.winner);
+        assert!(matches!(result.termination, Termination::Forfeit(ForfeitReason::IllegalMove(_,_))));
+        assert_eq!(2, result.moves.len());
+    }
+
+    #[test]
+    fn records() {
+        let mut p1 = LocalPlayer(Box::new(Scripted(vec!["e2", "e3", "e4"])));
+        let mut p2 = LocalPlayer(Box::new(Scripted(vec!["e8", "e7", "e4"])));
+        let config = GameConfig { time_control: Some("60+1".parse().unwrap()), .. Default::default() };
+        let mut result = play_game([&mut p1, &mut p2], &config, &mut |_,_,_| {});
+        result.scores[1] = Some(0.5);
+        let record = game_record(&result, ["one", "two"], &config);
+        assert_eq!(Some("one"), record.tag("Player1"));
+        assert_eq!(Some("60+1"), record.tag("TimeControl"));
+        assert!(record.tag("Termination").unwrap().starts_with("forfeit, "));
+        assert_eq!(GameOutcome::Win(0), record.outcome());
+        assert_eq!(Some(-0.5), record.moves[1].eval);
+        assert_eq!(10, record.tag("Date").unwrap().len());
+
+        let parsed :GameRecord = record.to_string().parse().unwrap();
+        assert_eq!(record, parsed);
+        assert_eq!(result.moves, parsed.moves.iter().map(|m| m.mv).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn move_limit() {
+        let mut p1 = LocalPlayer(Box::new(Scripted(vec!["e2", "e1", "e2"])));
+        let mut p2 = LocalPlayer(Box::new(Scripted(vec!["e8", "e9", "e8"])));
+        let config = GameConfig { max_plies: Some(5), .. Default::default() };
+        let result = play_game([&mut p1, &mut p2], &config, &mut |_,_,_| {});
+        assert_eq!((None, Termination::MoveLimit), (result.winner, result.termination));
+        assert_eq!(5, result.moves.len());
+    }
+
+    #[test]
+    fn forfeits() {
+        // The program answers once and then exits.
+        let result = play_remote("sh -c 'read x; echo e2'", Protocol::Legacy, None);
+        assert_eq!((Some(1), Termination::Forfeit(ForfeitReason::Disconnected)), (result.winner, result.termination));
+        assert_eq!(2, result.moves.len());
+
+        let result = play_remote("sh -c 'read x; echo hello'", Protocol::Legacy, None);
+        assert!(matches!(result.termination, Termination::Forfeit(ForfeitReason::InvalidOutput(_))));
+
+        let timeout = Duration::from_millis(200);
+        let result = play_remote("sleep 5", Protocol::Legacy, Some(timeout));
+        assert_eq!(Termination::Forfeit(ForfeitReason::Timeout(timeout)), result.termination);
+
+        let result = play_remote("sh -c 'read x; echo hello'", Protocol::Engine(vec![]), None);
+        assert!(matches!(result.termination, Termination::Forfeit(ForfeitReason::InvalidOutput(_))));
+
+        let result = play_remote("/nonexistent/engine", Protocol::Legacy, None);
+        assert!(matches!(result.termination, Termination::Forfeit(ForfeitReason::StartFailed(_))));
+        assert!(result.moves.is_empty());
+    }
+
+    /// Plays the given moves in order, taking some time for each.
+    struct Slow(Vec<(&'static str, u64)>);
+
+    impl Player for Slow {
+        fn mv(&mut self, _mv :Option<Move>) -> Move {
+            let (mv, millis) = self.0.remove(0);
+            thread::sleep(Duration::from_millis(millis));
+            parse(mv).unwrap()
+        }
+        fn reset(&mut self) {}
+    }
+
+    #[test]
+    fn loss_on_time() {
+        // Player 2 uses 150ms of a 200ms sudden death clock on the first move,
+        // and the second move takes longer than what is left.
+        let mut p1 = LocalPlayer(Box::new(Scripted(vec!["e2", "e3", "e4"])));
+        let mut p2 = LocalPlayer(Box::new(Slow(vec![("e8", 150), ("e7", 100)])));
+        let mut remaining = Vec::new();
+        let config = |tc :&str| GameConfig { time_control: Some(tc.parse().unwrap()), .. Default::default() };
+        let result = play_game([&mut p1, &mut p2], &config("0.2"), &mut |_,_,c| remaining.push(c.unwrap().remaining[1]));
+        assert_eq!((Some(0), Termination::Forfeit(ForfeitReason::OutOfTime)), (result.winner, result.termination));
+        assert_eq!(3, result.moves.len());
+        assert!(remaining[1] < Duration::from_millis(50));
+
+        // With an increment, the same moves are in time.
+        let mut p1 = LocalPlayer(Box::new(Scripted(vec!["e2", "e3", "e4"])));
+        let mut p2 = LocalPlayer(Box::new(Slow(vec![("e8", 150), ("e7", 100), ("e4", 0)])));
+        let result = play_game([&mut p1, &mut p2], &config("0.2+0.1"), &mut |_,_,_| {});
+        assert!(matches!(result.termination, Termination::Forfeit(ForfeitReason::IllegalMove(_,_))));
+
+        // A remote player is stopped when its time for the move is used.
+        let mut p1 = RemotePlayer::new(Connection::Program("sleep 5".to_string()), Protocol::Legacy);
+        let mut p2 = LocalPlayer(Box::new(Scripted(vec![])));
+        let start = Instant::now();
+        let result = play_game([&mut p1, &mut p2], &config("0.2/move"), &mut |_,_,_| {});
+        assert_eq!((Some(1), Termination::Forfeit(ForfeitReason::OutOfTime)), (result.winner, result.termination));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stop_after_timeout() {
+        // The engine only moves when it is stopped, and logs the commands it receives.
+        let log = std::env::temp_dir().join(format!("referee_stop_{}.log", std::process::id()));
+        let program = format!("sh -c 'while read cmd rest; do echo $cmd >> {}; case $cmd in \
+                               quoridor) echo id name stopper; echo ok;; isready) echo readyok;; \
+                               stop) echo bestmove e2;; quit) exit;; esac; done'", log.display());
+        let mut p1 = RemotePlayer::new(Connection::Program(program), Protocol::Engine(vec![]));
+        let config = GameConfig { timeout: Some(Duration::from_millis(200)), .. Default::default() };
+        for _ in 0..2 {
+            let mut p2 = LocalPlayer(Box::new(Scripted(vec![])));
+            let result = play_game([&mut p1, &mut p2], &config, &mut |_,_,_| {});
+            assert_eq!(Termination::Forfeit(ForfeitReason::Timeout(Duration::from_millis(200))), result.termination);
+            assert!(result.moves.is_empty());
+        }
+        // The engine answered the stop, so it plays the second game without a restart,
+        // and exits by itself after quit.
+        drop(p1);
+        let commands = std::fs::read_to_string(&log).unwrap();
+        let _ = std::fs::remove_file(&log);
+        let game = ["newgame", "isready", "position", "go", "stop"];
+        let expected = std::iter::once("quoridor").chain(game.iter().cloned()).chain(game.iter().cloned())
+            .chain(std::iter::once("quit")).collect::<Vec<_>>();
+        assert_eq!(expected, commands.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn restart_after_forfeit() {
+        // The player is started again for the next game after timing out.
+        let program = "sh -c 'read x; echo e2; read x; sleep 5'";
+        let mut p1 = RemotePlayer::new(Connection::Program(program.to_string()), Protocol::Legacy);
+        let config = GameConfig { timeout: Some(Duration::from_millis(200)), .. Default::default() };
+        for _ in 0..2 {
+            let mut p2 = LocalPlayer(Box::new(Scripted(vec!["e8", "e7"])));
+            let result = play_game([&mut p1, &mut p2], &config, &mut |_,_,_| {});
+            assert_eq!(Termination::Forfeit(ForfeitReason::Timeout(Duration::from_millis(200))), result.termination);
+            assert_eq!(2, result.moves.len());
+        }
+    }
+}
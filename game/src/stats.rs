@@ -0,0 +1,226 @@
+//! Statistics for comparing engines from the results of games between them.
+//! Quoridor has no draws, but the referee draws games that reach the move
+//! limit, and a draw counts as half a win.
+
+/// Elo rating difference corresponding to an expected score between 0 and 1.
+pub fn elo_difference(score :f64) -> f64 {
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+/// Number of standard deviations for a two-sided 95% confidence interval.
+const Z95 :f64 = 1.959964;
+
+/// An Elo difference estimated from game results, with a 95% confidence interval.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EloEstimate {
+    pub elo :f64,
+    pub lower :f64,
+    pub upper :f64,
+}
+
+impl EloEstimate {
+    /// Estimate from the results of one engine against another, using a
+    /// normal approximation of the score. `None` if no games were played.
+    pub fn new(wins :u32, draws :u32, losses :u32) -> Option<EloEstimate> {
+        let (w, d, l) = (wins as f64, draws as f64, losses as f64);
+        let games = w + d + l;
+        if games == 0.0 { return None; }
+        let score = (w + d / 2.0) / games;
+        let variance = (w * (1.0 - score).powi(2) + d * (0.5 - score).powi(2) + l * score.powi(2)) / games;
+        let deviation = (variance / games).sqrt();
+        let bound = |s :f64| elo_difference(s.clamp(0.0, 1.0));
+        Some(EloEstimate {
+            elo: elo_difference(score),
+            lower: bound(score - Z95 * deviation),
+            upper: bound(score + Z95 * deviation),
+        })
+    }
+
+    /// Half the width of the confidence interval.
+    pub fn error(&self) -> f64 {
+        (self.upper - self.lower) / 2.0
+    }
+}
+
+impl std::fmt::Display for EloEstimate {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.elo.is_finite() && self.error().is_finite() {
+            write!(f, "{:+.1} ± {:.1}", self.elo, self.error())
+        } else {
+            write!(f, "{:+.1}", self.elo)
+        }
+    }
+}
+
+/// The error function, with an absolute error below 1.5e-7
+/// (Abramowitz and Stegun 7.1.26).
+fn erf(x :f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = ((((1.061405429 * t - 1.453152027) * t + 1.421413741) * t - 0.284496736) * t + 0.254829592) * t;
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
+
+/// Likelihood of superiority: the probability that the engine with the
+/// given wins and losses against another is the stronger one. Draws do not
+/// change it.
+pub fn likelihood_of_superiority(wins :u32, losses :u32) -> f64 {
+    if wins + losses == 0 { return 0.5; }
+    let (w, l) = (wins as f64, losses as f64);
+    0.5 * (1.0 + erf((w - l) / (2.0 * (w + l)).sqrt()))
+}
+
+/// Expected score against an opponent with an Elo rating `elo` lower.
+pub fn expected_score(elo :f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Sequential probability ratio test of whether an engine is stronger than
+/// another, with the hypotheses H0: the Elo difference is `elo0` and H1: the
+/// Elo difference is `elo1`. `alpha` and `beta` are the probabilities of
+/// accepting H1 when H0 is true, and H0 when H1 is true. Written as
+/// `elo0=<elo>,elo1=<elo>[,alpha=<p>][,beta=<p>]`, with alpha and beta 0.05
+/// by default.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0 :f64,
+    pub elo1 :f64,
+    pub alpha :f64,
+    pub beta :f64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SprtDecision {
+    /// More games are needed.
+    Continue,
+    AcceptH0,
+    AcceptH1,
+}
+
+impl Sprt {
+    /// Lower and upper bounds for the log-likelihood ratio.
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    /// Log-likelihood ratio of H1 against H0, where each game is won with
+    /// the expected score for the hypothesis. A draw counts as half a win and
+    /// half a loss, which makes the test slightly conservative when there are draws.
+    pub fn llr(&self, wins :u32, draws :u32, losses :u32) -> f64 {
+        let (w, d, l) = (wins as f64, draws as f64, losses as f64);
+        let (s0, s1) = (expected_score(self.elo0), expected_score(self.elo1));
+        (w + d / 2.0) * (s1 / s0).ln() + (l + d / 2.0) * ((1.0 - s1) / (1.0 - s0)).ln()
+    }
+
+    pub fn decision(&self, wins :u32, draws :u32, losses :u32) -> SprtDecision {
+        let llr = self.llr(wins, draws, losses);
+        let (lower, upper) = self.bounds();
+        if llr >= upper { SprtDecision::AcceptH1 }
+        else if llr <= lower { SprtDecision::AcceptH0 }
+        else { SprtDecision::Continue }
+    }
+}
+
+impl std::str::FromStr for Sprt {
+    type Err = String;
+    fn from_str(s :&str) -> Result<Sprt,String> {
+        let (mut elo0, mut elo1, mut alpha, mut beta) = (None, None, 0.05, 0.05);
+        for part in s.split(',') {
+            let mut kv = part.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => return Err(format!("Expected name=value in SPRT parameters, got {:?}", part)),
+            };
+            let value :f64 = value.parse().map_err(|e| format!("Could not parse SPRT {} {:?}: {}", key, value, e))?;
+            match key {
+                "elo0" => elo0 = Some(value),
+                "elo1" => elo1 = Some(value),
+                "alpha" => alpha = value,
+                "beta" => beta = value,
+                x => return Err(format!("Unknown SPRT parameter {:?}", x)),
+            }
+        }
+        let (elo0, elo1) = match (elo0, elo1) {
+            (Some(elo0), Some(elo1)) => (elo0, elo1),
+            _ => return Err("SPRT needs elo0 and elo1".to_string()),
+        };
+        if elo0 >= elo1 || elo0.is_nan() || elo1.is_nan() { return Err("SPRT elo0 must be less than elo1".to_string()); }
+        if !(alpha > 0.0 && alpha < 0.5 && beta > 0.0 && beta < 0.5) {
+            return Err("SPRT alpha and beta must be between 0 and 0.5".to_string());
+        }
+        Ok(Sprt { elo0, elo1, alpha, beta })
+    }
+}
+
+impl std::fmt::Display for Sprt {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "elo0={},elo1={},alpha={},beta={}", self.elo0, self.elo1, self.alpha, self.beta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elo_estimates() {
+        assert_eq!(0.0, elo_difference(0.5));
+        assert!((elo_difference(0.75) - 190.85).abs() < 0.01);
+        assert!((elo_difference(0.25) + 190.85).abs() < 0.01);
+        assert_eq!(None, EloEstimate::new(0, 0, 0));
+
+        let even = EloEstimate::new(50, 0, 50).unwrap();
+        assert_eq!("+0.0 ± 69.0", even.to_string());
+        assert!((even.upper + even.lower).abs() < 1e-9);
+        assert!((even.error() - 69.0).abs() < 0.1, "{}", even);
+        assert!(EloEstimate::new(500, 0, 500).unwrap().error() < even.error());
+        // Draws make the result less uncertain than wins and losses.
+        let draws = EloEstimate::new(25, 50, 25).unwrap();
+        assert_eq!(0.0, draws.elo);
+        assert!(draws.error() < even.error());
+
+        let estimate = EloEstimate::new(75, 0, 25).unwrap();
+        assert!(estimate.lower < estimate.elo && estimate.elo < estimate.upper);
+        assert_eq!(-estimate.elo, EloEstimate::new(25, 0, 75).unwrap().elo);
+        assert_eq!(estimate.elo, EloEstimate::new(50, 50, 0).unwrap().elo);
+
+        let all = EloEstimate::new(10, 0, 0).unwrap();
+        assert_eq!(f64::INFINITY, all.elo);
+        assert_eq!("+inf", all.to_string());
+    }
+
+    #[test]
+    fn likelihood_of_superiority_values() {
+        assert_eq!(0.5, likelihood_of_superiority(0, 0));
+        assert!((likelihood_of_superiority(10, 10) - 0.5).abs() < 1e-7);
+        // (60 - 40) / sqrt(100) is two standard deviations.
+        assert!((likelihood_of_superiority(60, 40) - 0.97725).abs() < 1e-4);
+        assert!((likelihood_of_superiority(40, 60) - 0.02275).abs() < 1e-4);
+        assert!(likelihood_of_superiority(1000, 0) > 0.9999999);
+    }
+
+    #[test]
+    fn sprt() {
+        let sprt :Sprt = "elo0=0,elo1=10".parse().unwrap();
+        assert_eq!(Sprt { elo0: 0.0, elo1: 10.0, alpha: 0.05, beta: 0.05 }, sprt);
+        assert_eq!(Ok(sprt), sprt.to_string().parse());
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 1e-3 && (lower + 2.944).abs() < 1e-3);
+        for s in &["elo0=0", "elo0=5,elo1=0", "elo0=0,elo1=5,alpha=0", "elo0=0,elo1=5,gamma=1", "elo0=x,elo1=5", ""] {
+            assert!(s.parse::<Sprt>().is_err(), "{:?}", s);
+        }
+
+        assert_eq!(0.0, sprt.llr(0, 0, 0));
+        assert!(sprt.llr(5, 0, 0) > 0.0 && sprt.llr(0, 0, 5) < 0.0);
+        assert_eq!(sprt.llr(5, 10, 5), sprt.llr(10, 0, 10));
+        // Results halfway between the hypotheses give no evidence either way.
+        let half = expected_score(5.0);
+        let wins = (1000.0 * half).round() as u32;
+        assert!(sprt.llr(wins, 0, 1000 - wins).abs() < 0.1);
+        assert!(sprt.llr(60, 0, 40) > sprt.llr(50, 0, 50));
+
+        assert_eq!(SprtDecision::Continue, sprt.decision(12, 0, 8));
+        assert_eq!(SprtDecision::AcceptH1, sprt.decision(700, 0, 300));
+        assert_eq!(SprtDecision::AcceptH0, sprt.decision(300, 0, 700));
+        assert_eq!(SprtDecision::AcceptH0, sprt.decision(10000, 0, 10000));
+    }
+}
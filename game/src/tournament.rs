@@ -0,0 +1,336 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use crate::engine::Protocol;
+use crate::referee::*;
+use crate::stats::*;
+
+/// An engine taking part in a tournament.
+#[derive(Clone, Debug)]
+pub struct Entrant {
+    pub name :String,
+    /// Command line for starting the engine.
+    pub program :String,
+    pub protocol :Protocol,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Two engines play each other.
+    Match,
+    /// Every engine plays every other engine.
+    RoundRobin,
+    /// The first engine plays each of the others.
+    Gauntlet,
+}
+
+#[derive(Clone, Debug)]
+pub struct TournamentConfig {
+    pub format :Format,
+    /// Games between each pair of engines, alternating colours.
+    pub games :u32,
+    /// Games played at the same time.
+    pub concurrency :usize,
+    pub game :GameConfig,
+    /// For a match, stop when the test of the first engine against the second is decided.
+    pub sprt :Option<Sprt>,
+}
+
+/// A game in a tournament, with the players given as indices in the list
+/// of entrants, player 1 first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pairing {
+    pub game :usize,
+    pub players :[usize;2],
+}
+
+/// The games to play between `entrants` engines. Each pair plays one game
+/// before any pair plays the next, so that partial results are balanced.
+pub fn schedule(format :Format, entrants :usize, games :u32) -> Vec<Pairing> {
+    let pairs = (0..entrants).flat_map(|i| (i+1..entrants).map(move |j| (i,j)))
+        .filter(|(i,_)| format != Format::Gauntlet || *i == 0)
+        .collect::<Vec<_>>();
+    let mut pairings = Vec::new();
+    for round in 0..games {
+        for &(i,j) in &pairs {
+            let players = if round % 2 == 0 { [i,j] } else { [j,i] };
+            pairings.push(Pairing { game: pairings.len(), players });
+        }
+    }
+    pairings
+}
+
+/// Results for an engine against one opponent or against all of them.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub wins :u32,
+    pub draws :u32,
+    pub losses :u32,
+    /// Losses by forfeit.
+    pub forfeits :u32,
+    /// Total length of the games in plies.
+    pub plies :u64,
+}
+
+impl Score {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn add(&mut self, other :&Score) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+        self.forfeits += other.forfeits;
+        self.plies += other.plies;
+    }
+
+    /// Average game length in plies.
+    pub fn average_length(&self) -> f64 {
+        if self.games() == 0 { return 0.0; }
+        self.plies as f64 / self.games() as f64
+    }
+
+    pub fn elo(&self) -> Option<EloEstimate> {
+        EloEstimate::new(self.wins, self.draws, self.losses)
+    }
+
+    pub fn likelihood_of_superiority(&self) -> f64 {
+        likelihood_of_superiority(self.wins, self.losses)
+    }
+
+    pub fn sprt(&self, sprt :&Sprt) -> (f64, SprtDecision) {
+        (sprt.llr(self.wins, self.draws, self.losses), sprt.decision(self.wins, self.draws, self.losses))
+    }
+}
+
+/// Results of a tournament so far.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Standings {
+    pub names :Vec<String>,
+    /// `scores[i][j]` is the score of engine `i` against engine `j`.
+    pub scores :Vec<Vec<Score>>,
+}
+
+impl Standings {
+    pub fn new(names :Vec<String>) -> Standings {
+        let n = names.len();
+        Standings { names, scores: vec![vec![Default::default(); n]; n] }
+    }
+
+    pub fn add(&mut self, pairing :&Pairing, result :&GameResult) {
+        let [a, b] = pairing.players;
+        let plies = result.moves.len() as u64;
+        self.scores[a][b].plies += plies;
+        self.scores[b][a].plies += plies;
+        let (winner, loser) = match result.winner {
+            Some(w) => (pairing.players[w], pairing.players[1 - w]),
+            None => {
+                self.scores[a][b].draws += 1;
+                self.scores[b][a].draws += 1;
+                return;
+            },
+        };
+        self.scores[winner][loser].wins += 1;
+        self.scores[loser][winner].losses += 1;
+        if let Termination::Forfeit(_) = result.termination {
+            self.scores[loser][winner].forfeits += 1;
+        }
+    }
+
+    /// The engine's results against all opponents.
+    pub fn total(&self, engine :usize) -> Score {
+        let mut total = Score::default();
+        for score in &self.scores[engine] { total.add(score); }
+        total
+    }
+}
+
+impl std::fmt::Display for Standings {
+    /// A table of results for each engine, best first, followed by the
+    /// results of each pair of engines when there are more than two.
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        let width = self.names.iter().map(|n| n.len()).max().unwrap_or(0).max(6);
+        let header = |f :&mut std::fmt::Formatter, title :&str, width :usize| writeln!(f,
+            "{:<w$}  Games   Wins  Draws  Losses  Forfeits  Avg plies    LOS  Elo", title, w = width);
+        let row = |f :&mut std::fmt::Formatter, name :&str, width :usize, s :&Score| writeln!(f,
+            "{:<w$}  {:>5}  {:>5}  {:>5}  {:>6}  {:>8}  {:>9.1}  {:>4.0}%  {}",
+            name, s.games(), s.wins, s.draws, s.losses, s.forfeits, s.average_length(),
+            100.0 * s.likelihood_of_superiority(), s.elo().map(|e| e.to_string()).unwrap_or_else(|| "-".to_string()), w = width);
+
+        let mut order = (0..self.names.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| {
+            let total = self.total(i);
+            (std::cmp::Reverse(total.wins as i64 - total.losses as i64), i)
+        });
+        header(f, "Engine", width)?;
+        for &i in &order {
+            row(f, &self.names[i], width, &self.total(i))?;
+        }
+
+        if self.names.len() > 2 {
+            let pairs = order.iter().flat_map(|&i| order.iter().map(move |&j| (i,j)))
+                .filter(|&(i,j)| i != j && self.scores[i][j].games() > 0
+                        && order.iter().position(|&k| k == i) < order.iter().position(|&k| k == j))
+                .map(|(i,j)| (format!("{} vs {}", self.names[i], self.names[j]), self.scores[i][j]))
+                .collect::<Vec<_>>();
+            let width = pairs.iter().map(|(n,_)| n.len()).max().unwrap_or(0).max(width);
+            writeln!(f)?;
+            header(f, "Pair", width)?;
+            for (name, score) in &pairs {
+                row(f, name, width, score)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates the contestant for an entrant. Called on the thread playing the game.
+pub type ContestantFactory = Arc<dyn Fn(usize) -> Box<dyn Contestant> + Send + Sync>;
+
+/// Play the scheduled games on `concurrency` threads, each game with new
+/// contestants. `report` is called after each game with the standings so far,
+/// and returns false to stop after the games in progress.
+pub fn run_games(names :Vec<String>, pairings :Vec<Pairing>, concurrency :usize, config :&GameConfig,
+                 make_contestant :ContestantFactory,
+                 report :&mut dyn FnMut(&Pairing, &GameResult, &Standings) -> bool) -> Standings {
+    let mut standings = Standings::new(names);
+    let games = pairings.len();
+    let queue = Arc::new(Mutex::new(pairings.into_iter()));
+    let (tx, rx) = mpsc::channel();
+    let config = *config;
+    let workers = (0..concurrency.max(1).min(games)).map(|_| {
+        let (queue, tx, make_contestant) = (queue.clone(), tx.clone(), make_contestant.clone());
+        thread::spawn(move || {
+            loop {
+                let pairing = match queue.lock().unwrap().next() {
+                    Some(pairing) => pairing,
+                    None => break,
+                };
+                let mut p1 = make_contestant(pairing.players[0]);
+                let mut p2 = make_contestant(pairing.players[1]);
+                let result = play_game([&mut *p1, &mut *p2], &config, &mut |_,_,_| {});
+                if tx.send((pairing, result)).is_err() { break; }
+            }
+        })
+    }).collect::<Vec<_>>();
+    drop(tx);
+
+    for (pairing, result) in rx.iter() {
+        standings.add(&pairing, &result);
+        if !report(&pairing, &result, &standings) {
+            *queue.lock().unwrap() = Vec::new().into_iter();
+        }
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    standings
+}
+
+/// Run a tournament between engine programs.
+pub fn run_tournament(entrants :&[Entrant], config :&TournamentConfig,
+                      report :&mut dyn FnMut(&Pairing, &GameResult, &Standings) -> bool) -> Standings {
+    let names = entrants.iter().map(|e| e.name.clone()).collect();
+    let pairings = schedule(config.format, entrants.len(), config.games);
+    let entrants = entrants.to_vec();
+    let make_contestant :ContestantFactory = Arc::new(move |i| {
+        let entrant = &entrants[i];
+        Box::new(RemotePlayer::new(Connection::Program(entrant.program.clone()), entrant.protocol.clone()))
+    });
+    let sprt = config.sprt.filter(|_| config.format == Format::Match);
+    run_games(names, pairings, config.concurrency, &config.game, make_contestant, &mut |pairing, result, standings| {
+        let decided = sprt.map(|s| standings.scores[0][1].sprt(&s).1 != SprtDecision::Continue).unwrap_or(false);
+        report(pairing, result, standings) && !decided
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::*;
+    use std::time::Duration;
+
+    #[test]
+    fn schedules() {
+        let players = |format, n, games| schedule(format, n, games).iter().map(|p| p.players).collect::<Vec<_>>();
+        assert_eq!(vec![[0,1], [1,0], [0,1]], players(Format::Match, 2, 3));
+        assert_eq!(vec![[0,1], [0,2], [1,2], [1,0], [2,0], [2,1]], players(Format::RoundRobin, 3, 2));
+        assert_eq!(vec![[0,1], [0,2], [1,0], [2,0]], players(Format::Gauntlet, 3, 2));
+        let games = schedule(Format::RoundRobin, 4, 3);
+        assert_eq!(18, games.len());
+        assert!(games.iter().enumerate().all(|(i,p)| p.game == i));
+    }
+
+    /// Walks towards the goal row, placing a wall first if `walls` is set.
+    struct Walker { board :Board, walls :bool }
+
+    impl Player for Walker {
+        fn mv(&mut self, mv :Option<Move>) -> Move {
+            if let Some(mv) = mv { self.board.integrate(mv).unwrap(); }
+            let p = self.board.player();
+            let mv = if self.walls && self.board.num_walls() < 2 {
+                self.board.legal_wall_moves().as_slice()[0]
+            } else {
+                *self.board.legal_pawn_moves().as_slice().iter().min_by_key(|mv| {
+                    let mut board = self.board;
+                    board.integrate(**mv).unwrap();
+                    board.distance_to_goal(p)
+                }).unwrap()
+            };
+            self.board.integrate(mv).unwrap();
+            mv
+        }
+        fn reset(&mut self) { self.board = Default::default(); }
+    }
+
+    #[test]
+    fn tournament() {
+        // Engine 1 places walls before walking, and engine 2 cannot be started.
+        let make_contestant :ContestantFactory = Arc::new(|i| match i {
+            2 => Box::new(RemotePlayer::new(Connection::Program("/nonexistent/engine".to_string()), Protocol::Legacy)),
+            i => Box::new(LocalPlayer(Box::new(Walker { board: Default::default(), walls: i == 1 }))),
+        });
+        let names = vec!["walker".to_string(), "waller".to_string(), "broken".to_string()];
+        let mut reports = 0;
+        let standings = run_games(names, schedule(Format::RoundRobin, 3, 4), 3, &Default::default(), make_contestant,
+                                  &mut |_,_,_| { reports += 1; true });
+        assert_eq!(12, reports);
+
+        let broken = standings.total(2);
+        assert_eq!(Score { wins: 0, draws: 0, losses: 8, forfeits: 8, plies: 0 }, broken);
+        assert_eq!(Score { wins: 4, draws: 0, losses: 0, forfeits: 0, plies: 0 }, standings.scores[0][2]);
+        let (walker, waller) = (standings.scores[0][1], standings.scores[1][0]);
+        assert_eq!((4, 0), (walker.games(), walker.forfeits));
+        assert_eq!((walker.wins, walker.losses), (waller.losses, waller.wins));
+        assert!(walker.average_length() > 10.0);
+
+        let table = standings.to_string();
+        let lines = table.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("Engine"));
+        assert!(lines[3].starts_with("broken") && lines[3].contains("-inf"), "{}", table);
+        assert!(lines.iter().any(|l| l.starts_with("Pair")));
+        assert_eq!(9, lines.len(), "{}", table);
+
+        // Games reaching the move limit are drawn.
+        let make_contestant :ContestantFactory = Arc::new(|_| {
+            Box::new(LocalPlayer(Box::new(Walker { board: Default::default(), walls: false })))
+        });
+        let config = GameConfig { max_plies: Some(6), .. Default::default() };
+        let names = vec!["a".to_string(), "b".to_string()];
+        let standings = run_games(names.clone(), schedule(Format::Match, 2, 2), 1,
+                                  &config, make_contestant.clone(), &mut |_,_,_| true);
+        assert_eq!(Score { wins: 0, draws: 2, losses: 0, forfeits: 0, plies: 12 }, standings.total(0));
+        assert_eq!("+0.0 ± 0.0", standings.total(1).elo().unwrap().to_string());
+        assert_eq!(0.5, standings.total(1).likelihood_of_superiority());
+
+        // Stopping leaves the rest of the schedule unplayed. Games that have
+        // been started, at most one for each thread, are finished.
+        let slow :ContestantFactory = Arc::new(move |i| {
+            thread::sleep(Duration::from_millis(20));
+            make_contestant(i)
+        });
+        let standings = run_games(names, schedule(Format::Match, 2, 10), 2,
+                                  &config, slow, &mut |_,_,s| s.total(0).games() < 3);
+        assert!((3..=5).contains(&standings.total(0).games()), "{}", standings);
+    }
+}
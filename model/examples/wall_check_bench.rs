@@ -0,0 +1,65 @@
+//! Compare the time to check all wall placements using the dual graph
+//! loop test (`Board::can_add_wall`) against searching for paths for 
+//! every wall.
+//!
+//! Run with `cargo run --release --example wall_check_bench`.
+
+use model::*;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+
+fn random_boards(n :usize) -> Vec<Board> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+    let mut boards = Vec::new();
+    while boards.len() < n {
+        let mut board :Board = Default::default();
+        for _ in 0..rng.gen_range(0, 40) {
+            let moves = board.legal_moves();
+            if moves.is_empty() { break; }
+            board.integrate(moves[rng.gen_range(0, moves.len())]).unwrap();
+        }
+        if board.get_winner().is_none() { boards.push(board); }
+    }
+    boards
+}
+
+fn full_search_can_add_wall(board :&Board, ori :Orientation, pos :Position) -> bool {
+    if board.walls_left[board.player()] == 0 || board.wall_conflicts(ori,pos) { return false; }
+    let (mut h, mut v) = board.get_wall_bitsets();
+    match ori {
+        Orientation::Horizontal => h |= 1 << encode8(pos.x-1,pos.y-1),
+        Orientation::Vertical => v |= 1 << encode8(pos.x-1,pos.y-1),
+    }
+    goal_reachable(h, v, board.position(0), 9) && goal_reachable(h, v, board.position(1), 1)
+}
+
+fn main() {
+    let boards = random_boards(2000);
+    let walls :Vec<(Orientation,Position)> = [Orientation::Horizontal, Orientation::Vertical].iter()
+        .flat_map(|o| (1..=8).flat_map(move |x| (1..=8).map(move |y| (*o, Position { x, y }))))
+        .collect();
+
+    let start = Instant::now();
+    let mut count_dual = 0;
+    for board in &boards {
+        for (o,p) in &walls {
+            if board.can_add_wall(*o,*p) { count_dual += 1; }
+        }
+    }
+    let time_dual = start.elapsed();
+
+    let start = Instant::now();
+    let mut count_full = 0;
+    for board in &boards {
+        for (o,p) in &walls {
+            if full_search_can_add_wall(board,*o,*p) { count_full += 1; }
+        }
+    }
+    let time_full = start.elapsed();
+
+    assert_eq!(count_dual, count_full);
+    println!("{} boards, {} legal walls", boards.len(), count_dual);
+    println!("dual graph check:  {:?}", time_dual);
+    println!("full path search:  {:?}", time_full);
+    println!("speedup: {:.1}x", time_full.as_secs_f64() / time_dual.as_secs_f64());
+}
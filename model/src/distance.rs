@@ -0,0 +1,144 @@
+use crate::*;
+
+/// Distance value for cells that cannot reach the goal row.
+pub const UNREACHABLE :u8 = u8::MAX;
+
+/// The row a player must reach to win.
+pub fn goal_row(player :usize) -> i64 {
+    if player == 0 { 9 } else { 1 }
+}
+
+impl Board {
+    /// Cells adjacent to the given cell that are not separated from it by a wall.
+    /// Pawns are not taken into account.
+    pub fn open_neighbors(&self, cell :Position) -> impl Iterator<Item = Position> + '_ {
+        [(1,0),(-1,0),(0,1),(0,-1)].iter()
+            .map(move |(dx,dy)| Position { x: cell.x + dx, y: cell.y + dy })
+            .filter(move |n| in_bounds1to9(n) && !self.wall_between(&cell, n))
+    }
+
+    /// Number of steps from every cell (indexed by `encode9`) to the player's
+    /// goal row, going around walls but ignoring pawns and jumps.
+    /// Cells that cannot reach the goal row get the value `UNREACHABLE`.
+    pub fn distance_map(&self, player :usize) -> [u8; 81] {
+        let mut dist = [UNREACHABLE; 81];
+        let mut queue = [0usize; 81];
+        let (mut head, mut tail) = (0,0);
+        for x in 1..=9 {
+            let cell = encode9(x, goal_row(player));
+            dist[cell] = 0;
+            queue[tail] = cell;
+            tail += 1;
+        }
+
+        while head < tail {
+            let cell = queue[head];
+            head += 1;
+            for n in self.open_neighbors(decode9(cell)) {
+                let n = encode9(n.x,n.y);
+                if dist[n] == UNREACHABLE {
+                    dist[n] = dist[cell] + 1;
+                    queue[tail] = n;
+                    tail += 1;
+                }
+            }
+        }
+        dist
+    }
+
+    /// Number of steps for the player's pawn to reach the goal row,
+    /// ignoring the other pawn. `None` if the goal row cannot be reached.
+    pub fn distance_to_goal(&self, player :usize) -> Option<u8> {
+        let pos = self.position(player);
+        let d = self.distance_map(player)[encode9(pos.x,pos.y)];
+        if d == UNREACHABLE { None } else { Some(d) }
+    }
+
+    /// One shortest route for the player's pawn to the goal row,
+    /// as the sequence of cells visited after the current cell.
+    /// `None` if the goal row cannot be reached.
+    pub fn shortest_path(&self, player :usize) -> Option<Vec<Position>> {
+        let dist = self.distance_map(player);
+        let mut cell = self.position(player);
+        if dist[encode9(cell.x,cell.y)] == UNREACHABLE { return None; }
+
+        let mut path = Vec::new();
+        while dist[encode9(cell.x,cell.y)] > 0 {
+            let d = dist[encode9(cell.x,cell.y)];
+            cell = self.open_neighbors(cell)
+                .find(|n| dist[encode9(n.x,n.y)] == d - 1).unwrap();
+            path.push(cell);
+        }
+        Some(path)
+    }
+
+    /// All shortest routes for the player's pawn to the goal row, each given
+    /// as in `shortest_path`. Empty if the goal row cannot be reached.
+    pub fn shortest_paths(&self, player :usize) -> Vec<Vec<Position>> {
+        fn extend(board :&Board, dist :&[u8;81], cell :Position,
+                  path :&mut Vec<Position>, paths :&mut Vec<Vec<Position>>) {
+            let d = dist[encode9(cell.x,cell.y)];
+            if d == 0 { paths.push(path.clone()); return; }
+            for n in board.open_neighbors(cell) {
+                if dist[encode9(n.x,n.y)] == d - 1 {
+                    path.push(n);
+                    extend(board, dist, n, path, paths);
+                    path.pop();
+                }
+            }
+        }
+
+        let dist = self.distance_map(player);
+        let pos = self.position(player);
+        let mut paths = Vec::new();
+        if dist[encode9(pos.x,pos.y)] != UNREACHABLE {
+            extend(self, &dist, pos, &mut Vec::new(), &mut paths);
+        }
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_distances() {
+        let board :Board = Default::default();
+        assert_eq!(Some(8), board.distance_to_goal(0));
+        assert_eq!(Some(8), board.distance_to_goal(1));
+        let map = board.distance_map(0);
+        assert_eq!(0, map[encode9(1,9)]);
+        assert_eq!(5, map[encode9(9,4)]);
+
+        assert_eq!(1, board.shortest_paths(0).len());
+        let path = board.shortest_path(0).unwrap();
+        assert_eq!(8, path.len());
+        assert_eq!(Position { x: 5, y: 2 }, path[0]);
+        assert_eq!(Position { x: 5, y: 9 }, path[7]);
+    }
+
+    #[test]
+    fn distances_around_walls() {
+        let mut board :Board = Default::default();
+        // Wall in front of player 1 (index 0), covering columns e and f.
+        board.add_wall(Orientation::Horizontal, Position { x: 5, y: 1 });
+        assert_eq!(Some(9), board.distance_to_goal(0));
+        let paths = board.shortest_paths(0);
+        // Go left to d1 and then up, or go right past the wall to g1 (too long).
+        assert_eq!(1, paths.len());
+        assert_eq!(Position { x: 4, y: 1 }, paths[0][0]);
+        assert_eq!(Some(paths[0].clone()), board.shortest_path(0));
+
+        // Walls on both sides enclose the pawn (not a legal position).
+        board.add_wall(Orientation::Vertical, Position { x: 4, y: 1 });
+        board.add_wall(Orientation::Vertical, Position { x: 6, y: 1 });
+        assert_eq!(None, board.distance_to_goal(0));
+        assert_eq!(None, board.shortest_path(0));
+        assert!(board.shortest_paths(0).is_empty());
+        assert_eq!(UNREACHABLE, board.distance_map(0)[encode9(6,1)]);
+        assert_eq!(7, board.distance_map(0)[encode9(5,2)]);
+        // The other player can go around the enclosed cells.
+        assert_eq!(Some(9), board.distance_to_goal(1));
+    }
+}
@@ -1,6 +1,14 @@
 mod model;
 mod modelsimple;
 mod parser;
+mod movegen;
+mod distance;
+mod protocol;
+mod record;
 
 pub use modelsimple::*;
 pub use parser::*;
+pub use movegen::*;
+pub use distance::*;
+pub use protocol::*;
+pub use record::*;
@@ -1,4 +1,5 @@
 use serde::{Serialize, Deserialize};
+use crate::parser::printer;
 
 
 pub trait Player {
@@ -8,6 +9,22 @@ pub trait Player {
     fn reset(&mut self);
 }
 
+impl<P :Player + ?Sized> Player for Box<P> {
+    fn mv(&mut self, mv :Option<Move>) -> Move { (**self).mv(mv) }
+    fn reset(&mut self) { (**self).reset() }
+}
+
+/// A player's clock.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Clock {
+    /// Time left for the rest of the game.
+    pub remaining :std::time::Duration,
+    /// Time added to the clock after each move.
+    pub increment :std::time::Duration,
+    /// Time limit for the next move, when the game has a fixed time per move.
+    pub move_time :Option<std::time::Duration>,
+}
+
 
 #[derive(PartialEq, Eq, Copy, Clone, Debug, Hash)]
 #[derive(Serialize, Deserialize)]
@@ -22,55 +39,113 @@ pub struct Position {
     pub y: i64,
 }
 
-// use std::hash::Hash;
-#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+/// Compact board representation. Walls are stored as two bit sets, one for
+/// each orientation, and the pawn positions are packed as `encode9` cell indices,
+/// so that the board is `Copy` and cheap to hash and compare.
+///
+/// Wall coordinates:
+///   for horizontal walls:
+///   _a_b_
+///    c d  we give the top left (lowest) coordinate, a.
+///
+///   for vertical walls:
+///    a|b
+///    c|d  we give the top left (lowest) coordinate, a.
+///
+///   So both x and y coordinates can be in [1,8], and the wall at (x,y)
+///   is stored as bit `encode8(x-1,y-1)` in the bit set for its orientation.
+///
+/// The board keeps a Zobrist hash of the walls and pawn positions, updated
+/// incrementally as moves are made (see `zobrist_key`).
+///
+/// The board is serialized through `VecBoard`, so the JSON format
+/// (sent to the web GUI) is the same as the list-of-walls representation.
+/// Deserializing checks that the board is valid.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Hash)]
 #[derive(Serialize, Deserialize)]
+#[serde(into = "VecBoard", try_from = "VecBoard")]
 pub struct Board {
-    // with this non-optimized size, we get:
-    //  player=64, positions: 2*2*64, walls_left: 2*64, vec=3*64?
-    //  so (1+4+2+3) = 80 bytes
-    // Can be improved to 24 bytes (2x64 bit walls, 7x8bit numbers -- current player, walls left,
-    // positions)
-    pub player :usize, // 1 u8
-    pub positions :[Position;2], // 2*2 u8
-    pub walls_left :[usize;2], // 2*u8
-    // total size: 7 u8 < 64bit
-    //
-    //
-    pub walls :Vec<(Orientation,Position)>
-        // a wall is an orientation and a coordinate.
-        // for horizontal walls:
-        // _a_b_
-        //  c d  we give the top left (lowest) coordinate, a.
-        //
-        // for vertical walls:
-        //  a|b
-        //  c|d  we give the top left (lowest) coordinate, a.
-        //
-        //  So both x and y coordinates can be in [1,8]. 
-        //  X=9 would place the vertical wall outside the board, horizontal would stick out.
-        //  Y=9 would place the horizontal wall below the board, vertical would stick out.
-        //
-        //  checking whether two coordinates between 4-connected positions 
-        //  are separated by a wall, amounts to checking:
-        //   i. (x,y)-(x+1,y)  -- check vertical walls 
-        //      at position x or x-1, y=y.
-        //  ii. (x,y)-(x,y+1)  -- check horizontal walls
-        //      at position x=x, y= y or y-1.
+    horizontal_walls :u64,
+    vertical_walls :u64,
+    key :u64,
+    cells :[u8;2],
+    pub walls_left :[u8;2],
+    player :u8,
+    // total size: 3*64 bits + 5*8 bits, padded to 32 bytes.
+}
+
+/// Board with walls given as a list, as used by the JSON format
+/// (and the original representation of the board).
+#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+#[derive(Serialize, Deserialize)]
+pub struct VecBoard {
+    pub player :usize,
+    pub positions :[Position;2],
+    pub walls_left :[usize;2],
+    pub walls :Vec<(Orientation,Position)>,
+}
+
+impl From<Board> for VecBoard {
+    fn from(board :Board) -> VecBoard {
+        VecBoard {
+            player: board.player(),
+            positions: [board.position(0), board.position(1)],
+            walls_left: [board.walls_left[0] as usize, board.walls_left[1] as usize],
+            walls: board.walls().collect(),
+        }
+    }
+}
+
+impl std::convert::TryFrom<VecBoard> for Board {
+    type Error = String;
+    /// Convert a board from the list-of-walls representation, checking that
+    /// the pawns and walls are on the board, the walls do not overlap or block
+    /// a player's path, and the player to move and walls left are in range.
+    fn try_from(board :VecBoard) -> Result<Board,String> {
+        for pos in &board.positions {
+            if !in_bounds1to9(pos) { return Err(format!("pawn position {:?} is outside the board", pos)); }
+        }
+        if board.positions[0] == board.positions[1] { return Err("the pawns are on the same cell".to_string()); }
+        if board.player > 1 { return Err(format!("invalid player to move {}", board.player)); }
+        if board.walls_left.iter().any(|n| *n > u8::MAX as usize) {
+            return Err(format!("invalid number of walls left {:?}", board.walls_left));
+        }
+        let mut b = Board {
+            horizontal_walls: 0,
+            vertical_walls: 0,
+            key: 0,
+            cells: [encode9(board.positions[0].x, board.positions[0].y) as u8,
+                    encode9(board.positions[1].x, board.positions[1].y) as u8],
+            walls_left: [board.walls_left[0] as u8, board.walls_left[1] as u8],
+            player: board.player as u8,
+        };
+        for (ori,pos) in board.walls {
+            if !in_bounds1to8(&pos) || b.wall_conflicts(ori,pos) {
+                return Err(format!("invalid wall {:?} at {:?}", ori, pos));
+            }
+            b.add_wall(ori,pos);
+        }
+        if b.distance_to_goal(0).is_none() || b.distance_to_goal(1).is_none() {
+            return Err("the walls block a player's path to the goal".to_string());
+        }
+        b.key = b.compute_key();
+        Ok(b)
+    }
 }
 
 impl Default for Board {
     fn default() -> Board {
         // Starting positions for players; no walls.
-        Board {
-            player: 0,
+        let mut board = Board {
+            horizontal_walls: 0,
+            vertical_walls: 0,
+            key: 0,
+            cells: [encode9(5,1) as u8, encode9(5,9) as u8],
             walls_left: [10,10],
-            positions: [
-                Position { x: 5, y: 1 },
-                Position { x: 5, y: 9 },
-            ],
-            walls: Vec::new(),
-        }
+            player: 0,
+        };
+        board.key = board.compute_key();
+        board
     }
 }
 
@@ -80,51 +155,221 @@ pub enum Move {
     WallAt(Orientation,Position),
 }
 
+/// The reason a move is not allowed on a board.
+#[derive(Copy,Clone,Debug, PartialEq, Eq)]
+pub enum IllegalMove {
+    /// The pawn or wall position is outside the board.
+    OutOfBounds,
+    /// The wall overlaps or crosses the given existing wall.
+    WallOverlaps(Orientation,Position),
+    /// The current player has no walls left.
+    NoWallsLeft,
+    /// The wall would leave the given player without a path to their goal row.
+    BlocksPath(usize),
+    /// There is a wall between the pawn and the target cell.
+    PawnBlockedByWall,
+    /// The target cell is occupied by the other pawn.
+    Occupied,
+    /// The target cell is not adjacent, and is not a valid jump over the other pawn.
+    InvalidJump,
+    /// One of the players has already reached their goal row.
+    GameOver,
+}
+
+impl std::fmt::Display for IllegalMove {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            IllegalMove::OutOfBounds => write!(f, "position is outside the board"),
+            IllegalMove::WallOverlaps(ori,pos) => 
+                write!(f, "wall overlaps existing wall {}", printer(&Move::WallAt(*ori,*pos))),
+            IllegalMove::NoWallsLeft => write!(f, "no walls left"),
+            IllegalMove::BlocksPath(player) => 
+                write!(f, "wall would block player {}'s path to the goal", player+1),
+            IllegalMove::PawnBlockedByWall => write!(f, "pawn is blocked by a wall"),
+            IllegalMove::Occupied => write!(f, "cell is occupied by the other pawn"),
+            IllegalMove::InvalidJump => write!(f, "invalid jump"),
+            IllegalMove::GameOver => write!(f, "game is already over"),
+        }
+    }
+}
+
+impl std::error::Error for IllegalMove {}
+
+/// Information needed to take back a move, returned from `Board::make_move`.
+#[derive(Copy,Clone,Debug, PartialEq, Eq)]
+pub struct Undo {
+    mv :Move,
+    from :Position,
+}
+
 impl Board {
-    pub fn integrate(&mut self, mv :Move) -> Result<(),()> {
+    pub fn integrate(&mut self, mv :Move) -> Result<(),IllegalMove> {
+        self.make_move(mv).map(|_| ())
+    }
+
+    /// Perform a move, like `integrate`, and return the information
+    /// needed to restore the board using `unmake_move`.
+    pub fn make_move(&mut self, mv :Move) -> Result<Undo,IllegalMove> {
+        self.check_move(&mv)?;
+        let from = self.position(self.player());
         match mv {
             Move::PawnTo(pos) => {
-                if !self.is_valid_pawn_move(&pos) { return Err(()); }
-                self.positions[self.player] = pos;
+                self.set_position(self.player(), pos);
             },
             Move::WallAt(ori,pos) => {
-                if !self.can_add_wall(ori,pos) { return Err(()); }
-                self.walls.push((ori,pos));
-                self.walls_left[self.player] -= 1;
-                // TODO sort?
+                self.add_wall(ori,pos);
+                self.walls_left[self.player()] -= 1;
             }
         }
 
         self.player = 1 - self.player;
-        Ok(())
+        Ok(Undo { mv, from })
+    }
+
+    /// Take back the last move made by `make_move`. Undo tokens must be
+    /// given back in the reverse order of the moves.
+    pub fn unmake_move(&mut self, undo :Undo) {
+        self.player = 1 - self.player;
+        match undo.mv {
+            Move::PawnTo(_) => {
+                self.set_position(self.player(), undo.from);
+            },
+            Move::WallAt(ori,pos) => {
+                self.remove_wall(ori,pos);
+                self.walls_left[self.player()] += 1;
+            },
+        }
+    }
+
+    /// The player whose turn it is (0 or 1).
+    pub fn player(&self) -> usize {
+        self.player as usize
+    }
+
+    pub fn set_player(&mut self, player :usize) {
+        self.player = player as u8;
+    }
+
+    pub fn position(&self, player :usize) -> Position {
+        decode9(self.cells[player] as usize)
+    }
+
+    /// Move a pawn without checking the rules.
+    pub fn set_position(&mut self, player :usize, pos :Position) {
+        let cell = encode9(pos.x,pos.y) as u8;
+        self.key ^= zobrist_pawn(player, self.cells[player]) ^ zobrist_pawn(player, cell);
+        self.cells[player] = cell;
+    }
+
+    /// Add a wall without checking the rules or counting walls left.
+    /// The position must be in bounds ([1,8]).
+    pub fn add_wall(&mut self, ori :Orientation, pos :Position) {
+        if self.has_wall(ori,pos) { return; }
+        bitset_add_wall(&mut self.horizontal_walls, &mut self.vertical_walls, &ori, &pos);
+        self.key ^= zobrist_wall(ori, encode8(pos.x-1,pos.y-1));
+    }
+
+    /// Remove a wall without checking the rules or counting walls left.
+    pub fn remove_wall(&mut self, ori :Orientation, pos :Position) {
+        use bit_field::BitField;
+        if !self.has_wall(ori,pos) { return; }
+        match ori {
+            Orientation::Horizontal => { self.horizontal_walls.set_bit(encode8(pos.x-1,pos.y-1), false); },
+            Orientation::Vertical => { self.vertical_walls.set_bit(encode8(pos.x-1,pos.y-1), false); },
+        }
+        self.key ^= zobrist_wall(ori, encode8(pos.x-1,pos.y-1));
+    }
+
+    /// Zobrist hash of the board, for use in transposition tables.
+    /// Walls and pawns are hashed incrementally when they change, while the 
+    /// walls left and the current player are included when the key is requested.
+    pub fn zobrist_key(&self) -> u64 {
+        let side = if self.player == 1 { zobrist(ZOBRIST_SIDE) } else { 0 };
+        self.key ^ side ^ 
+            zobrist_walls_left(0, self.walls_left[0]) ^ 
+            zobrist_walls_left(1, self.walls_left[1])
+    }
+
+    /// Hash of the walls and pawns, computed from scratch.
+    fn compute_key(&self) -> u64 {
+        let mut key = zobrist_pawn(0, self.cells[0]) ^ zobrist_pawn(1, self.cells[1]);
+        for (ori,pos) in self.walls() {
+            key ^= zobrist_wall(ori, encode8(pos.x-1,pos.y-1));
+        }
+        key
+    }
+
+    pub fn has_wall(&self, ori :Orientation, pos :Position) -> bool {
+        wall_bit(self.wall_bitset(ori), pos.x, pos.y)
+    }
+
+    fn wall_bitset(&self, ori :Orientation) -> u64 {
+        match ori {
+            Orientation::Horizontal => self.horizontal_walls,
+            Orientation::Vertical => self.vertical_walls,
+        }
+    }
+
+    /// Iterate over the walls on the board, horizontal walls first,
+    /// each in raster order.
+    pub fn walls(&self) -> impl Iterator<Item = (Orientation,Position)> {
+        let (h,v) = (self.horizontal_walls, self.vertical_walls);
+        let wall_positions = |ori, bits :u64| (0..64)
+            .filter(move |i| (bits >> i) & 1 == 1)
+            .map(move |i| (ori, Position { x: (i%8) as i64 + 1, y: (i/8) as i64 + 1 }));
+        wall_positions(Orientation::Horizontal, h)
+            .chain(wall_positions(Orientation::Vertical, v))
+    }
+
+    /// Does the wall share an end point or its center point with a wall on the board?
+    pub fn touches_wall(&self, ori :Orientation, pos :Position) -> bool {
+        let points = wall_points(ori, pos);
+        self.walls().any(|(o,p)| wall_points(o,p) & points != 0)
+    }
+
+    pub fn num_walls(&self) -> usize {
+        (self.horizontal_walls.count_ones() + self.vertical_walls.count_ones()) as usize
     }
 
     pub fn is_valid_move(&self, mv :&Move) -> bool {
+        self.check_move(mv).is_ok()
+    }
+
+    /// Check whether the current player can perform the move, 
+    /// and give the reason if not.
+    pub fn check_move(&self, mv :&Move) -> Result<(),IllegalMove> {
+        if self.get_winner().is_some() { return Err(IllegalMove::GameOver); }
         match mv {
-            Move::PawnTo(pos) => self.is_valid_pawn_move(pos),
-            Move::WallAt(ori,pos) => self.can_add_wall(*ori,*pos),
+            Move::PawnTo(pos) => self.check_pawn_move(pos),
+            Move::WallAt(ori,pos) => self.check_wall(*ori,*pos),
         }
     }
 
     pub fn is_valid_pawn_move(&self, pos :&Position) -> bool {
-        if !in_bounds1to9(pos) { return false; }
-        if is_neighbor(&self.positions[self.player], pos) {
-            eprintln!("{:?} IS NEIGHBOR OF {:?}", &self.positions[self.player], pos);
-            if !self.is_empty(pos) { return false; }
-            if self.wall_between(&self.positions[self.player], pos) { return false; }
-            true
+        self.check_pawn_move(pos).is_ok()
+    }
+
+    pub fn check_pawn_move(&self, pos :&Position) -> Result<(),IllegalMove> {
+        if !in_bounds1to9(pos) { return Err(IllegalMove::OutOfBounds); }
+        let current = self.position(self.player());
+        if is_neighbor(&current, pos) {
+            if !self.is_empty(pos) { return Err(IllegalMove::Occupied); }
+            if self.wall_between(&current, pos) { return Err(IllegalMove::PawnBlockedByWall); }
+            Ok(())
+        } else if self.is_valid_jump(*pos) {
+            Ok(())
         } else {
-            self.is_valid_jump(*pos)
+            Err(IllegalMove::InvalidJump)
         }
     }
 
     pub fn is_empty(&self, pos :&Position) -> bool {
-        self.positions[0] != *pos && self.positions[1] != *pos
+        self.position(0) != *pos && self.position(1) != *pos
     }
 
     pub fn get_winner(&self) -> Option<usize> {
-        if self.positions[0].y == 9 { return Some(0); }
-        if self.positions[1].y == 1 { return Some(1); }
+        if self.position(0).y == 9 { return Some(0); }
+        if self.position(1).y == 1 { return Some(1); }
         None
     }
 
@@ -134,61 +379,78 @@ impl Board {
     /// 4-connected neighbors.
     pub fn wall_between(&self, a :&Position, b :&Position) -> bool {
         if (b.x-a.x).abs() == 1 && b.y-a.y == 0 {
-            // check vertical walls
-            for (ori,pos) in &self.walls {
-                if let Orientation::Vertical = ori {
-                    let left = b.x.min(a.x);
-                    if left == pos.x && (pos.y == b.y || pos.y +1 == b.y) {
-                        return true;
-                    }
-                }
-            }
+            // check vertical walls at x=left, y=y or y-1.
+            let left = b.x.min(a.x);
+            wall_bit(self.vertical_walls, left, b.y) || wall_bit(self.vertical_walls, left, b.y-1)
         } else if (b.y-a.y).abs() == 1 && b.x-a.x == 0 {
-            // check horizontal walls
-            for (ori,pos) in &self.walls {
-                if let Orientation::Horizontal = ori {
-                    let top = b.y.min(a.y);
-                    if top == pos.y && (pos.x == b.x || pos.x +1 == b.x) {
-                        return true;
-                    }
-                }
-            }
+            // check horizontal walls at y=top, x=x or x-1.
+            let top = b.y.min(a.y);
+            wall_bit(self.horizontal_walls, b.x, top) || wall_bit(self.horizontal_walls, b.x-1, top)
         } else {
             eprintln!("Could not check for wall between {:?} and {:?}", a, b);
             panic!();
         }
-        false
     }
 
     pub fn can_add_wall(&self, ori :Orientation, pos :Position) -> bool {
-        if !(self.walls_left[self.player] > 0) { return false; }
-        // TODO improve efficiency by storing bit sets for checking conflicts
-        if !in_bounds1to8(&pos) { return false; }
-
-        for (o,p) in &self.walls {
-            let x=  wall_conflicts(&ori,&pos,o,p) ;
-            //println!("wall conflicts? {:?}", x);
-            if x {
-                return false;
-            }
+        self.check_wall(ori,pos).is_ok()
+    }
+
+    pub fn check_wall(&self, ori :Orientation, pos :Position) -> Result<(),IllegalMove> {
+        if self.walls_left[self.player()] == 0 { return Err(IllegalMove::NoWallsLeft); }
+        if !in_bounds1to8(&pos) { return Err(IllegalMove::OutOfBounds); }
+        if let Some((o,p)) = self.conflicting_wall(ori,pos) { 
+            return Err(IllegalMove::WallOverlaps(o,p)); 
+        }
+        if let Some(player) = self.blocked_player(ori,pos) {
+            return Err(IllegalMove::BlocksPath(player));
         }
+        Ok(())
+    }
+
+    /// Checks whether a wall would overlap or cross any wall on the board.
+    /// The position must be in bounds ([1,8]).
+    pub fn wall_conflicts(&self, ori :Orientation, pos :Position) -> bool {
+        self.conflicting_wall(ori,pos).is_some()
+    }
 
-        if !self.goal_reachable(ori,pos) {
-            return false;
+    /// Find an existing wall that would overlap or cross the given wall.
+    pub fn conflicting_wall(&self, ori :Orientation, pos :Position) -> Option<(Orientation,Position)> {
+        let (same, crossing) = match ori {
+            Orientation::Horizontal => (self.horizontal_walls, self.vertical_walls),
+            Orientation::Vertical => (self.vertical_walls, self.horizontal_walls),
+        };
+
+        // Walls of the same orientation overlap if they are less than two steps
+        // apart along the wall's direction.
+        let (dx,dy) = match ori {
+            Orientation::Horizontal => (1,0),
+            Orientation::Vertical => (0,1),
+        };
+        for d in &[-1,0,1] {
+            let p = Position { x: pos.x + d*dx, y: pos.y + d*dy };
+            if wall_bit(same, p.x, p.y) { return Some((ori,p)); }
         }
 
-        true
+        if wall_bit(crossing, pos.x, pos.y) {
+            let other = match ori {
+                Orientation::Horizontal => Orientation::Vertical,
+                Orientation::Vertical => Orientation::Horizontal,
+            };
+            return Some((other,pos));
+        }
+        None
     }
 
     pub fn is_valid_jump(&self, pos :Position) -> bool {
-        let start = self.positions[self.player];
+        let start = self.position(self.player());
         let end = pos;
 
         let horizontal_jump = (end.x - start.x).abs() == 2 && end.y == start.y;
         let vertical_jump   = (end.y - start.y).abs() == 2 && end.x == start.x;
         let diagonal_jump   = (end.x - start.x).abs() == 1 && (end.y - start.y).abs() == 1;
 
-        let other_player_pos = self.positions[1 - self.player];
+        let other_player_pos = self.position(1 - self.player());
         if !is_neighbor(&start, &other_player_pos) { return false; }
 
         if horizontal_jump || vertical_jump {
@@ -199,6 +461,8 @@ impl Board {
                !self.wall_between(&middle_pos,&end)
 
         } else if diagonal_jump {
+            // The jump lands beside the other player, not diagonally behind the current player.
+            if !is_neighbor(&other_player_pos, &end) { return false; }
 
             // Opposite side of the other player from the current player.
             let other_side = Position { x: other_player_pos.x + (other_player_pos.x - start.x),
@@ -214,34 +478,72 @@ impl Board {
     }
 
     pub fn get_wall_bitsets(&self) -> (u64,u64) {
-        let mut horizontal_walls = 0u64;
-        let mut vertical_walls = 0u64;
-
-        for (ori,pos) in &self.walls {
-            bitset_add_wall(&mut horizontal_walls, &mut vertical_walls, &ori, &pos);
-        }
-        (horizontal_walls,vertical_walls)
+        (self.horizontal_walls, self.vertical_walls)
     }
 
     pub fn goal_reachable(&self, ori :Orientation, pos :Position) -> bool {
+        self.blocked_player(ori,pos).is_none()
+    }
+
+    /// Returns a player who would not be able to reach their goal row 
+    /// if the wall was added.
+    ///
+    /// Assumes that both players can reach their goal rows before the wall 
+    /// is added, which always holds for positions reached by legal moves.
+    pub fn blocked_player(&self, ori :Orientation, pos :Position) -> Option<usize> {
+        if !wall_closes_loop(self.horizontal_walls, self.vertical_walls, ori, pos) {
+            // The new wall does not split any region of the board.
+            return None;
+        }
+
         let (mut horizontal_walls,mut vertical_walls) = self.get_wall_bitsets();
         bitset_add_wall(&mut horizontal_walls, &mut vertical_walls, &ori, &pos);
 
-        // TODO store bit set directly in Board instead of converting 
-        // vector of walls (not needed information when game is progressing forward).
-
         // player 1 must be able to reach top
         // and player 2 must be able to reach bootom
-        let p1 = goal_reachable(horizontal_walls,
-                       vertical_walls,
-                       self.positions[0], 9);
-        let p2 = goal_reachable(horizontal_walls,
-                       vertical_walls,
-                       self.positions[1], 1);
-        p1 && p2
+        if !goal_reachable(horizontal_walls, vertical_walls, self.position(0), 9) {
+            return Some(0);
+        }
+        if !goal_reachable(horizontal_walls, vertical_walls, self.position(1), 1) {
+            return Some(1);
+        }
+        None
     }
 }
 
+// Zobrist keys are generated by hashing an index for each feature of the board.
+const ZOBRIST_WALLS :u64 = 0;        // 2*64 walls
+const ZOBRIST_PAWNS :u64 = 128;      // 2*81 pawn positions
+const ZOBRIST_WALLS_LEFT :u64 = 290; // 2*256 walls left counts
+const ZOBRIST_SIDE :u64 = 802;
+
+/// Pseudo-random key for a board feature (splitmix64).
+fn zobrist(index :u64) -> u64 {
+    let mut z = index.wrapping_add(1).wrapping_mul(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+fn zobrist_wall(ori :Orientation, bit :usize) -> u64 {
+    let offset = match ori { Orientation::Horizontal => 0, Orientation::Vertical => 64 };
+    zobrist(ZOBRIST_WALLS + offset + bit as u64)
+}
+
+fn zobrist_pawn(player :usize, cell :u8) -> u64 {
+    zobrist(ZOBRIST_PAWNS + 81*player as u64 + cell as u64)
+}
+
+fn zobrist_walls_left(player :usize, walls_left :u8) -> u64 {
+    zobrist(ZOBRIST_WALLS_LEFT + 256*player as u64 + walls_left as u64)
+}
+
+/// Is the wall at (x,y) set in the bit set? Out of bounds walls are never set.
+fn wall_bit(bits :u64, x :i64, y :i64) -> bool {
+    use bit_field::BitField;
+    (1..=8).contains(&x) && (1..=8).contains(&y) && bits.get_bit(encode8(x-1,y-1))
+}
+
 fn bitset_add_wall(horizontal_walls :&mut u64, vertical_walls :&mut u64, 
                    ori :&Orientation, pos :&Position) {
     use bit_field::BitField;
@@ -267,6 +569,94 @@ pub fn encode8(x :i64, y :i64) -> usize {
     (x+8*y) as usize
 }
 
+/// Corner points of the board, between the cells, are numbered `i+10*j` 
+/// for i and j in [0,9]. Corner (i,j) is below and to the right of cell (i,j),
+/// so the wall at (x,y) is centered on corner (x,y).
+/// The points of the board's border are all connected to each other.
+const BORDER_POINTS :u128 = {
+    let mut border = 0u128;
+    let mut i = 0;
+    while i < 10 {
+        border |= 1 << i;            // j = 0
+        border |= 1 << (i + 90);     // j = 9
+        border |= 1 << (10*i);       // i = 0
+        border |= 1 << (9 + 10*i);   // i = 9
+        i += 1;
+    }
+    border
+};
+
+/// The three corner points (two ends and the center) touched by a wall, as a bit set.
+fn wall_points(ori :Orientation, pos :Position) -> u128 {
+    let center = (pos.x + 10*pos.y) as u32;
+    let step = match ori {
+        Orientation::Horizontal => 1,
+        Orientation::Vertical => 10,
+    };
+    (1u128 << (center - step)) | (1u128 << center) | (1u128 << (center + step))
+}
+
+/// Extend a set of corner points to include every point connected to it
+/// by walls or by the border.
+fn connected_points(horizontal_walls :u64, vertical_walls :u64, mut points :u128) -> u128 {
+    loop {
+        let mut extended = points;
+        if extended & BORDER_POINTS != 0 { extended |= BORDER_POINTS; }
+        for (ori,bits) in &[(Orientation::Horizontal, horizontal_walls), 
+                            (Orientation::Vertical, vertical_walls)] {
+            let mut bits = *bits;
+            while bits != 0 {
+                let i = bits.trailing_zeros() as i64;
+                bits &= bits - 1;
+                let wall = wall_points(*ori, Position { x: i%8 + 1, y: i/8 + 1 });
+                if wall & extended != 0 { extended |= wall; }
+            }
+        }
+        if extended == points { return points; }
+        points = extended;
+    }
+}
+
+/// Checks whether adding the wall could split a region of the board in two.
+/// This can only happen if the wall closes a loop in the dual graph, i.e. 
+/// two of the wall's points are already connected through other walls
+/// or the border. Most walls touch at most one other wall or the border, 
+/// and are handled without any search.
+pub fn wall_closes_loop(horizontal_walls :u64, vertical_walls :u64, 
+                        ori :Orientation, pos :Position) -> bool {
+    let new_wall = wall_points(ori,pos);
+
+    let mut touched = BORDER_POINTS;
+    let mut bits = horizontal_walls;
+    while bits != 0 {
+        let i = bits.trailing_zeros() as i64;
+        bits &= bits - 1;
+        touched |= wall_points(Orientation::Horizontal, Position { x: i%8 + 1, y: i/8 + 1 });
+    }
+    let mut bits = vertical_walls;
+    while bits != 0 {
+        let i = bits.trailing_zeros() as i64;
+        bits &= bits - 1;
+        touched |= wall_points(Orientation::Vertical, Position { x: i%8 + 1, y: i/8 + 1 });
+    }
+
+    let mut touching = new_wall & touched;
+    if touching.count_ones() <= 1 { 
+        // Isolated wall, or touching only one point.
+        return false; 
+    }
+
+    // Is any pair of the touched points connected?
+    while touching != 0 {
+        let point = touching & touching.wrapping_neg();
+        touching &= !point;
+        if connected_points(horizontal_walls, vertical_walls, point) & touching != 0 {
+            return true;
+        }
+    }
+    false
+}
+
 pub fn goal_reachable(horizontal_walls: u64,
                       vertical_walls: u64, 
                       pos :Position, 
@@ -276,12 +666,8 @@ pub fn goal_reachable(horizontal_walls: u64,
     let mut uf = UnionFind::new(9*9);
 
 
-    // TODO: It could be that finding connectivity
-    // in the dual graph is faster, becuse much of the
-    // size of the state space is going to be in placing walls
-    // when there is few walls and little chance to break connnectivity.
-    // In that case, iterating only over the set bits of the 
-    // walls bitsets and connecting nodes in the dual graph might be faster.
+    // Checking connectivity in the dual graph first (see `wall_closes_loop`)
+    // avoids this search for most wall placements.
 
     for x in 0..=8 {
         for y in 0..=8 {
@@ -328,27 +714,6 @@ pub fn goal_reachable(horizontal_walls: u64,
     return false;
 }
 
-fn wall_conflicts(oa :&Orientation, pa :&Position, ob :&Orientation, pb :&Position) -> bool {
-    //println!("CHeck wall conflict {:?} {:?}", (oa,pa), (ob,pb));
-    if oa == ob {
-        match oa {
-            Orientation::Horizontal => {
-                pa.y == pb.y && (pa.x == pb.x || pa.x + 1 == pb.x || pb.x + 1 == pa.x)
-            },
-            Orientation::Vertical => {
-                pa.x == pb.x && (pa.y == pb.y || pa.y + 1 == pb.y || pb.y + 1 == pa.y)
-            },
-        }
-    } else {
-        if let Orientation::Vertical = oa {
-            // A is vertical, B is horizontal
-            pa == pb
-        } else {
-            wall_conflicts(ob,pb,oa,pa)
-        }
-    }
-}
-
 pub fn in_bounds1to9(pos :&Position) -> bool {
     pos.x > 0 && pos.x <= 9 && pos.y > 0 && pos.y <= 9
 }
@@ -370,6 +735,8 @@ fn is_neighbor(a :&Position, b :&Position) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser::parse;
+    use std::convert::TryFrom;
     #[test]
     fn board() {
         let mut board :Board = Default::default();
@@ -377,47 +744,47 @@ mod tests {
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: -1, y: -1 })).is_err());
 
-        assert_eq!(0, board.player); // first player first
+        assert_eq!(0, board.player()); // first player first
 
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: 1, y: 1 })).is_ok());
 
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
 
         // Cannot place wall there
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: 1, y: 1 })).is_err());
 
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
 
         // Cannot place wall there
         assert!(board.integrate(
                 Move::WallAt(Orientation::Vertical, 
                              Position { x: 1, y: 1 })).is_err());
 
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
         
         // Cannot place wall there
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: 2, y: 1 })).is_err());
 
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
         //
         // CAN place wall there
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: 8, y: 1 })).is_ok());
 
-        assert_eq!(0, board.player); // first player again
+        assert_eq!(0, board.player()); // first player again
         // Cannot place wall there
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: 7, y: 1 })).is_err());
 
-        assert_eq!(0, board.player); // second player
+        assert_eq!(0, board.player()); // second player
     }
 
     #[test]
@@ -438,14 +805,14 @@ mod tests {
         assert!(board.integrate(
                 Move::WallAt(Orientation::Vertical, 
                              Position { x: 7, y: 6 })).is_ok());
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
         //println!("walls {:?}", board.walls);
         //
         // BOTH players should now become blocked, so the move should not go through.
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, 
                              Position { x: 8, y: 6 })).is_err());
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
     }
 
     #[test]
@@ -462,20 +829,20 @@ mod tests {
 
 
         let mut board: Board = Default::default();
-        board.positions[0] = Position {x: 5, y: 6};
-        board.positions[1] = Position {x: 5, y: 5};
+        board.set_position(0, Position {x: 5, y: 6});
+        board.set_position(1, Position {x: 5, y: 5});
 
         // now we can jump over
         assert!(board.integrate(
                 Move::PawnTo(Position { x: 5, y: 4 })).is_ok());
 
         let mut board: Board = Default::default();
-        board.positions[0] = Position {x: 5, y: 6};
-        board.positions[1] = Position {x: 5, y: 5};
+        board.set_position(0, Position {x: 5, y: 6});
+        board.set_position(1, Position {x: 5, y: 5});
 
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 6 })).is_ok());
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
 
         // now we can jump diagonally
         assert!(board.integrate(
@@ -483,26 +850,284 @@ mod tests {
 
 
         let mut board: Board = Default::default();
-        board.positions[0] = Position {x: 5, y: 6};
-        board.positions[1] = Position {x: 5, y: 5};
+        board.set_position(0, Position {x: 5, y: 6});
+        board.set_position(1, Position {x: 5, y: 5});
 
         assert!(board.integrate(
                 Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 6 })).is_ok());
-        assert_eq!(1, board.player); // second player
+        assert_eq!(1, board.player()); // second player
 
         // now we can jump diagonally #2
         assert!(board.integrate(
                 Move::PawnTo(Position { x: 6, y: 6 })).is_ok());
     }
 
+    #[test]
+    fn diagonal_jump_lands_beside_other_player() {
+        let mut board: Board = Default::default();
+        board.set_position(0, Position {x: 5, y: 6});
+        board.set_position(1, Position {x: 5, y: 5});
+        board.integrate(Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 6 })).unwrap();
+
+        for x in &[4,6] {
+            assert!(board.is_valid_jump(Position { x: *x, y: 6 }));
+            // Diagonally away from the other player.
+            assert!(!board.is_valid_jump(Position { x: *x, y: 4 }));
+            assert_eq!(Err(IllegalMove::InvalidJump), board.check_move(&Move::PawnTo(Position { x: *x, y: 4 })));
+        }
+    }
+
+    #[test]
+    fn zobrist_transposition() {
+        // The same position reached by different move orders has the same key.
+        let moves = ["e2", "e8", "a1h", "a8h"];
+        let play = |order :&[usize]| {
+            let mut board :Board = Default::default();
+            for i in order { board.integrate(parse(moves[*i]).unwrap()).unwrap(); }
+            board
+        };
+        let a = play(&[0,1,2,3]);
+        let b = play(&[2,3,0,1]);
+        let c = play(&[2,1,0,3]);
+        assert_eq!(a, b);
+        assert_eq!(a.zobrist_key(), b.zobrist_key());
+        assert_eq!(a.zobrist_key(), c.zobrist_key());
+
+        let mut d = a;
+        d.walls_left[0] += 1;
+        assert_ne!(a.zobrist_key(), d.zobrist_key());
+        let mut e = a;
+        e.set_player(1);
+        assert_ne!(a.zobrist_key(), e.zobrist_key());
+    }
+
+    #[test]
+    fn illegal_move_reasons() {
+        let mut board :Board = Default::default();
+        board.set_position(0, Position { x: 5, y: 5 });
+        board.set_position(1, Position { x: 5, y: 6 });
+        board.add_wall(Orientation::Vertical, Position { x: 5, y: 4 });
+
+        let check = |b :&Board, mv| b.check_move(&mv);
+        assert_eq!(Err(IllegalMove::OutOfBounds), 
+                   check(&board, Move::WallAt(Orientation::Horizontal, Position { x: 9, y: 1 })));
+        assert_eq!(Err(IllegalMove::WallOverlaps(Orientation::Vertical, Position { x: 5, y: 4 })), 
+                   check(&board, Move::WallAt(Orientation::Vertical, Position { x: 5, y: 5 })));
+        assert_eq!(Err(IllegalMove::WallOverlaps(Orientation::Vertical, Position { x: 5, y: 4 })), 
+                   check(&board, Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 4 })));
+        assert_eq!(Err(IllegalMove::PawnBlockedByWall), check(&board, Move::PawnTo(Position { x: 6, y: 5 })));
+        assert_eq!(Err(IllegalMove::Occupied), check(&board, Move::PawnTo(Position { x: 5, y: 6 })));
+        assert_eq!(Err(IllegalMove::InvalidJump), check(&board, Move::PawnTo(Position { x: 4, y: 6 })));
+        assert_eq!(Err(IllegalMove::InvalidJump), check(&board, Move::PawnTo(Position { x: 1, y: 1 })));
+        assert_eq!(Ok(()), check(&board, Move::PawnTo(Position { x: 5, y: 7 })));
+
+        board.walls_left[0] = 0;
+        assert_eq!(Err(IllegalMove::NoWallsLeft), 
+                   check(&board, Move::WallAt(Orientation::Horizontal, Position { x: 1, y: 1 })));
+
+        let mut board :Board = Default::default();
+        board.add_wall(Orientation::Vertical, Position { x: 4, y: 1 });
+        board.add_wall(Orientation::Vertical, Position { x: 5, y: 1 });
+        assert_eq!(Err(IllegalMove::BlocksPath(0)), 
+                   check(&board, Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 2 })));
+
+        board.set_position(1, Position { x: 5, y: 1 });
+        board.set_position(0, Position { x: 4, y: 1 });
+        assert_eq!(Err(IllegalMove::GameOver), check(&board, Move::PawnTo(Position { x: 4, y: 2 })));
+    }
+
     #[test]
     pub fn board_struct_size() {
         // The size of Board should be as small as possible
         // to ensure efficient memoization of the heuristic function 
         // and the minimax function.
         //
-        assert_eq!(8*3, std::mem::size_of::<Vec<usize>>());
-        assert_eq!(8*(3+7), std::mem::size_of::<Board>()); // TODO optimize size
+        assert_eq!(8*4, std::mem::size_of::<Board>());
+    }
+
+    #[test]
+    fn vec_board_conversion() {
+        let mut board :Board = Default::default();
+        for mv in &[Move::WallAt(Orientation::Horizontal, Position { x: 1, y: 1 }),
+                    Move::WallAt(Orientation::Vertical, Position { x: 8, y: 8 }),
+                    Move::PawnTo(Position { x: 4, y: 1 }),
+                    Move::WallAt(Orientation::Vertical, Position { x: 4, y: 3 }),
+                    Move::PawnTo(Position { x: 4, y: 2 })] {
+            board.integrate(*mv).unwrap();
+        }
+
+        let vec_board :VecBoard = board.into();
+        assert_eq!(3, vec_board.walls.len());
+        assert_eq!(Position { x: 4, y: 2 }, vec_board.positions[0]);
+        assert_eq!([9,8], vec_board.walls_left);
+        assert_eq!(1, vec_board.player);
+        assert_eq!(Ok(board), Board::try_from(vec_board.clone()));
+
+        let invalid = |change :&dyn Fn(&mut VecBoard)| {
+            let mut b = vec_board.clone();
+            change(&mut b);
+            Board::try_from(b).is_err()
+        };
+        assert!(invalid(&|b| b.positions[1] = Position { x: 10, y: 1 }));
+        assert!(invalid(&|b| b.positions[1] = Position { x: 0, y: 5 }));
+        assert!(invalid(&|b| b.positions[1] = b.positions[0]));
+        assert!(invalid(&|b| b.player = 2));
+        assert!(invalid(&|b| b.walls_left[0] = 1000));
+        assert!(invalid(&|b| b.walls.push((Orientation::Horizontal, Position { x: 9, y: 1 }))));
+        assert!(invalid(&|b| b.walls.push((Orientation::Horizontal, Position { x: 2, y: 1 }))));
+        assert!(invalid(&|b| b.walls.push((Orientation::Horizontal, Position { x: 4, y: 3 }))));
+        // Boxing in player 1 in the corner under the wall at a1h.
+        assert!(invalid(&|b| {
+            b.positions[0] = Position { x: 1, y: 1 };
+            b.walls.push((Orientation::Vertical, Position { x: 2, y: 1 }));
+        }));
+        assert!(!invalid(&|b| b.positions[0] = Position { x: 1, y: 1 }));
+    }
+
+    fn random_valid_move(board :&Board, rng :&mut impl rand::Rng) -> Option<Move> {
+        let mut moves = Vec::new();
+        for x in 1..=9 {
+            for y in 1..=9 {
+                moves.push(Move::PawnTo(Position { x, y }));
+                if x <= 8 && y <= 8 {
+                    moves.push(Move::WallAt(Orientation::Horizontal, Position { x, y }));
+                    moves.push(Move::WallAt(Orientation::Vertical, Position { x, y }));
+                }
+            }
+        }
+        moves.retain(|m| board.is_valid_move(m));
+        if moves.is_empty() { return None; }
+        Some(moves[rng.gen_range(0, moves.len())])
+    }
+
+    #[test]
+    fn make_unmake_random() {
+        use rand::{SeedableRng, Rng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        for _ in 0..20 {
+            let original :Board = Default::default();
+            let mut board = original;
+            let mut history = Vec::new();
+            let length = rng.gen_range(1, 60);
+            while history.len() < length && board.get_winner().is_none() {
+                let mv = match random_valid_move(&board, &mut rng) {
+                    Some(mv) => mv,
+                    None => break,
+                };
+                let before = board;
+                let undo = board.make_move(mv).unwrap();
+                assert_eq!(board.compute_key(), board.key);
+                assert_ne!(before.zobrist_key(), board.zobrist_key());
+                history.push((before, undo));
+
+                // Sometimes take back a move in the middle of the sequence.
+                if rng.gen_bool(0.2) {
+                    let (before, undo) = history.pop().unwrap();
+                    board.unmake_move(undo);
+                    assert_eq!(before, board);
+                }
+            }
+
+            while let Some((before, undo)) = history.pop() {
+                board.unmake_move(undo);
+                assert_eq!(before, board);
+            }
+            assert_eq!(original, board);
+        }
+    }
+
+    #[test]
+    fn touches_wall() {
+        let mut board :Board = Default::default();
+        board.add_wall(Orientation::Horizontal, Position { x: 4, y: 4 });
+        // End to end, and a vertical wall through the end point.
+        assert!(board.touches_wall(Orientation::Horizontal, Position { x: 6, y: 4 }));
+        assert!(board.touches_wall(Orientation::Vertical, Position { x: 3, y: 3 }));
+        // Center to center.
+        assert!(board.touches_wall(Orientation::Vertical, Position { x: 4, y: 4 }));
+        // Parallel and one row apart.
+        assert!(!board.touches_wall(Orientation::Horizontal, Position { x: 4, y: 5 }));
+        assert!(!board.touches_wall(Orientation::Vertical, Position { x: 2, y: 4 }));
+    }
+
+    #[test]
+    fn wall_closes_loop_matches_full_search() {
+        // Compare the dual graph check with searching for paths for both players, 
+        // for every wall on random boards reached by legal moves.
+        use rand::{SeedableRng, Rng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(6);
+        let (mut loops, mut blocked) = (0, 0);
+        for _ in 0..30 {
+            let mut board = Board { walls_left: [30,30], .. Default::default() };
+            for _ in 0..rng.gen_range(0, 80) {
+                let mv = match random_valid_move(&board, &mut rng) {
+                    Some(mv) => mv,
+                    None => break,
+                };
+                board.integrate(mv).unwrap();
+                if board.get_winner().is_some() { break; }
+            }
+
+            for ori in &[Orientation::Horizontal, Orientation::Vertical] {
+                for x in 1..=8 {
+                    for y in 1..=8 {
+                        let pos = Position { x, y };
+                        if board.wall_conflicts(*ori,pos) { continue; }
+                        let (h0, v0) = board.get_wall_bitsets();
+                        let (mut h, mut v) = (h0, v0);
+                        bitset_add_wall(&mut h, &mut v, ori, &pos);
+                        let expected = !goal_reachable(h, v, board.position(0), 9) ||
+                                       !goal_reachable(h, v, board.position(1), 1);
+                        assert_eq!(expected, !board.goal_reachable(*ori,pos), "{:?} {:?} {:?}", board, ori, pos);
+                        if wall_closes_loop(h0, v0, *ori, pos) {
+                            loops += 1;
+                        }
+                        if expected { blocked += 1; }
+                    }
+                }
+            }
+        }
+        assert!(loops > blocked && blocked > 0, "loops {} blocked {}", loops, blocked);
+    }
+
+    fn pairwise_wall_conflicts(oa :&Orientation, pa :&Position, ob :&Orientation, pb :&Position) -> bool {
+        if oa == ob {
+            match oa {
+                Orientation::Horizontal => {
+                    pa.y == pb.y && (pa.x == pb.x || pa.x + 1 == pb.x || pb.x + 1 == pa.x)
+                },
+                Orientation::Vertical => {
+                    pa.x == pb.x && (pa.y == pb.y || pa.y + 1 == pb.y || pb.y + 1 == pa.y)
+                },
+            }
+        } else {
+            if let Orientation::Vertical = oa {
+                // A is vertical, B is horizontal
+                pa == pb
+            } else {
+                pairwise_wall_conflicts(ob,pb,oa,pa)
+            }
+        }
+    }
+
+    #[test]
+    fn wall_conflicts_bitset() {
+        // Compare the bit set conflict check to checking each pair of walls.
+        let walls = [(Orientation::Horizontal, Position { x: 4, y: 4 }),
+                     (Orientation::Vertical, Position { x: 1, y: 8 }),
+                     (Orientation::Horizontal, Position { x: 8, y: 2 })];
+        let mut board :Board = Default::default();
+        for (o,p) in &walls { board.add_wall(*o,*p); }
+
+        for o in &[Orientation::Horizontal, Orientation::Vertical] {
+            for x in 1..=8 {
+                for y in 1..=8 {
+                    let p = Position { x, y };
+                    let expected = walls.iter().any(|(wo,wp)| pairwise_wall_conflicts(o,&p,wo,wp));
+                    assert_eq!(expected, board.wall_conflicts(*o,p), "{:?} {:?}", o, p);
+                }
+            }
+        }
     }
 
 }
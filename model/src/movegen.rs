@@ -0,0 +1,310 @@
+use crate::*;
+
+/// Upper bound on the number of legal moves in a position:
+/// at most 5 pawn moves (3 steps and 2 diagonal jumps) and 2*8*8 walls.
+pub const MAX_MOVES :usize = 5 + 2*8*8;
+
+/// Fixed-capacity list of moves, to avoid allocating in move generation.
+#[derive(Clone)]
+pub struct MoveList {
+    moves :[Move; MAX_MOVES],
+    len :usize,
+}
+
+impl MoveList {
+    pub fn new() -> MoveList {
+        MoveList {
+            moves: [Move::PawnTo(Position { x: 0, y: 0 }); MAX_MOVES],
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, mv :Move) {
+        self.moves[self.len] = mv;
+        self.len += 1;
+    }
+
+    pub fn as_slice(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> MoveList { MoveList::new() }
+}
+
+impl std::fmt::Debug for MoveList {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+impl std::ops::Deref for MoveList {
+    type Target = [Move];
+    fn deref(&self) -> &[Move] { self.as_slice() }
+}
+
+impl std::ops::DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] { self.as_mut_slice() }
+}
+
+impl<'a> IntoIterator for &'a MoveList {
+    type Item = &'a Move;
+    type IntoIter = std::slice::Iter<'a, Move>;
+    fn into_iter(self) -> Self::IntoIter { self.as_slice().iter() }
+}
+
+impl Board {
+    /// All legal moves for the current player, pawn moves first.
+    pub fn legal_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        self.add_pawn_moves(&mut moves);
+        self.add_wall_moves(&mut moves);
+        moves
+    }
+
+    /// Legal pawn moves (steps and jumps) for the current player.
+    pub fn legal_pawn_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        self.add_pawn_moves(&mut moves);
+        moves
+    }
+
+    /// Legal wall placements for the current player.
+    pub fn legal_wall_moves(&self) -> MoveList {
+        let mut moves = MoveList::new();
+        self.add_wall_moves(&mut moves);
+        moves
+    }
+
+    fn add_pawn_moves(&self, moves :&mut MoveList) {
+        if self.get_winner().is_some() { return; }
+        let current_pos = self.position(self.player());
+        let other_pos   = self.position(1 - self.player());
+
+        for (dx,dy) in &[(1,0),(-1,0),(0,1),(0,-1)] {
+            let candidate = Position { x: current_pos.x + dx, y: current_pos.y + dy };
+            if !in_bounds1to9(&candidate) || self.wall_between(&current_pos, &candidate) {
+                continue;
+            }
+
+            if candidate != other_pos {
+                moves.push(Move::PawnTo(candidate));
+                continue;
+            }
+
+            let other_side = Position { x: other_pos.x + dx, y: other_pos.y + dy };
+            if in_bounds1to9(&other_side) && !self.wall_between(&other_pos, &other_side) {
+                // Straight jump over the other player.
+                moves.push(Move::PawnTo(other_side));
+            } else if self.wall_between(&other_pos, &other_side) {
+                // There is a back wall after the other player, diagonal jumps allowed.
+                for sign in &[-1,1] {
+                    let diag = Position { x: other_pos.x + sign*dy, y: other_pos.y + sign*dx };
+                    if in_bounds1to9(&diag) && !self.wall_between(&other_pos, &diag) {
+                        moves.push(Move::PawnTo(diag));
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_wall_moves(&self, moves :&mut MoveList) {
+        if self.get_winner().is_some() { return; }
+        if self.walls_left[self.player()] == 0 { return; }
+        for orientation in &[Orientation::Horizontal, Orientation::Vertical] {
+            for x in 1..=8 {
+                for y in 1..=8 {
+                    let pos = Position { x, y };
+                    if self.can_add_wall(*orientation, pos) {
+                        moves.push(Move::WallAt(*orientation, pos));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Count the number of leaf nodes of the game tree to the given depth.
+/// Positions where the game is finished are leaves (there are no legal moves).
+pub fn perft(board :&Board, depth :u32) -> u64 {
+    let mut board = *board;
+    perft_rec(&mut board, depth)
+}
+
+fn perft_rec(board :&mut Board, depth :u32) -> u64 {
+    if depth == 0 { return 1; }
+    let moves = board.legal_moves();
+    if depth == 1 { return moves.len() as u64; }
+    let mut nodes = 0;
+    for mv in &moves {
+        let undo = board.make_move(*mv).unwrap();
+        nodes += perft_rec(board, depth - 1);
+        board.unmake_move(undo);
+    }
+    nodes
+}
+
+/// Perft split by the first move, for finding which move subtree 
+/// has a wrong node count.
+pub fn perft_divide(board :&Board, depth :u32) -> Vec<(Move,u64)> {
+    let mut board = *board;
+    if depth == 0 { return Vec::new(); }
+    let mut result = Vec::new();
+    for mv in &board.legal_moves() {
+        let undo = board.make_move(*mv).unwrap();
+        result.push((*mv, perft_rec(&mut board, depth - 1)));
+        board.unmake_move(undo);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    fn all_moves() -> Vec<Move> {
+        let mut moves = Vec::new();
+        for x in 0..=10 {
+            for y in 0..=10 {
+                moves.push(Move::PawnTo(Position { x, y }));
+                moves.push(Move::WallAt(Orientation::Horizontal, Position { x, y }));
+                moves.push(Move::WallAt(Orientation::Vertical, Position { x, y }));
+            }
+        }
+        moves
+    }
+
+    /// Random board with some walls, and pawns often placed next to each other
+    /// to exercise jumps.
+    fn random_board(rng :&mut impl Rng) -> Board {
+        let mut board :Board = Default::default();
+        let n_walls = rng.gen_range(0, 20);
+        for _ in 0..n_walls {
+            let walls = board.legal_wall_moves();
+            if walls.is_empty() { break; }
+            board.integrate(walls[rng.gen_range(0, walls.len())]).unwrap();
+        }
+
+        let random_cell = |rng :&mut dyn rand::RngCore|
+            Position { x: rng.gen_range(1, 10), y: rng.gen_range(2, 9) };
+        let p0 = random_cell(rng);
+        let mut p1 = random_cell(rng);
+        if rng.gen_bool(0.5) {
+            let (dx,dy) = [(1,0),(-1,0),(0,1),(0,-1)][rng.gen_range(0,4)];
+            p1 = Position { x: p0.x + dx, y: p0.y + dy };
+        }
+        if p1 == p0 || !in_bounds1to9(&p1) || p1.y == 1 { return board; }
+
+        board.set_position(0, p0);
+        board.set_position(1, p1);
+        board.set_player(rng.gen_range(0, 2));
+        board.walls_left = [rng.gen_range(0, 3), rng.gen_range(0, 3)];
+        board
+    }
+
+    #[test]
+    fn start_position() {
+        let board :Board = Default::default();
+        assert_eq!(3, board.legal_pawn_moves().len());
+        assert_eq!(128, board.legal_wall_moves().len());
+        assert_eq!(131, board.legal_moves().len());
+    }
+
+    #[test]
+    fn generated_moves_match_validation() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let candidates = all_moves();
+        for _ in 0..100 {
+            let board = random_board(&mut rng);
+            let generated = board.legal_moves();
+            for mv in &generated {
+                assert!(board.is_valid_move(mv), "generated invalid move {:?} on {:?}", mv, board);
+            }
+            for mv in &candidates {
+                if board.is_valid_move(mv) {
+                    assert!(generated.contains(mv), "move {:?} not generated on {:?}", mv, board);
+                }
+            }
+            for (i,mv) in generated.iter().enumerate() {
+                assert!(!generated[i+1..].contains(mv), "duplicate move {:?}", mv);
+            }
+        }
+    }
+
+    fn board_from(p0 :&str, p1 :&str, player :usize, walls_left :[u8;2], walls :&[&str]) -> Board {
+        let pos = |s| match parse(s).unwrap() { Move::PawnTo(p) => p, _ => panic!() };
+        let mut board :Board = Default::default();
+        board.set_position(0, pos(p0));
+        board.set_position(1, pos(p1));
+        board.set_player(player);
+        board.walls_left = walls_left;
+        for w in walls {
+            match parse(w).unwrap() {
+                Move::WallAt(o,p) => board.add_wall(o,p),
+                _ => panic!(),
+            }
+        }
+        board
+    }
+
+    /// Perft counted without the move generator, 
+    /// by trying every possible move with `is_valid_move`.
+    fn perft_brute_force(board :&Board, depth :u32) -> u64 {
+        if depth == 0 { return 1; }
+        let mut nodes = 0;
+        for mv in all_moves() {
+            let mut b = *board;
+            if b.integrate(mv).is_ok() {
+                nodes += perft_brute_force(&b, depth - 1);
+            }
+        }
+        nodes
+    }
+
+    /// Known node counts: position name, board, and perft counts for depth 1, 2, ...
+    fn perft_table() -> Vec<(&'static str, Board, Vec<u64>)> {
+        vec![
+            ("start", Default::default(), vec![131, 16677]),
+            // Pawns facing each other, straight jumps.
+            ("jump", board_from("e5","e6",0,[0,0],&[]), vec![4, 16, 64, 252]),
+            // Wall behind the other pawn, diagonal jumps.
+            ("diagonal", board_from("e5","e6",0,[0,0],&["e6h"]), vec![5, 15, 58, 218]),
+            // The board edge behind the other pawn does not allow diagonal jumps.
+            ("edge", board_from("a8","a9",0,[0,0],&[]), vec![2, 4, 12, 30]),
+            // Walls around player 1, so that several wall placements would block a path.
+            ("near blocking", board_from("e1","e9",0,[1,1],&["d1v","e2h","c2h","b3v"]), 
+             vec![114, 12701, 50462]),
+        ]
+    }
+
+    #[test]
+    fn perft_known_counts() {
+        for (name, board, counts) in perft_table() {
+            for (i,count) in counts.iter().enumerate() {
+                let depth = i as u32 + 1;
+                assert_eq!(*count, perft(&board, depth), "perft {} depth {}", name, depth);
+            }
+            let divided :u64 = perft_divide(&board, 2).iter().map(|(_,n)| n).sum();
+            assert_eq!(counts[1], divided, "perft divide {}", name);
+        }
+    }
+
+    #[test]
+    fn perft_brute_force_counts() {
+        for (name, board, counts) in perft_table() {
+            assert_eq!(counts[0], perft_brute_force(&board, 1), "brute force perft {}", name);
+        }
+    }
+
+    #[test]
+    #[ignore] // slow, run with --release --ignored
+    fn perft_start_depth_3() {
+        assert_eq!(2062264, perft(&Default::default(), 3));
+    }
+}
@@ -18,12 +18,46 @@
 
 use crate::*;
 
-pub fn parse(s :&str) -> Result<Move,()> {
+/// Error from parsing a move, with the character offset into the input string.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Unexpected character at the given offset.
+    UnexpectedChar(usize, char),
+    /// The input ended (at the given offset) before a complete move was read.
+    UnexpectedEnd(usize),
+}
+
+impl ParseError {
+    pub fn offset(&self) -> usize {
+        match self {
+            ParseError::UnexpectedChar(offset,_) => *offset,
+            ParseError::UnexpectedEnd(offset) => *offset,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::UnexpectedChar(offset,c) => 
+                write!(f, "unexpected character {:?} at offset {}", c, offset),
+            ParseError::UnexpectedEnd(offset) => 
+                write!(f, "incomplete move, input ended at offset {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(s :&str) -> Result<Move,ParseError> {
     let mut x = None;
     let mut y = None;
     let mut orientation = None;
-    for c in s.chars() {
+    let mut length = 0;
+    for (offset,c) in s.chars().enumerate() {
+        length = offset + 1;
         if c.is_whitespace() { continue; }
+        let err = Err(ParseError::UnexpectedChar(offset, c));
 
         if x.is_none() {
             match c {
@@ -36,7 +70,7 @@ pub fn parse(s :&str) -> Result<Move,()> {
                 'g' => { x = Some(7); },
                 'h' => { x = Some(8); },
                 'i' => { x = Some(9); },
-                _ => { return Err(()); },
+                _ => { return err; },
             };
         } else if y.is_none() {
             match c {
@@ -49,16 +83,16 @@ pub fn parse(s :&str) -> Result<Move,()> {
                 '7' => { y = Some(7); },
                 '8' => { y = Some(8); },
                 '9' => { y = Some(9); },
-                _ => { return Err(()); },
+                _ => { return err; },
             };
         } else if orientation.is_none() {
             match c {
                 'h' => { orientation = Some(Orientation::Horizontal); },
                 'v' => { orientation = Some(Orientation::Vertical); },
-                _ => { return Err(()); },
+                _ => { return err; },
             };
         } else {
-            return Err(());
+            return err;
         }
     }
 
@@ -72,7 +106,7 @@ pub fn parse(s :&str) -> Result<Move,()> {
         }
     }
 
-    Err(())
+    Err(ParseError::UnexpectedEnd(length))
 }
 
 pub fn print_pos(pos :Position) -> String {
@@ -99,7 +133,9 @@ mod tests {
     fn board() {
         assert_eq!(parse("e9").unwrap(), Move::PawnTo(Position { x: 5, y: 9 }));
         assert_eq!(parse("e9h").unwrap(), Move::WallAt(Orientation::Horizontal, Position { x: 5, y: 9 }));
-        assert!(parse("e9hz").is_err());
+        assert_eq!(parse("e9hz"), Err(ParseError::UnexpectedChar(3, 'z')));
+        assert_eq!(parse(" j1"), Err(ParseError::UnexpectedChar(1, 'j')));
+        assert_eq!(parse("e"), Err(ParseError::UnexpectedEnd(1)));
     }
 
 }
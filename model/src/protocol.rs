@@ -0,0 +1,357 @@
+//! Text protocol between the referee and engines, modelled on UCI.
+//!
+//! The referee starts the engine process and sends commands on the engine's
+//! standard input, one per line. The engine answers on standard output.
+//! Lines the engine writes to standard error are not part of the protocol.
+//! Moves use the notation of `parse` and `printer`, and times are in milliseconds.
+//!
+//! Handshake:
+//!  - `quoridor`: the engine answers with `id name <name>`, optionally
+//!    `id author <author>`, then `protocol <version>` with the protocol version
+//!    it implements (currently `1`), one `option name <name> [default <value>]`
+//!    line for each option it supports, and finally `ok`.
+//!
+//! Commands from the referee:
+//!  - `setoption name <name> value <value>`: set an engine option.
+//!  - `isready`: the engine answers `readyok` when it has finished handling
+//!    the previous commands, for example loading files given by options.
+//!  - `newgame`: the next position is from a new game.
+//!  - `position startpos [moves <move> ...]`: the position after playing the
+//!    moves from the start position.
+//!  - `go [p1time <ms>] [p2time <ms>] [p1inc <ms>] [p2inc <ms>] [movetime <ms>]`:
+//!    search the current position, given the time left and the increment for
+//!    each player, or a fixed time for the move. The engine may answer with
+//!    `info` lines and then answers `bestmove <move>`.
+//!  - `stop`: answer `bestmove` as soon as possible. The referee sends it when
+//!    the time for the move has passed, and waits a short time for the answer
+//!    before stopping the engine. The move is not played. Engines that search
+//!    synchronously have already answered, and ignore it.
+//!  - `quit`: exit the engine. The referee sends it to an engine that is not
+//!    searching before closing the connection.
+//!
+//! Information from the engine during a search:
+//!  - `info [depth <plies>] [score <score>] [nodes <n>] [time <ms>] [pv <move> ...]`,
+//!    where the score is from the perspective of the player to move.
+//!    Unknown fields are ignored, so that engines can add their own.
+
+use std::time::Duration;
+use crate::*;
+
+/// Version of the protocol described in this module.
+pub const PROTOCOL_VERSION :u32 = 1;
+
+/// Command from the referee to an engine.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Quoridor,
+    SetOption { name :String, value :String },
+    IsReady,
+    NewGame,
+    /// Moves from the start position.
+    Position(Vec<Move>),
+    Go(GoParams),
+    Stop,
+    Quit,
+}
+
+/// Time controls for the `go` command, with clocks indexed by player.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GoParams {
+    pub time :[Option<Duration>;2],
+    pub increment :[Option<Duration>;2],
+    pub move_time :Option<Duration>,
+}
+
+impl GoParams {
+    /// The clock for the player, if there is any time control.
+    pub fn clock(&self, player :usize) -> Option<Clock> {
+        let remaining = self.time[player].or(self.move_time)?;
+        Some(Clock {
+            remaining,
+            increment: self.increment[player].unwrap_or_default(),
+            move_time: self.move_time,
+        })
+    }
+}
+
+/// Message from an engine to the referee.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    /// Engine identification, such as `id name <name>`.
+    Id { key :String, value :String },
+    /// Protocol version implemented by the engine.
+    Protocol(u32),
+    EngineOption { name :String, default :Option<String> },
+    Ok,
+    ReadyOk,
+    Info(SearchInfo),
+    BestMove(Move),
+}
+
+/// Search progress reported by an engine.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchInfo {
+    pub depth :Option<u16>,
+    /// Score from the perspective of the player to move.
+    pub score :Option<f32>,
+    pub nodes :Option<u64>,
+    pub time :Option<Duration>,
+    /// Principal variation.
+    pub pv :Vec<Move>,
+}
+
+fn millis(d :Duration) -> u128 {
+    d.as_millis()
+}
+
+fn parse_millis(key :&str, value :Option<&str>) -> Result<Duration,String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", key))?;
+    value.parse::<u64>().map(Duration::from_millis)
+        .map_err(|e| format!("could not parse {} {:?}: {}", key, value, e))
+}
+
+fn parse_value<T :std::str::FromStr>(key :&str, value :Option<&str>) -> Result<T,String>
+    where T::Err :std::fmt::Display {
+    let value = value.ok_or_else(|| format!("missing value for {}", key))?;
+    value.parse().map_err(|e| format!("could not parse {} {:?}: {}", key, value, e))
+}
+
+fn parse_moves<'a>(words :impl Iterator<Item = &'a str>) -> Result<Vec<Move>,String> {
+    words.map(|w| parse(w).map_err(|e| format!("could not parse move {:?}: {}", w, e))).collect()
+}
+
+fn write_moves(f :&mut std::fmt::Formatter, moves :&[Move]) -> std::fmt::Result {
+    for mv in moves { write!(f, " {}", printer(mv))?; }
+    Ok(())
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Command::Quoridor => write!(f, "quoridor"),
+            Command::SetOption { name, value } => write!(f, "setoption name {} value {}", name, value),
+            Command::IsReady => write!(f, "isready"),
+            Command::NewGame => write!(f, "newgame"),
+            Command::Position(moves) => {
+                write!(f, "position startpos")?;
+                if !moves.is_empty() {
+                    write!(f, " moves")?;
+                    write_moves(f, moves)?;
+                }
+                Ok(())
+            },
+            Command::Go(params) => {
+                write!(f, "go")?;
+                let names = [("p1time", "p1inc"), ("p2time", "p2inc")];
+                for (player, (time, inc)) in names.iter().enumerate() {
+                    if let Some(t) = params.time[player] { write!(f, " {} {}", time, millis(t))?; }
+                    if let Some(t) = params.increment[player] { write!(f, " {} {}", inc, millis(t))?; }
+                }
+                if let Some(t) = params.move_time { write!(f, " movetime {}", millis(t))?; }
+                Ok(())
+            },
+            Command::Stop => write!(f, "stop"),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+impl std::str::FromStr for Command {
+    type Err = String;
+    fn from_str(line :&str) -> Result<Command,String> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("quoridor") => Ok(Command::Quoridor),
+            Some("isready") => Ok(Command::IsReady),
+            Some("newgame") => Ok(Command::NewGame),
+            Some("stop") => Ok(Command::Stop),
+            Some("quit") => Ok(Command::Quit),
+            Some("setoption") => {
+                let words = words.collect::<Vec<_>>();
+                let value_idx = words.iter().position(|w| *w == "value");
+                match (words.first(), value_idx) {
+                    (Some(&"name"), Some(idx)) if idx > 1 => Ok(Command::SetOption {
+                        name: words[1..idx].join(" "),
+                        value: words[idx+1..].join(" "),
+                    }),
+                    _ => Err("expected setoption name <name> value <value>".to_string()),
+                }
+            },
+            Some("position") => {
+                if words.next() != Some("startpos") {
+                    return Err("expected position startpos".to_string());
+                }
+                match words.next() {
+                    None => Ok(Command::Position(Vec::new())),
+                    Some("moves") => Ok(Command::Position(parse_moves(words)?)),
+                    Some(x) => Err(format!("unexpected {:?} in position", x)),
+                }
+            },
+            Some("go") => {
+                let mut params = GoParams::default();
+                while let Some(key) = words.next() {
+                    match key {
+                        "p1time" => params.time[0] = Some(parse_millis(key, words.next())?),
+                        "p2time" => params.time[1] = Some(parse_millis(key, words.next())?),
+                        "p1inc" => params.increment[0] = Some(parse_millis(key, words.next())?),
+                        "p2inc" => params.increment[1] = Some(parse_millis(key, words.next())?),
+                        "movetime" => params.move_time = Some(parse_millis(key, words.next())?),
+                        x => return Err(format!("unexpected {:?} in go", x)),
+                    }
+                }
+                Ok(Command::Go(params))
+            },
+            Some(x) => Err(format!("unknown command {:?}", x)),
+            None => Err("empty command".to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for SearchInfo {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "info")?;
+        if let Some(depth) = self.depth { write!(f, " depth {}", depth)?; }
+        if let Some(score) = self.score { write!(f, " score {}", score)?; }
+        if let Some(nodes) = self.nodes { write!(f, " nodes {}", nodes)?; }
+        if let Some(time) = self.time { write!(f, " time {}", millis(time))?; }
+        if !self.pv.is_empty() {
+            write!(f, " pv")?;
+            write_moves(f, &self.pv)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Response {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Response::Id { key, value } => write!(f, "id {} {}", key, value),
+            Response::Protocol(version) => write!(f, "protocol {}", version),
+            Response::EngineOption { name, default } => {
+                write!(f, "option name {}", name)?;
+                if let Some(default) = default { write!(f, " default {}", default)?; }
+                Ok(())
+            },
+            Response::Ok => write!(f, "ok"),
+            Response::ReadyOk => write!(f, "readyok"),
+            Response::Info(info) => write!(f, "{}", info),
+            Response::BestMove(mv) => write!(f, "bestmove {}", printer(mv)),
+        }
+    }
+}
+
+impl std::str::FromStr for Response {
+    type Err = String;
+    fn from_str(line :&str) -> Result<Response,String> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("ok") => Ok(Response::Ok),
+            Some("readyok") => Ok(Response::ReadyOk),
+            Some("id") => {
+                let key = words.next().ok_or("missing id key")?.to_string();
+                Ok(Response::Id { key, value: words.collect::<Vec<_>>().join(" ") })
+            },
+            Some("protocol") => Ok(Response::Protocol(parse_value("protocol", words.next())?)),
+            Some("option") => {
+                if words.next() != Some("name") {
+                    return Err("expected option name <name>".to_string());
+                }
+                let name = words.next().ok_or("missing option name")?.to_string();
+                match words.next() {
+                    None => Ok(Response::EngineOption { name, default: None }),
+                    Some("default") => Ok(Response::EngineOption {
+                        name, default: Some(words.collect::<Vec<_>>().join(" ")),
+                    }),
+                    Some(x) => Err(format!("unexpected {:?} in option", x)),
+                }
+            },
+            Some("info") => {
+                let mut info = SearchInfo::default();
+                while let Some(key) = words.next() {
+                    match key {
+                        "depth" => info.depth = Some(parse_value(key, words.next())?),
+                        "score" => info.score = Some(parse_value(key, words.next())?),
+                        "nodes" => info.nodes = Some(parse_value(key, words.next())?),
+                        "time" => info.time = Some(parse_millis(key, words.next())?),
+                        "pv" => {
+                            info.pv = parse_moves(&mut words)?;
+                        },
+                        _ => {},
+                    }
+                }
+                Ok(Response::Info(info))
+            },
+            Some("bestmove") => {
+                let mv = words.next().ok_or("missing move")?;
+                parse(mv).map(Response::BestMove)
+                    .map_err(|e| format!("could not parse move {:?}: {}", mv, e))
+            },
+            Some(x) => Err(format!("unknown response {:?}", x)),
+            None => Err("empty response".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let e2 = parse("e2").unwrap();
+        let e8h = parse("e8h").unwrap();
+        let go = GoParams {
+            time: [Some(Duration::from_secs(60)), Some(Duration::from_millis(59500))],
+            increment: [Some(Duration::from_secs(1)), Some(Duration::from_secs(1))],
+            move_time: None,
+        };
+        let commands = vec![
+            Command::Quoridor, Command::IsReady, Command::NewGame, Command::Stop, Command::Quit,
+            Command::SetOption { name: "engine".to_string(), value: "minimax".to_string() },
+            Command::Position(vec![]), Command::Position(vec![e2, e8h]),
+            Command::Go(Default::default()), Command::Go(go.clone()),
+            Command::Go(GoParams { move_time: Some(Duration::from_millis(500)), .. Default::default() }),
+        ];
+        for command in commands {
+            assert_eq!(command, command.to_string().parse().unwrap());
+        }
+
+        assert_eq!("position startpos moves e2 e8h", Command::Position(vec![e2, e8h]).to_string());
+        assert_eq!("go p1time 60000 p1inc 1000 p2time 59500 p2inc 1000", Command::Go(go.clone()).to_string());
+        assert_eq!(Some(Clock { remaining: Duration::from_millis(59500), increment: Duration::from_secs(1),
+                                move_time: None }), go.clock(1));
+        assert_eq!(None, GoParams::default().clock(0));
+
+        assert!("position e2".parse::<Command>().is_err());
+        assert!("position startpos moves e2 x".parse::<Command>().is_err());
+        assert!("go p1time".parse::<Command>().is_err());
+        assert!("setoption name value 1".parse::<Command>().is_err());
+        assert!("".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn responses() {
+        let info = SearchInfo {
+            depth: Some(3), score: Some(-1.5), nodes: Some(1234),
+            time: Some(Duration::from_millis(250)), pv: vec![parse("e2").unwrap(), parse("d8v").unwrap()],
+        };
+        let responses = vec![
+            Response::Id { key: "name".to_string(), value: "Quoridor bot".to_string() },
+            Response::Protocol(PROTOCOL_VERSION), Response::Ok, Response::ReadyOk,
+            Response::EngineOption { name: "depth".to_string(), default: None },
+            Response::EngineOption { name: "engine".to_string(), default: Some("heuristic".to_string()) },
+            Response::Info(info), Response::Info(Default::default()),
+            Response::BestMove(parse("a1h").unwrap()),
+        ];
+        for response in responses {
+            assert_eq!(response, response.to_string().parse().unwrap());
+        }
+
+        // Unknown info fields are ignored.
+        assert_eq!(Response::Info(SearchInfo { depth: Some(2), .. Default::default() }),
+                   "info seldepth 5 depth 2".parse().unwrap());
+        assert!("bestmove".parse::<Response>().is_err());
+        assert!("bestmove z0".parse::<Response>().is_err());
+        assert!("e2".parse::<Response>().is_err());
+    }
+}
@@ -0,0 +1,461 @@
+//! Text format for recording games, modelled on PGN.
+//!
+//! A record starts with header tags, one per line, on the form `[Name "value"]`,
+//! with `\"` and `\\` escaped in the value. The usual tags are `Player1` and
+//! `Player2` (player 1 moves first), `Date`, `Result` (`1-0`, `0-1`, `1/2-1/2`
+//! or `*` for unknown), `Termination` and `TimeControl`. A `Position` tag
+//! gives the starting position, written by `print_board`, when the game did
+//! not start from the standard position.
+//!
+//! The tags are followed by the moves in the notation of `parse` and `printer`,
+//! usually one line for each move number, as in `3. e3 e7`. A move can be
+//! followed by a comment in braces on the same line, with `\`, `}` and
+//! line breaks escaped as `\\`, `\}` and `\n`. An evaluation in the
+//! comment, written as `[%eval <score>]` with two decimals, is the score
+//! from player 1's point of view. The moves may end with the result. A file
+//! can hold several records, each starting with its tags.
+
+use crate::*;
+
+/// A move in a game record, with its annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedMove {
+    pub mv :Move,
+    /// Evaluation from player 1's point of view.
+    pub eval :Option<f32>,
+    pub comment :Option<String>,
+}
+
+impl RecordedMove {
+    pub fn new(mv :Move) -> RecordedMove {
+        RecordedMove { mv, eval: None, comment: None }
+    }
+}
+
+/// Outcome of a game, as in the `Result` tag.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GameOutcome {
+    Win(usize),
+    Draw,
+    Unknown,
+}
+
+impl std::fmt::Display for GameOutcome {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GameOutcome::Win(0) => write!(f, "1-0"),
+            GameOutcome::Win(_) => write!(f, "0-1"),
+            GameOutcome::Draw => write!(f, "1/2-1/2"),
+            GameOutcome::Unknown => write!(f, "*"),
+        }
+    }
+}
+
+impl std::str::FromStr for GameOutcome {
+    type Err = String;
+    fn from_str(s :&str) -> Result<GameOutcome,String> {
+        match s {
+            "1-0" => Ok(GameOutcome::Win(0)),
+            "0-1" => Ok(GameOutcome::Win(1)),
+            "1/2-1/2" => Ok(GameOutcome::Draw),
+            "*" => Ok(GameOutcome::Unknown),
+            x => Err(format!("unknown result {:?}", x)),
+        }
+    }
+}
+
+/// A recorded game.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameRecord {
+    /// Header tags in the order they are written, except `Position`.
+    pub tags :Vec<(String,String)>,
+    /// The starting position, if it is not the standard one.
+    pub start :Option<Board>,
+    pub moves :Vec<RecordedMove>,
+}
+
+impl GameRecord {
+    pub fn tag(&self, name :&str) -> Option<&str> {
+        self.tags.iter().find(|(n,_)| n == name).map(|(_,v)| v.as_str())
+    }
+
+    /// Set a tag, replacing its value if it is already set.
+    pub fn set_tag(&mut self, name :&str, value :&str) {
+        match self.tags.iter_mut().find(|(n,_)| n == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// The result given by the `Result` tag.
+    pub fn outcome(&self) -> GameOutcome {
+        self.tag("Result").and_then(|r| r.parse().ok()).unwrap_or(GameOutcome::Unknown)
+    }
+
+    /// The board after all the moves of the game.
+    pub fn board(&self) -> Result<Board,IllegalMove> {
+        let mut board = self.start.unwrap_or_default();
+        for m in &self.moves { board.integrate(m.mv)?; }
+        Ok(board)
+    }
+
+    /// Read all records in a file.
+    pub fn load(path :&str) -> Result<Vec<GameRecord>,String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read game record file {:?}: {}", path, e))?;
+        parse_records(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Add the record to the end of a file, creating the file if necessary.
+    pub fn append(&self, path :&str) -> Result<(),String> {
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| format!("Could not open game record file {:?}: {}", path, e))?;
+        let separator = if file.metadata().map(|m| m.len() > 0).unwrap_or(false) { "\n" } else { "" };
+        write!(file, "{}{}", separator, self)
+            .map_err(|e| format!("Could not write game record file {:?}: {}", path, e))
+    }
+}
+
+fn escape(value :&str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_comment(comment :&str) -> String {
+    comment.replace('\\', "\\\\").replace('}', "\\}").replace('\r', "").replace('\n', "\\n")
+}
+
+/// Split text after the opening brace of a comment into the unescaped
+/// comment and the rest of the line.
+fn split_comment(text :&str) -> Result<(String, &str),String> {
+    let mut comment = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '}' => return Ok((comment, &text[i+1..])),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => comment.push('\n'),
+                Some((_, c)) => comment.push(c),
+                None => break,
+            },
+            c => comment.push(c),
+        }
+    }
+    Err("comment is not closed on the same line".to_string())
+}
+
+impl std::fmt::Display for GameRecord {
+    fn fmt(&self, f :&mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+        if let Some(board) = &self.start {
+            writeln!(f, "[Position \"{}\"]", print_board(board))?;
+        }
+        writeln!(f)?;
+
+        let first_player = self.start.map(|b| b.player()).unwrap_or(0);
+        for (i, m) in self.moves.iter().enumerate() {
+            let ply = i + first_player;
+            match (ply % 2, i) {
+                (0, 0) => write!(f, "1. ")?,
+                (0, _) => write!(f, "\n{}. ", ply / 2 + 1)?,
+                (_, 0) => write!(f, "1... ")?,
+                _ => write!(f, " ")?,
+            }
+            write!(f, "{}", printer(&m.mv))?;
+            match (&m.eval, &m.comment) {
+                (None, None) => {},
+                (Some(eval), None) => write!(f, " {{[%eval {:.2}]}}", eval)?,
+                (None, Some(comment)) => write!(f, " {{{}}}", escape_comment(comment))?,
+                (Some(eval), Some(comment)) => write!(f, " {{[%eval {:.2}] {}}}", eval, escape_comment(comment))?,
+            }
+        }
+        if !self.moves.is_empty() { writeln!(f)?; }
+        writeln!(f, "{}", self.outcome())
+    }
+}
+
+impl std::str::FromStr for GameRecord {
+    type Err = String;
+    /// Parse a single record.
+    fn from_str(text :&str) -> Result<GameRecord,String> {
+        let mut records = parse_records(text)?;
+        match records.len() {
+            1 => Ok(records.remove(0)),
+            n => Err(format!("Expected one game record, found {}", n)),
+        }
+    }
+}
+
+/// Parse a header tag line, which starts with `[`.
+fn parse_tag(line :&str) -> Result<(String,String),String> {
+    let inner = line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+        .ok_or("tag must be on the form [Name \"value\"]")?;
+    let space = inner.find(' ').ok_or("tag must be on the form [Name \"value\"]")?;
+    let (name, value) = (&inner[..space], inner[space..].trim());
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid tag name {:?}", name));
+    }
+    let quoted = value.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| format!("value of tag {} must be quoted", name))?;
+    let mut unescaped = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c) if c == '\\' || c == '"' => unescaped.push(c),
+                _ => return Err(format!("invalid escape in tag {}", name)),
+            },
+            '"' => return Err(format!("unescaped quote in tag {}", name)),
+            c => unescaped.push(c),
+        }
+    }
+    Ok((name.to_string(), unescaped))
+}
+
+/// Take the evaluation out of a comment.
+fn parse_comment(comment :&str) -> Result<(Option<f32>, Option<String>),String> {
+    let mut eval = None;
+    let mut text = comment.to_string();
+    if let Some(start) = text.find("[%eval") {
+        let end = text[start..].find(']').ok_or("unterminated [%eval] in comment")? + start;
+        let value = text[start + "[%eval".len()..end].trim();
+        eval = Some(value.parse::<f32>().map_err(|e| format!("could not parse evaluation {:?}: {}", value, e))?);
+        text.replace_range(start..=end, "");
+    }
+    let text = text.trim();
+    Ok((eval, if text.is_empty() { None } else { Some(text.to_string()) }))
+}
+
+struct RecordParser {
+    record :GameRecord,
+    board :Board,
+    /// The result at the end of the moves.
+    result :Option<GameOutcome>,
+}
+
+impl RecordParser {
+    fn new() -> RecordParser {
+        RecordParser { record: Default::default(), board: Default::default(), result: None }
+    }
+
+    fn tag(&mut self, name :String, value :String) -> Result<(),String> {
+        if !self.record.moves.is_empty() { return Err("tag after the moves".to_string()); }
+        match name.as_str() {
+            "Position" => {
+                let board = parse_board(&value)?;
+                self.record.start = Some(board);
+                self.board = board;
+            },
+            "Result" => {
+                value.parse::<GameOutcome>()?;
+                self.record.tags.push((name, value));
+            },
+            _ => self.record.tags.push((name, value)),
+        }
+        Ok(())
+    }
+
+    fn moves(&mut self, line :&str) -> Result<(),String> {
+        let mut rest = line.trim();
+        while !rest.is_empty() {
+            if self.result.is_some() { return Err(format!("unexpected {:?} after the result", rest)); }
+            if let Some(comment) = rest.strip_prefix('{') {
+                let (comment, after) = split_comment(comment)?;
+                let (eval, text) = parse_comment(&comment)?;
+                let last = self.record.moves.last_mut().ok_or("comment before the first move")?;
+                if eval.is_some() { last.eval = eval; }
+                if text.is_some() { last.comment = text; }
+                rest = after.trim_start();
+                continue;
+            }
+
+            let end = rest.find(|c :char| c.is_whitespace() || c == '{').unwrap_or(rest.len());
+            let token = &rest[..end];
+            rest = rest[end..].trim_start();
+            if let Ok(outcome) = token.parse::<GameOutcome>() {
+                self.result = Some(outcome);
+                continue;
+            }
+            let digits = token.trim_end_matches('.');
+            if digits.len() < token.len() && !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            let mv = parse(token).map_err(|e| format!("could not parse move {:?}: {}", token, e))?;
+            self.board.integrate(mv).map_err(|e| format!("illegal move {}: {}", token, e))?;
+            self.record.moves.push(RecordedMove::new(mv));
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<GameRecord,String> {
+        let tagged = self.record.outcome();
+        if let Some(result) = self.result {
+            if self.record.tag("Result").is_some() && result != tagged {
+                return Err(format!("result {} does not match the Result tag {}", result, tagged));
+            }
+        }
+        let outcome = self.result.filter(|_| self.record.tag("Result").is_none()).unwrap_or(tagged);
+        if let Some(winner) = self.board.get_winner() {
+            if outcome != GameOutcome::Win(winner) && outcome != GameOutcome::Unknown {
+                return Err(format!("result {} does not match the game, which player {} won", outcome, winner + 1));
+            }
+        }
+        let mut record = self.record;
+        if record.tag("Result").is_none() && outcome != GameOutcome::Unknown {
+            record.set_tag("Result", &outcome.to_string());
+        }
+        Ok(record)
+    }
+}
+
+/// Parse the records in a text, replaying the moves to check that they are legal.
+/// Errors are given with line numbers.
+pub fn parse_records(text :&str) -> Result<Vec<GameRecord>,String> {
+    let mut records = Vec::new();
+    let mut parser :Option<(usize, RecordParser)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() { continue; }
+        let result = if line.starts_with('[') {
+            let (name, value) = parse_tag(line).map_err(|e| format!("Line {}: {}", i+1, e))?;
+            // Tags after the moves start the next record.
+            if let Some((start, p)) = parser.take() {
+                if p.record.moves.is_empty() && p.result.is_none() {
+                    parser = Some((start, p));
+                } else {
+                    records.push(p.finish().map_err(|e| format!("Line {}: {}", start, e))?);
+                }
+            }
+            parser.get_or_insert_with(|| (i+1, RecordParser::new())).1.tag(name, value)
+        } else {
+            parser.get_or_insert_with(|| (i+1, RecordParser::new())).1.moves(line)
+        };
+        result.map_err(|e| format!("Line {}: {}", i+1, e))?;
+    }
+    if let Some((start, p)) = parser {
+        records.push(p.finish().map_err(|e| format!("Line {}: {}", start, e))?);
+    }
+    Ok(records)
+}
+
+/// Write a position as the two pawns, the walls left for each player, the
+/// player to move (1 or 2) and then the walls, as in `e1 e9 10 10 1 d4h e5v`.
+pub fn print_board(board :&Board) -> String {
+    let mut s = format!("{} {} {} {} {}", print_pos(board.position(0)), print_pos(board.position(1)),
+                        board.walls_left[0], board.walls_left[1], board.player() + 1);
+    for (ori, pos) in board.walls() {
+        s.push(' ');
+        s.push_str(&printer(&Move::WallAt(ori, pos)));
+    }
+    s
+}
+
+/// Read a position written by `print_board`.
+pub fn parse_board(s :&str) -> Result<Board,String> {
+    let parts = s.split_whitespace().collect::<Vec<_>>();
+    if parts.len() < 5 {
+        return Err(format!("position {:?} must have the pawns, walls left and player to move", s));
+    }
+    let mut board = Board::default();
+    for player in 0..2 {
+        match parse(parts[player]) {
+            Ok(Move::PawnTo(pos)) if in_bounds1to9(&pos) => board.set_position(player, pos),
+            _ => return Err(format!("invalid pawn position {:?}", parts[player])),
+        }
+        board.walls_left[player] = match parts[2 + player].parse::<u8>() {
+            Ok(n) if n <= 10 => n,
+            _ => return Err(format!("invalid number of walls left {:?}", parts[2 + player])),
+        };
+    }
+    if board.position(0) == board.position(1) { return Err("the pawns are on the same cell".to_string()); }
+    match parts[4] {
+        "1" => board.set_player(0),
+        "2" => board.set_player(1),
+        x => return Err(format!("invalid player to move {:?}", x)),
+    }
+    for wall in &parts[5..] {
+        match parse(wall) {
+            Ok(Move::WallAt(ori, pos)) if in_bounds1to8(&pos) && !board.wall_conflicts(ori, pos) =>
+                board.add_wall(ori, pos),
+            _ => return Err(format!("invalid wall {:?}", wall)),
+        }
+    }
+    if board.distance_to_goal(0).is_none() || board.distance_to_goal(1).is_none() {
+        return Err("the walls block a player's path to the goal".to_string());
+    }
+    Ok(board)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let text = "[Player1 \"bot \\\"minimax\\\"\"]\n[Player2 \"bot\"]\n[Result \"0-1\"]\n\
+                    [Termination \"forfeit, ran out of time\"]\n\n\
+                    1. e2 {[%eval 0.50] book} e8\n2. a3h {[%eval -1.25]} e7 {opening}\n0-1\n";
+        let record :GameRecord = text.parse().unwrap();
+        assert_eq!(Some("bot \"minimax\""), record.tag("Player1"));
+        assert_eq!(GameOutcome::Win(1), record.outcome());
+        assert_eq!(4, record.moves.len());
+        assert_eq!(RecordedMove { mv: parse("e2").unwrap(), eval: Some(0.5), comment: Some("book".to_string()) },
+                   record.moves[0]);
+        assert_eq!((Some(-1.25), None), (record.moves[2].eval, record.moves[2].comment.clone()));
+        assert_eq!(Some("opening".to_string()), record.moves[3].comment);
+        assert_eq!(text, record.to_string());
+        assert_eq!([9, 10], record.board().unwrap().walls_left);
+
+        // Records in one file, the second from a position with player 2 to move.
+        let mut second = GameRecord::default();
+        second.set_tag("Result", "1/2-1/2");
+        second.start = Some(parse_board("e5 e6 3 4 2 a1h d4v").unwrap());
+        second.moves = vec![RecordedMove::new(parse("f6").unwrap()), RecordedMove::new(parse("e6").unwrap())];
+        assert!(second.to_string().contains("1... f6\n2. e6\n"));
+        let both = format!("{}\n{}", record, second);
+        assert_eq!(vec![record.clone(), second.clone()], parse_records(&both).unwrap());
+        // Moves without tags, ending with the result.
+        let untagged :GameRecord = "1. e2 e8 *".parse().unwrap();
+        assert_eq!((2, None), (untagged.moves.len(), untagged.tag("Result")));
+        assert!(both.parse::<GameRecord>().is_err());
+
+        // Comments with braces, backslashes and line breaks.
+        let mut commented = untagged.clone();
+        commented.moves[0].comment = Some("a {b} c:\\d\nsecond line".to_string());
+        commented.moves[1] = RecordedMove { eval: Some(1.0), comment: Some("}".to_string()), .. commented.moves[1] };
+        assert!(commented.to_string().contains("{a {b\\} c:\\\\d\\nsecond line}"));
+        assert_eq!(commented, commented.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn invalid_records() {
+        let error = |text :&str| text.parse::<GameRecord>().unwrap_err();
+        assert!(error("[Result \"1-0\"]\n1. e2 e8\n2. e3 e5\n").starts_with("Line 3: illegal move e5"));
+        assert!(error("1. e2 e8\n2. x3\n").starts_with("Line 2: could not parse move \"x3\""));
+        assert!(error("[Result \"2-0\"]\n").starts_with("Line 1: unknown result"));
+        assert!(error("[Player1 bot]\n").starts_with("Line 1: value of tag Player1 must be quoted"));
+        assert!(error("{hello} e2\n").starts_with("Line 1: comment before the first move"));
+        assert!(error("1. e2 {hello\n").starts_with("Line 1: comment is not closed"));
+        assert!(error("1. e2 {[%eval x]}\n").starts_with("Line 1: could not parse evaluation"));
+        assert!(error("\n\n1. e2 e8 1-0 e3\n").starts_with("Line 3: unexpected"));
+        assert!(error("[Result \"1-0\"]\n1. e2 e8\n0-1\n").starts_with("Line 1: result 0-1 does not match"));
+        assert!(error("[Position \"e8 e2 0 0 1\"]\n1. e9\n0-1\n").contains("which player 1 won"));
+    }
+
+    #[test]
+    fn boards() {
+        let mut board = Board::default();
+        for mv in &["e2", "e8", "d4h", "e5v"] { board.integrate(parse(mv).unwrap()).unwrap(); }
+        assert_eq!("e2 e8 9 9 1 d4h e5v", print_board(&board));
+        assert_eq!(Ok(board), parse_board(&print_board(&board)));
+        assert_eq!(board.zobrist_key(), parse_board(&print_board(&board)).unwrap().zobrist_key());
+        assert_eq!("e1 e9 10 10 1", print_board(&Board::default()));
+
+        for s in &["", "e1 e9 10 10", "e1 e1 10 10 1", "e1 e9 11 10 1", "e1 e9 10 10 3", "j1 e9 10 10 1",
+                   "e1 e9 10 10 1 i9h", "e1 e9 10 10 1 d4h d4v", "e1 e9 10 10 1 d4h e4h"] {
+            assert!(parse_board(s).is_err(), "{:?}", s);
+        }
+    }
+}